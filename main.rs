@@ -1,5 +1,10 @@
-use minifb::{Key, Window, WindowOptions};
-use rust_vm::io_devices::screen::Screen;
+use minifb::{Key, KeyRepeat, Window, WindowOptions};
+use rust_vm::input::{InputEvent, InputRecorder, InputScript};
+use rust_vm::io_devices::controller::Button;
+use rust_vm::io_devices::font::{self, GLYPH_HEIGHT, GLYPH_WIDTH};
+use rust_vm::io_devices::IoDevices;
+use rust_vm::{parse_number, Engine, FastVm};
+use std::path::Path;
 
 const PIXEL_SIZE: usize = 16;
 const GRID_WIDTH: usize = 32;
@@ -12,25 +17,528 @@ const HUD_HEIGHT: usize = DISPLAY_HEIGHT / 8; // 480/8 = 60
 const WINDOW_WIDTH: usize = DISPLAY_WIDTH;
 const WINDOW_HEIGHT: usize = DISPLAY_HEIGHT + HUD_HEIGHT;
 
+const HUD_MARGIN: usize = 16;
+const HUD_TEXT_SCALE: usize = 4; // 5x7 glyphs drawn at 20x28
+const HUD_TEXT_COLOR: u32 = 0xF5CBA7;
+
 const TICKS_PER_FRAME: usize = 150; // 9000 instructions per second
+const QUICKSAVE_PATH: &str = "quicksave.bpss";
+
+type CliResult = Result<(), Box<dyn std::error::Error>>;
+
 fn main() {
-    let mut vm = rust_vm::VM::new();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.first().map(String::as_str) {
+        Some("disassemble") => disassemble(&args[1..]),
+        Some("debug") => debug(&args[1..]),
+        Some("trace") => trace(&args[1..]),
+        Some("run") => run(&args[1..]),
+        Some("test") => test(&args[1..]),
+        Some("snapshot") => snapshot(&args[1..]),
+        Some("window") => window(&args[1..]),
+        _ => window(&[]),
+    };
+    if let Err(e) = result {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+}
+
+fn disassemble(args: &[String]) -> CliResult {
+    let (path, extended_alu) = match args {
+        [path] => (path, false),
+        [path, flag] if flag == "--extended-alu" => (path, true),
+        _ => return Err("usage: rust_vm disassemble <file.mc> [--extended-alu]".into()),
+    };
+    let machine_code = std::fs::read_to_string(path)?;
+    let program = rust_vm::parse_machine_code(&machine_code)?;
+    print!("{}", rust_vm::disassemble_with(&program, extended_alu));
+    Ok(())
+}
+
+// `--extended-alu` may come anywhere among the other arguments
+fn take_extended_alu(args: &[String]) -> (Vec<String>, bool) {
+    let rest: Vec<String> = args
+        .iter()
+        .filter(|arg| *arg != "--extended-alu")
+        .cloned()
+        .collect();
+    let extended_alu = rest.len() < args.len();
+    (rest, extended_alu)
+}
+
+fn debug(args: &[String]) -> CliResult {
+    use std::io::{BufRead, Write};
+
+    let (args, extended_alu) = take_extended_alu(args);
+    let [path] = args.as_slice() else {
+        return Err("usage: rust_vm debug <file.as> [--extended-alu]".into());
+    };
+    let mut debugger = rust_vm::Debugger::load_with(path, extended_alu)?;
+    println!("{}", debugger.execute("where"));
+
+    // an empty line repeats the previous command
+    let mut previous = String::new();
+    let mut lines = std::io::stdin().lock().lines();
+    loop {
+        print!("(debug) ");
+        std::io::stdout().flush()?;
+        let Some(line) = lines.next() else {
+            break;
+        };
+        let line = line?;
+        let command = match line.trim() {
+            "" => previous.clone(),
+            command => command.to_string(),
+        };
+        if matches!(command.as_str(), "quit" | "q") {
+            break;
+        }
+        println!("{}", debugger.execute(&command));
+        previous = command;
+    }
+    Ok(())
+}
+
+// Traces up to `max-steps` instructions; the output format follows the file extension
+fn trace(args: &[String]) -> CliResult {
+    let (args, extended_alu) = take_extended_alu(args);
+    let (path, output, max_steps) = match args.as_slice() {
+        [path, output] => (path, output, 100_000),
+        [path, output, max_steps] => (path, output, max_steps.parse()?),
+        _ => {
+            return Err(
+                "usage: rust_vm trace <file.as> <out.jsonl|out.bin> [max-steps] \
+[--extended-alu]"
+                    .into(),
+            )
+        }
+    };
+    let mut vm = rust_vm::VM::with_config(rust_vm::VmConfig {
+        extended_alu,
+        ..Default::default()
+    });
+    vm.load_source(&std::fs::read_to_string(path)?)?;
+    vm.add_observer(rust_vm::Tracer::new());
+    for _ in 0..max_steps {
+        if vm.clock() == rust_vm::OPCODE_HLT {
+            break;
+        }
+    }
+    let tracer = vm
+        .remove_observer::<rust_vm::Tracer>()
+        .ok_or("tracer was removed")?;
+    let file = std::io::BufWriter::new(std::fs::File::create(output)?);
+    if output.ends_with(".jsonl") || output.ends_with(".json") {
+        tracer.write_jsonl(file)?;
+    } else {
+        tracer.write_binary(file)?;
+    }
+    eprintln!("traced {} instructions to {output}", tracer.records.len());
+    Ok(())
+}
+
+// Exit codes of `run`; any other error exits with 1
+const EXIT_HALTED: i32 = 0;
+const EXIT_CYCLE_LIMIT: i32 = 2;
+
+const RUN_USAGE: &str = "usage: rust_vm run <file.as> [--max-cycles N] [--trap] \
+[--seed N] [--rng lfsr|host|V,V,...] [--banks N] [--extended-alu] [--control-rom FILE.csv] [--fast] [--reg rN=V]... [--mem ADDR=V]... [--controller [CYCLE:]BUTTONS]... [--input SCRIPT] \
+[--dump-registers] [--dump-memory] [--screen-ascii]";
+
+#[derive(Debug, Default)]
+struct RunOptions {
+    path: String,
+    max_cycles: Option<u64>,
+    trap: bool,
+    seed: Option<u64>,
+    rng: rust_vm::RngSource,
+    banks: Option<usize>,
+    extended_alu: bool,
+    control_rom: Option<String>,
+    fast: bool,
+    registers: Vec<(u8, u8)>,
+    memory: Vec<(u8, u8)>,
+    controller: Vec<InputEvent>,
+    input: Option<String>,
+    dump_registers: bool,
+    dump_memory: bool,
+    screen_ascii: bool,
+}
+
+// Runs a program without a window, for scripts and CI
+fn run(args: &[String]) -> CliResult {
+    let options = parse_run_options(args)?;
+    let script = match &options.input {
+        Some(input) => InputScript::load(input)?,
+        None => InputScript::default(),
+    };
+    // a recorded session brings its own seed
+    let mut vm = rust_vm::VmBuilder::from_config(rust_vm::VmConfig {
+        fault_policy: if options.trap {
+            rust_vm::FaultPolicy::Trap
+        } else {
+            rust_vm::FaultPolicy::Emulate
+        },
+        rng_seed: options.seed.or(script.seed),
+        rng_source: options.rng.clone(),
+        memory_banks: options.banks.unwrap_or(1),
+        extended_alu: options.extended_alu,
+        control_rom: match &options.control_rom {
+            Some(path) => rust_vm::ControlRom::load(path)?,
+            None => rust_vm::ControlRom::batpu2(),
+        },
+        ..Default::default()
+    })
+    .build()?;
+    vm.load_source(&std::fs::read_to_string(&options.path)?)?;
+    for &(register, value) in &options.registers {
+        vm.set_register(register, value);
+    }
+    for &(address, value) in &options.memory {
+        vm.data_memory.memory[address as usize] = value.into();
+    }
+
+    let mut events = options.controller.clone();
+    events.extend(script.events);
+    let mut inputs = InputScript::new(events);
+    let (result, cycles) = if options.fast {
+        let mut fast = FastVm::from_vm(&vm);
+        let outcome = run_to_end(&mut fast, &mut inputs, options.max_cycles);
+        vm = fast.to_vm();
+        outcome
+    } else {
+        run_to_end(&mut vm, &mut inputs, options.max_cycles)
+    };
+
+    if options.dump_registers {
+        println!("{}", format_registers(&vm));
+    }
+    if options.dump_memory {
+        println!("{}", format_memory(&vm));
+    }
+    if options.screen_ascii {
+        print!("{}", vm.io_devices.screen.render());
+    }
+    match result {
+        Ok(true) => {
+            eprintln!("halted after {cycles} cycles");
+            std::process::exit(EXIT_HALTED);
+        }
+        Ok(false) => {
+            eprintln!("stopped at the cycle limit of {cycles} cycles");
+            std::process::exit(EXIT_CYCLE_LIMIT);
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Whether the program halted, and the cycles it ran for
+fn run_to_end(
+    vm: &mut impl Engine,
+    inputs: &mut InputScript,
+    max_cycles: Option<u64>,
+) -> (rust_vm::Result<bool>, u64) {
+    let mut cycles = 0;
+    let result = loop {
+        if max_cycles.is_some_and(|max| cycles >= max) {
+            break Ok(false);
+        }
+        inputs.advance(cycles, &mut vm.io_devices_mut().controller);
+        cycles += 1;
+        match vm.step() {
+            Ok(opcode) if opcode == rust_vm::OPCODE_HLT => break Ok(true),
+            Ok(_) => {}
+            Err(e) => break Err(e),
+        }
+    };
+    (result, cycles)
+}
+
+fn parse_run_options(args: &[String]) -> Result<RunOptions, String> {
+    let mut options = RunOptions::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or(format!("{arg} needs a value\n{RUN_USAGE}"))
+        };
+        match arg.as_str() {
+            "--max-cycles" => options.max_cycles = Some(parse_number(value()?)?),
+            "--trap" => options.trap = true,
+            "--fast" => options.fast = true,
+            "--seed" => options.seed = Some(parse_number(value()?)?),
+            "--banks" => options.banks = Some(parse_number(value()?)?),
+            "--extended-alu" => options.extended_alu = true,
+            "--control-rom" => options.control_rom = Some(value()?.clone()),
+            "--rng" => {
+                options.rng = match value()?.as_str() {
+                    "lfsr" => rust_vm::RngSource::Lfsr,
+                    "host" => rust_vm::RngSource::Host,
+                    values => rust_vm::RngSource::Sequence(
+                        values
+                            .split(',')
+                            .map(parse_number)
+                            .collect::<Result<_, _>>()?,
+                    ),
+                }
+            }
+            "--reg" => {
+                let (register, value) = parse_assignment(value()?)?;
+                let register = register
+                    .strip_prefix('r')
+                    .and_then(|index| index.parse::<u8>().ok())
+                    .filter(|index| (1..16).contains(index))
+                    .ok_or(format!("expected a register r1 to r15, found '{register}'"))?;
+                options.registers.push((register, parse_number(value)?));
+            }
+            "--mem" => {
+                let (address, value) = parse_assignment(value()?)?;
+                options
+                    .memory
+                    .push((parse_number(address)?, parse_number(value)?));
+            }
+            "--controller" => {
+                let input = value()?;
+                let (cycle, buttons) = match input.split_once(':') {
+                    Some((cycle, buttons)) => (parse_number(cycle)?, buttons),
+                    None => (0, input.as_str()),
+                };
+                let mut held = Vec::new();
+                for name in buttons.split(',').map(str::trim) {
+                    if name.is_empty() || name == "none" {
+                        continue;
+                    }
+                    held.push(Button::from_name(name).ok_or(format!(
+                        "unknown button '{name}', expected one of {}",
+                        Button::ALL.map(Button::name).join(", ")
+                    ))?);
+                }
+                // every button that is not listed is released
+                options
+                    .controller
+                    .extend(Button::ALL.map(|button| InputEvent {
+                        cycle,
+                        button,
+                        pressed: held.contains(&button),
+                    }));
+            }
+            "--input" => options.input = Some(value()?.clone()),
+            "--dump-registers" => options.dump_registers = true,
+            "--dump-memory" => options.dump_memory = true,
+            "--screen-ascii" => options.screen_ascii = true,
+            flag if flag.starts_with("--") => {
+                return Err(format!("unknown option '{flag}'\n{RUN_USAGE}"))
+            }
+            path if options.path.is_empty() => options.path = path.to_string(),
+            _ => return Err(RUN_USAGE.to_string()),
+        }
+    }
+    if options.path.is_empty() {
+        return Err(RUN_USAGE.to_string());
+    }
+    Ok(options)
+}
+
+fn parse_assignment(assignment: &str) -> Result<(&str, &str), String> {
+    assignment
+        .split_once('=')
+        .ok_or(format!("expected NAME=VALUE, found '{assignment}'"))
+}
+
+fn format_registers(vm: &rust_vm::VM) -> String {
+    let lines: Vec<String> = vm.reg_file.register_banks[0]
+        .iter()
+        .enumerate()
+        .map(|(index, &value)| format!("r{index} = {}", u8::from(value)))
+        .collect();
+    lines.join("\n")
+}
+
+// one line of 16 bytes per row, prefixed with the row's first address
+fn format_memory(vm: &rust_vm::VM) -> String {
+    let lines: Vec<String> = vm
+        .data_memory
+        .memory
+        .chunks(16)
+        .enumerate()
+        .map(|(row, chunk)| {
+            let values: Vec<String> = chunk
+                .iter()
+                .map(|&value| format!("{:>3}", u8::from(value)))
+                .collect();
+            format!("{:>3}: {}", row * 16, values.join(" "))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+// Runs every program with `@expect` comments in the given files and directories
+fn test(args: &[String]) -> CliResult {
+    let (args, extended_alu) = take_extended_alu(args);
+    if args.is_empty() {
+        return Err("usage: rust_vm test <dir|file.as>... [--extended-alu]".into());
+    }
+    let mut programs = Vec::new();
+    for arg in &args {
+        let path = std::path::PathBuf::from(arg);
+        if path.is_dir() {
+            let mut entries: Vec<_> = std::fs::read_dir(&path)?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<_, _>>()?;
+            entries.retain(|entry| entry.extension().is_some_and(|extension| extension == "as"));
+            entries.sort();
+            programs.extend(entries);
+        } else {
+            programs.push(path);
+        }
+    }
+
+    let (mut passed, mut failed) = (0, 0);
+    for program in &programs {
+        let name = program.display();
+        match rust_vm::spec::run_file_with(program, extended_alu) {
+            Ok(None) => {}
+            Ok(Some(report)) if report.passed() => {
+                passed += 1;
+                println!("PASS {name} ({} cycles)", report.cycles);
+            }
+            Ok(Some(report)) => {
+                failed += 1;
+                println!("FAIL {name}");
+                for failure in &report.failures {
+                    println!("    {}", failure.replace('\n', "\n    "));
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAIL {name}\n    {}", e.to_string().replace('\n', "\n    "));
+            }
+        }
+    }
+    println!("{passed} passed, {failed} failed");
+    if failed > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+const SNAPSHOT_USAGE: &str = "usage: rust_vm snapshot <file.as> <out-dir> [--at CYCLE,...] \
+[--on-buffer] [--max-cycles N] [--seed N] [--pbm]";
+
+// Saves the screen at the given cycles and/or at every buffer_screen write, or once the
+// program stops when neither is asked for
+fn snapshot(args: &[String]) -> CliResult {
+    use rust_vm::snapshot::{self, ScreenCapture};
+
+    let [path, out_dir, options @ ..] = args else {
+        return Err(SNAPSHOT_USAGE.into());
+    };
+    let (mut at_cycles, mut on_buffer, mut max_cycles, mut extension) =
+        (Vec::new(), false, rust_vm::spec::DEFAULT_CYCLES, "txt");
+    let mut seed = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "--at" => {
+                let cycles = options.next().ok_or(SNAPSHOT_USAGE)?;
+                for cycle in cycles.split(',') {
+                    at_cycles.push(parse_number(cycle)?);
+                }
+            }
+            "--on-buffer" => on_buffer = true,
+            "--max-cycles" => max_cycles = parse_number(options.next().ok_or(SNAPSHOT_USAGE)?)?,
+            "--seed" => seed = Some(parse_number(options.next().ok_or(SNAPSHOT_USAGE)?)?),
+            "--pbm" => extension = "pbm",
+            _ => return Err(SNAPSHOT_USAGE.into()),
+        }
+    }
+    let at_end = at_cycles.is_empty() && !on_buffer;
 
-    // completed programs:
-    // vm.load_program("programs/dvd.as").unwrap();
-    // vm.load_programs("programs/gol.as").unwrap();
-    // vm.load_program("programs/maze.as").unwrap();
+    let mut vm = rust_vm::VM::with_config(rust_vm::VmConfig {
+        rng_seed: seed,
+        ..Default::default()
+    });
+    vm.load_source(&std::fs::read_to_string(path)?)?;
+    vm.add_observer(ScreenCapture::new(at_cycles, on_buffer));
+    let mut cycles = 0;
+    while cycles < max_cycles {
+        cycles += 1;
+        if vm.step()? == rust_vm::OPCODE_HLT {
+            break;
+        }
+    }
+    let mut frames = vm
+        .remove_observer::<ScreenCapture>()
+        .ok_or("screen capture was removed")?
+        .frames;
+    if at_end {
+        frames.push(rust_vm::snapshot::Frame {
+            cycle: cycles,
+            pixels: vm.io_devices.screen.active,
+        });
+    }
 
-    //working, but waiting for character display:
-    // vm.load_program("programs/helloworld.as").unwrap();
+    std::fs::create_dir_all(out_dir)?;
+    let stem = std::path::Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("screen");
+    for frame in &frames {
+        let file =
+            std::path::Path::new(out_dir).join(format!("{stem}_{}.{extension}", frame.cycle));
+        snapshot::save(&file, &frame.pixels)?;
+    }
+    eprintln!("saved {} snapshots to {out_dir}", frames.len());
+    Ok(())
+}
 
-    // working screen programs:
-    // vm.load_program("programs/calculator.as").unwrap();
-    // vm.load_program("programs/2048.as").unwrap();
-    // vm.load_program("programs/connect4.as").unwrap();
+const WINDOW_USAGE: &str = "usage: rust_vm window [file.as] [--seed N] [--record SCRIPT] [--fast] \
+[--ticks N] [--extended-alu]";
 
-    vm.load_program("programs/maze.as").unwrap();
+// `--record` saves the controller input and RNG seed of the session as an input script
+// for `run --input`. `--fast` runs the program on `FastVm`, which can afford far more than
+// the default `--ticks` instructions per frame.
+fn window(args: &[String]) -> CliResult {
+    let mut path = "programs/maze.as";
+    let mut record = None;
+    let mut seed = rand::random();
+    let mut fast = false;
+    let mut extended_alu = false;
+    let mut ticks_per_frame = TICKS_PER_FRAME;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--record" => record = Some(args.next().ok_or(WINDOW_USAGE)?),
+            "--seed" => seed = parse_number(args.next().ok_or(WINDOW_USAGE)?)?,
+            "--fast" => fast = true,
+            "--extended-alu" => extended_alu = true,
+            "--ticks" => ticks_per_frame = parse_number(args.next().ok_or(WINDOW_USAGE)?)?,
+            flag if flag.starts_with("--") => return Err(WINDOW_USAGE.into()),
+            file => path = file,
+        }
+    }
+    let mut vm = rust_vm::VM::with_config(rust_vm::VmConfig {
+        rng_seed: Some(seed),
+        extended_alu,
+        ..Default::default()
+    });
+    vm.load_program(path)?;
+    let recorder = if fast {
+        run_window(FastVm::from_vm(&vm), ticks_per_frame)
+    } else {
+        run_window(vm, ticks_per_frame)
+    };
+    if let Some(record) = record {
+        let mut script = recorder.finish();
+        script.seed = Some(seed);
+        script.save(record)?;
+        eprintln!("recorded controller input to {record}");
+    }
+    Ok(())
+}
 
+fn run_window(mut vm: impl Engine, ticks_per_frame: usize) -> InputRecorder {
     let mut buffer: Vec<u32> = vec![0; WINDOW_WIDTH * WINDOW_HEIGHT];
     let width = WINDOW_WIDTH;
     let height = WINDOW_HEIGHT;
@@ -48,10 +556,13 @@ fn main() {
     window.set_target_fps(60);
 
     let mut is_halted = false;
+    let mut cycles = 0;
+    let mut recorder = InputRecorder::new();
     // Main loop
     while window.is_open() && !window.is_key_down(Key::Escape) {
         if !is_halted {
-            for _ in 0..TICKS_PER_FRAME {
+            for _ in 0..ticks_per_frame {
+                cycles += 1;
                 if vm.clock() == rust_vm::OPCODE_HLT {
                     is_halted = true;
                     break;
@@ -60,18 +571,18 @@ fn main() {
         }
 
         handle_controller_input(&mut vm, &window);
+        recorder.record(cycles, &vm.io_devices().controller);
+        handle_quicksave(&mut vm, &window, &mut is_halted);
 
-        screen_to_buffer_with_hud(vm.io_devices.screen, &mut buffer[..]);
+        screen_to_buffer_with_hud(vm.io_devices(), &mut buffer[..]);
 
         // Update the window with the buffer
         window.update_with_buffer(&buffer, width, height).unwrap();
-
-        vm.io_devices.character_display.display();
-        vm.io_devices.number_display.display();
     }
+    recorder
 }
 
-fn screen_to_buffer_with_hud(screen: Screen, buffer: &mut [u32]) {
+fn screen_to_buffer_with_hud(devices: &IoDevices, buffer: &mut [u32]) {
     // Fill the HUD area (top HUD_HEIGHT rows) with a background color (e.g., dark gray)
     for y in 0..HUD_HEIGHT {
         for x in 0..WINDOW_WIDTH {
@@ -79,7 +590,25 @@ fn screen_to_buffer_with_hud(screen: Screen, buffer: &mut [u32]) {
         }
     }
 
+    // The character display on the left of the HUD, the number display on the right
+    let text_y = (HUD_HEIGHT - GLYPH_HEIGHT * HUD_TEXT_SCALE) / 2;
+    draw_text(
+        buffer,
+        &devices.character_display.active,
+        HUD_MARGIN,
+        text_y,
+    );
+    let number = devices.number_display.get_display_val();
+    let number_width = number.len() * (GLYPH_WIDTH + 1) * HUD_TEXT_SCALE;
+    draw_text(
+        buffer,
+        &number,
+        WINDOW_WIDTH.saturating_sub(HUD_MARGIN + number_width),
+        text_y,
+    );
+
     // Draw the screen below the HUD
+    let screen = &devices.screen;
     let screen_height = screen.active.len();
     for (i, row) in screen.active.iter().enumerate() {
         let flipped_i = screen_height - 1 - i;
@@ -98,17 +627,39 @@ fn screen_to_buffer_with_hud(screen: Screen, buffer: &mut [u32]) {
     }
 }
 
-fn handle_controller_input(vm: &mut rust_vm::VM, window: &Window) {
-    vm.io_devices
-        .controller
-        .set_up(window.is_key_down(Key::Up) || window.is_key_down(Key::W));
-    vm.io_devices
-        .controller
-        .set_down(window.is_key_down(Key::Down) || window.is_key_down(Key::S));
-    vm.io_devices
-        .controller
-        .set_left(window.is_key_down(Key::Left) || window.is_key_down(Key::A));
-    vm.io_devices
-        .controller
-        .set_right(window.is_key_down(Key::Right) || window.is_key_down(Key::D));
+fn draw_text(buffer: &mut [u32], text: &str, left: usize, top: usize) {
+    for (gx, gy) in font::pixels(text) {
+        for dy in 0..HUD_TEXT_SCALE {
+            for dx in 0..HUD_TEXT_SCALE {
+                let x = left + gx * HUD_TEXT_SCALE + dx;
+                let y = top + gy * HUD_TEXT_SCALE + dy;
+                if x < WINDOW_WIDTH && y < HUD_HEIGHT {
+                    buffer[y * WINDOW_WIDTH + x] = HUD_TEXT_COLOR;
+                }
+            }
+        }
+    }
+}
+
+// F5 saves the whole machine, F9 loads it back
+fn handle_quicksave(vm: &mut impl Engine, window: &Window, is_halted: &mut bool) {
+    if window.is_key_pressed(Key::F5, KeyRepeat::No) {
+        if let Err(e) = vm.save_state(Path::new(QUICKSAVE_PATH)) {
+            eprintln!("{e}");
+        }
+    }
+    if window.is_key_pressed(Key::F9, KeyRepeat::No) {
+        match vm.load_state(Path::new(QUICKSAVE_PATH)) {
+            Ok(()) => *is_halted = false,
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+fn handle_controller_input(vm: &mut impl Engine, window: &Window) {
+    let controller = &mut vm.io_devices_mut().controller;
+    controller.set_up(window.is_key_down(Key::Up) || window.is_key_down(Key::W));
+    controller.set_down(window.is_key_down(Key::Down) || window.is_key_down(Key::S));
+    controller.set_left(window.is_key_down(Key::Left) || window.is_key_down(Key::A));
+    controller.set_right(window.is_key_down(Key::Right) || window.is_key_down(Key::D));
 }
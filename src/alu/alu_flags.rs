@@ -1,9 +1,9 @@
 use crate::Condition;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) struct AluFlags {
-    pub(crate) zero: bool,
-    pub(crate) carry: bool,
+pub struct AluFlags {
+    pub zero: bool,
+    pub carry: bool,
 }
 
 impl AluFlags {
@@ -9,7 +9,7 @@ pub(crate) struct AluConfig {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Default, Eq)]
-pub(crate) enum AluSettings {
+pub enum AluSettings {
     #[default]
     Add,
     Sub,
@@ -3,5 +3,6 @@ use super::*;
 mod utils;
 mod flood_carry;
 mod invert_and_carry_in;
+mod reference;
 mod rshift;
 mod xor_to_or;
@@ -0,0 +1,99 @@
+use super::*;
+
+const SETTINGS: [AluSettings; 11] = [
+    AluSettings::Add,
+    AluSettings::Sub,
+    AluSettings::Xor,
+    AluSettings::Xnor,
+    AluSettings::Or,
+    AluSettings::Nor,
+    AluSettings::And,
+    AluSettings::Nand,
+    AluSettings::Implies,
+    AluSettings::Nimplies,
+    AluSettings::Rshift,
+];
+
+// What the gates should produce, as plain integers. The logic settings have no carry
+// chain: the ones that flood it report a carry, the others never do. A right shift leaves
+// both flags alone.
+fn reference(setting: AluSettings, a: u8, b: u8) -> (u8, Option<bool>) {
+    let (a16, b16) = (u16::from(a), u16::from(b));
+    let (result, carry) = match setting {
+        AluSettings::Add => ((a16 + b16) as u8, a16 + b16 > 0xFF),
+        AluSettings::Sub => (
+            (a16 + (!b16 & 0xFF) + 1) as u8,
+            a16 + (!b16 & 0xFF) + 1 > 0xFF,
+        ),
+        AluSettings::Xor => (a ^ b, true),
+        AluSettings::Xnor => (!(a ^ b), true),
+        AluSettings::Or => (a | b, false),
+        AluSettings::Nor => (!(a | b), true),
+        AluSettings::And => (a & b, true),
+        AluSettings::Nand => (!(a & b), false),
+        AluSettings::Implies => (!a | b, false),
+        AluSettings::Nimplies => (a & !b, true),
+        AluSettings::Rshift => return (a / 2, None),
+    };
+    (result, Some(carry))
+}
+
+#[test]
+fn every_setting_matches_the_reference_on_every_input() {
+    for setting in SETTINGS {
+        let mut alu = Alu::new(setting);
+        alu.set_flags = true;
+        for a in 0..=u8::MAX {
+            for b in 0..=u8::MAX {
+                // start from flags the result cannot have left behind by accident
+                let (expected, carry) = reference(setting, a, b);
+                let before = AluFlags {
+                    zero: expected != 0,
+                    carry: !carry.unwrap_or(a % 2 == 0),
+                };
+                alu.flags = before;
+                let result = u8::from(alu.compute(Bits::from(a), Bits::from(b)));
+                let flags = match carry {
+                    Some(carry) => AluFlags {
+                        zero: expected == 0,
+                        carry,
+                    },
+                    None => before,
+                };
+                assert_eq!(
+                    (result, alu.flags),
+                    (expected, flags),
+                    "{setting:?} {a} {b}"
+                );
+                assert_eq!(
+                    crate::vm::fast::alu(setting, a, b).0,
+                    expected,
+                    "fast core {setting:?} {a} {b}"
+                );
+                if let Some(carry) = carry {
+                    assert_eq!(crate::vm::fast::alu(setting, a, b).1, carry);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn flags_only_change_when_enabled() {
+    for setting in SETTINGS {
+        let mut alu = Alu::new(setting);
+        for flags in [
+            AluFlags::default(),
+            AluFlags {
+                zero: true,
+                carry: true,
+            },
+        ] {
+            alu.flags = flags;
+            for (a, b) in [(0u8, 0u8), (255, 1), (3, 200), (128, 128)] {
+                alu.compute(Bits::<8>::from(a), Bits::from(b));
+                assert_eq!(alu.flags, flags, "{setting:?} {a} {b}");
+            }
+        }
+    }
+}
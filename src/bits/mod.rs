@@ -100,7 +100,7 @@ impl<const N: usize> Bits<N> {
     }
 
     pub(crate) fn split_into_chunks<const CHUNK_SIZE: usize>(self) -> Vec<Bits<CHUNK_SIZE>> {
-        assert!(N % CHUNK_SIZE == 0, "Size must divide N evenly");
+        assert!(N.is_multiple_of(CHUNK_SIZE), "Size must divide N evenly");
         let mut chunks = Vec::new();
         for chunk in self.bit_array.chunks(CHUNK_SIZE) {
             let mut bits = [false; CHUNK_SIZE];
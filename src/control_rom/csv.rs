@@ -0,0 +1,244 @@
+// Control ROM tables as CSV, one row per opcode:
+//
+//     Mnemonic,Opcode,Set Flags?,ALU,Register Write,Data,Destination,ALU B,Next PC,Branch,Call Stack,Immediate,Memory,Read B
+//     ADD,0010,yes,add,yes,alu,c,register,increment,no,none,immediate,none,b
+//
+// The first three columns mirror `BatPU-2 ISA.xlsx`; the others name the control signals.
+// Columns can come in any order and may be left out, like cells may be left empty, to
+// get the signal a NOP has. Opcodes without a row are NOPs, apart from 0001, which always
+// halts. Lines starting with '#' are comments.
+use super::{
+    AddrMux, AluMux, AluSettings, CallStackState, ControlRom, ControlSignals, DataMux, DestMux,
+    ImmediateMux, MemoryAccess, ReadMux, OPCODES,
+};
+use crate::error::VmError;
+use crate::Result;
+use std::fmt::Write as _;
+
+const ALU: [(&str, AluSettings); 11] = [
+    ("add", AluSettings::Add),
+    ("sub", AluSettings::Sub),
+    ("xor", AluSettings::Xor),
+    ("xnor", AluSettings::Xnor),
+    ("or", AluSettings::Or),
+    ("nor", AluSettings::Nor),
+    ("and", AluSettings::And),
+    ("nand", AluSettings::Nand),
+    ("imp", AluSettings::Implies),
+    ("nimp", AluSettings::Nimplies),
+    ("rsh", AluSettings::Rshift),
+];
+const YES_NO: [(&str, bool); 2] = [("yes", true), ("no", false)];
+const DATA: [(&str, DataMux); 3] = [
+    ("alu", DataMux::Alu),
+    ("immediate", DataMux::Immediate),
+    ("memory", DataMux::Memory),
+];
+// the operand fields, A in bits 8-11, B in 4-7 and C in 0-3
+const DESTINATION: [(&str, DestMux); 3] = [
+    ("a", DestMux::First),
+    ("b", DestMux::Second),
+    ("c", DestMux::Third),
+];
+const ALU_B: [(&str, AluMux); 2] = [
+    ("register", AluMux::R2),
+    ("immediate", AluMux::BypassRegisterFile),
+];
+const NEXT_PC: [(&str, AddrMux); 3] = [
+    ("increment", AddrMux::Increment),
+    ("jump", AddrMux::Jump),
+    ("return", AddrMux::Return),
+];
+const CALL_STACK: [(&str, CallStackState); 3] = [
+    ("none", CallStackState::Disabled),
+    ("push", CallStackState::Push),
+    ("pop", CallStackState::Pop),
+];
+const IMMEDIATE: [(&str, ImmediateMux); 2] = [
+    ("immediate", ImmediateMux::Immediate),
+    ("offset", ImmediateMux::Offset),
+];
+const MEMORY: [(&str, MemoryAccess); 3] = [
+    ("none", MemoryAccess::Disabled),
+    ("read", MemoryAccess::Read),
+    ("write", MemoryAccess::Write),
+];
+const READ_B: [(&str, ReadMux); 2] = [("b", ReadMux::Second), ("c", ReadMux::Third)];
+
+const COLUMNS: [&str; 14] = [
+    "Mnemonic",
+    "Opcode",
+    "Set Flags?",
+    "ALU",
+    "Register Write",
+    "Data",
+    "Destination",
+    "ALU B",
+    "Next PC",
+    "Branch",
+    "Call Stack",
+    "Immediate",
+    "Memory",
+    "Read B",
+];
+
+fn name<T: PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names
+        .iter()
+        .find(|(_, v)| *v == value)
+        .map_or("", |(name, _)| name)
+}
+
+fn parse<T: Copy>(names: &[(&str, T)], column: &str, cell: &str) -> Result<T> {
+    names
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(cell))
+        .map(|&(_, value)| value)
+        .ok_or_else(|| {
+            let expected: Vec<&str> = names.iter().map(|(name, _)| *name).collect();
+            VmError::ControlRom(format!(
+                "unknown {column} '{cell}', expected one of {}",
+                expected.join(", ")
+            ))
+        })
+}
+
+impl ControlRom {
+    pub fn from_csv(text: &str) -> Result<ControlRom> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        let Some((_, header)) = lines.next() else {
+            return Err(VmError::ControlRom("missing the header row".to_string()));
+        };
+        let mut columns = Vec::new();
+        for cell in header.split(',').map(str::trim) {
+            let column = COLUMNS
+                .iter()
+                .position(|column| column.eq_ignore_ascii_case(cell))
+                .ok_or_else(|| VmError::ControlRom(format!("unknown column '{cell}'")))?;
+            if columns.contains(&column) {
+                return Err(VmError::ControlRom(format!(
+                    "column '{cell}' appears twice"
+                )));
+            }
+            columns.push(column);
+        }
+        if !columns.contains(&1) {
+            return Err(VmError::ControlRom("missing the Opcode column".to_string()));
+        }
+
+        let mut rom = ControlRom::empty();
+        let mut seen = [false; OPCODES];
+        for (number, line) in lines {
+            let context = |error: VmError| match error {
+                VmError::ControlRom(reason) => {
+                    VmError::ControlRom(format!("line {number}: {reason}"))
+                }
+                error => error,
+            };
+            let cells: Vec<&str> = line.split(',').map(str::trim).collect();
+            if cells.len() != columns.len() {
+                return Err(context(VmError::ControlRom(format!(
+                    "expected {} cells, found {}",
+                    columns.len(),
+                    cells.len()
+                ))));
+            }
+            let mut mnemonic = "";
+            let mut opcode = None;
+            let mut signals = ControlSignals::default();
+            for (&column, &cell) in columns.iter().zip(&cells) {
+                if cell.is_empty() {
+                    continue;
+                }
+                let column_name = COLUMNS[column];
+                match column {
+                    0 => mnemonic = cell,
+                    1 => opcode = Some(parse_opcode(cell).map_err(context)?),
+                    2 => signals.set_flags = parse(&YES_NO, column_name, cell).map_err(context)?,
+                    3 => signals.alu_settings = parse(&ALU, column_name, cell).map_err(context)?,
+                    4 => {
+                        signals.reg_file_enable =
+                            parse(&YES_NO, column_name, cell).map_err(context)?
+                    }
+                    5 => signals.data_mux = parse(&DATA, column_name, cell).map_err(context)?,
+                    6 => {
+                        signals.dest_mux =
+                            parse(&DESTINATION, column_name, cell).map_err(context)?
+                    }
+                    7 => signals.alu_mux = parse(&ALU_B, column_name, cell).map_err(context)?,
+                    8 => signals.addr_mux = parse(&NEXT_PC, column_name, cell).map_err(context)?,
+                    9 => signals.is_branch = parse(&YES_NO, column_name, cell).map_err(context)?,
+                    10 => {
+                        signals.call_stack_state =
+                            parse(&CALL_STACK, column_name, cell).map_err(context)?
+                    }
+                    11 => {
+                        signals.immediate_mux =
+                            parse(&IMMEDIATE, column_name, cell).map_err(context)?
+                    }
+                    12 => {
+                        signals.memory_access =
+                            parse(&MEMORY, column_name, cell).map_err(context)?
+                    }
+                    _ => signals.read_mux = parse(&READ_B, column_name, cell).map_err(context)?,
+                }
+            }
+            let Some(opcode) = opcode else {
+                return Err(context(VmError::ControlRom(
+                    "missing the opcode".to_string(),
+                )));
+            };
+            if std::mem::replace(&mut seen[opcode as usize], true) {
+                return Err(context(VmError::ControlRom(format!(
+                    "opcode {opcode:04b} is defined twice"
+                ))));
+            }
+            rom = rom.with_instruction(opcode, mnemonic, signals);
+        }
+        Ok(rom)
+    }
+
+    pub fn load(file_path: impl AsRef<std::path::Path>) -> Result<ControlRom> {
+        ControlRom::from_csv(&std::fs::read_to_string(file_path)?)
+    }
+
+    // Every column, one row per opcode
+    pub fn to_csv(&self) -> String {
+        let mut out = COLUMNS.join(",");
+        out.push('\n');
+        for (opcode, signals) in self.signals.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "{},{opcode:04b},{},{},{},{},{},{},{},{},{},{},{},{}",
+                self.mnemonics[opcode],
+                name(&YES_NO, signals.set_flags),
+                name(&ALU, signals.alu_settings),
+                name(&YES_NO, signals.reg_file_enable),
+                name(&DATA, signals.data_mux),
+                name(&DESTINATION, signals.dest_mux),
+                name(&ALU_B, signals.alu_mux),
+                name(&NEXT_PC, signals.addr_mux),
+                name(&YES_NO, signals.is_branch),
+                name(&CALL_STACK, signals.call_stack_state),
+                name(&IMMEDIATE, signals.immediate_mux),
+                name(&MEMORY, signals.memory_access),
+                name(&READ_B, signals.read_mux),
+            );
+        }
+        out
+    }
+}
+
+// Four binary digits, as written in the ISA sheet
+fn parse_opcode(cell: &str) -> Result<u8> {
+    if cell.len() != 4 || !cell.chars().all(|c| c == '0' || c == '1') {
+        return Err(VmError::ControlRom(format!(
+            "opcode '{cell}' is not 4 binary digits"
+        )));
+    }
+    Ok(u8::from_str_radix(cell, 2)?)
+}
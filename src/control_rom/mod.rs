@@ -1,10 +1,28 @@
-use crate::{alu::AluSettings, bits::Bits};
+// The decoder: a table from each of the 16 opcodes to the control signals that drive the
+// datapath. `ControlRom::batpu2()` is the original machine. Other tables can be built in
+// Rust with `with_instruction`, or loaded from a CSV file, see `csv.rs`. Halting is not a
+// signal: opcode 0001 stops the machine in every table, once its row's signals have run.
+pub use crate::alu::alu_settings::AluSettings;
+use crate::bits::Bits;
+use crate::ProgramInstruction;
 
-#[derive(Debug, Default)]
-pub(crate) struct ControlRom;
+mod csv;
+
+pub const OPCODES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControlRom {
+    signals: [ControlSignals; OPCODES],
+    mnemonics: [String; OPCODES], // only for reading and writing tables
+}
+
+// 0111, the opcode whose B field selects the function of the extended ALU
+pub(crate) const OPCODE_RSH: Bits<4> = Bits {
+    bit_array: [true, true, true, false],
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum AddrMux {
+pub enum AddrMux {
     #[default]
     Increment,
     Jump,
@@ -12,7 +30,7 @@ pub(crate) enum AddrMux {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum DataMux {
+pub enum DataMux {
     #[default]
     Alu,
     Immediate,
@@ -20,7 +38,7 @@ pub(crate) enum DataMux {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum DestMux {
+pub enum DestMux {
     First,
     Second,
     #[default]
@@ -28,7 +46,7 @@ pub(crate) enum DestMux {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum MemoryAccess {
+pub enum MemoryAccess {
     #[default]
     Disabled,
     Read,
@@ -36,14 +54,14 @@ pub(crate) enum MemoryAccess {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum AluMux {
+pub enum AluMux {
     #[default]
     R2,
     BypassRegisterFile,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum CallStackState {
+pub enum CallStackState {
     #[default]
     Disabled,
     Push,
@@ -51,144 +69,236 @@ pub(crate) enum CallStackState {
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) enum ImmediateMux {
+pub enum ImmediateMux {
     #[default]
     Immediate,
     Offset,
 }
 
+// Which field addresses the second read port of the register file
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub(crate) struct ControlSignals {
-    pub(crate) alu_settings: AluSettings,
-    pub(crate) reg_file_enable: bool,
-    pub(crate) data_mux: DataMux,
-    pub(crate) dest_mux: DestMux,
-    pub(crate) alu_mux: AluMux,
-    pub(crate) addr_mux: AddrMux,
-    pub(crate) is_branch: bool,
-    pub(crate) set_flags: bool,
-    pub(crate) call_stack_state: CallStackState,
-    pub(crate) immediate_mux: ImmediateMux,
-    pub(crate) memory_access: MemoryAccess,
+pub enum ReadMux {
+    #[default]
+    Second,
+    Third,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ControlSignals {
+    pub alu_settings: AluSettings,
+    pub reg_file_enable: bool,
+    pub data_mux: DataMux,
+    pub dest_mux: DestMux,
+    pub alu_mux: AluMux,
+    pub addr_mux: AddrMux,
+    pub is_branch: bool,
+    pub set_flags: bool,
+    pub call_stack_state: CallStackState,
+    pub immediate_mux: ImmediateMux,
+    pub memory_access: MemoryAccess,
+    pub read_mux: ReadMux,
+}
+
+const BATPU2_MNEMONICS: [&str; OPCODES] = [
+    "NOP", "HLT", "ADD", "SUB", "NOR", "AND", "XOR", "RSH", "LDI", "ADI", "JMP", "BRH", "CAL",
+    "RET", "LOD", "STR",
+];
+
+// The BatPU-2 decode table
+fn batpu2_signals(opcode: usize) -> ControlSignals {
+    match opcode {
+        // NOP
+        0b0000 => ControlSignals {
+            ..Default::default()
+        },
+        // HLT
+        0b0001 => ControlSignals {
+            ..Default::default()
+        },
+        // ADD
+        0b0010 => ControlSignals {
+            alu_settings: AluSettings::Add,
+            reg_file_enable: true,
+            set_flags: true,
+            ..Default::default()
+        },
+        // SUB
+        0b0011 => ControlSignals {
+            alu_settings: AluSettings::Sub,
+            reg_file_enable: true,
+            set_flags: true,
+            ..Default::default()
+        },
+        // NOR
+        0b0100 => ControlSignals {
+            alu_settings: AluSettings::Nor,
+            reg_file_enable: true,
+            set_flags: true,
+            ..Default::default()
+        },
+        // AND
+        0b0101 => ControlSignals {
+            alu_settings: AluSettings::And,
+            reg_file_enable: true,
+            set_flags: true,
+            ..Default::default()
+        },
+        // XOR
+        0b0110 => ControlSignals {
+            alu_settings: AluSettings::Xor,
+            reg_file_enable: true,
+            set_flags: true,
+            ..Default::default()
+        },
+        // RSH
+        0b0111 => ControlSignals {
+            alu_settings: AluSettings::Rshift,
+            reg_file_enable: true,
+            set_flags: false, // as per original design, RSH should not set flags
+            ..Default::default()
+        },
+        // LDI
+        0b1000 => ControlSignals {
+            reg_file_enable: true,
+            data_mux: DataMux::Immediate,
+            dest_mux: DestMux::First,
+            immediate_mux: ImmediateMux::Immediate,
+            ..Default::default()
+        },
+        // ADI
+        0b1001 => ControlSignals {
+            alu_settings: AluSettings::Add,
+            reg_file_enable: true,
+            dest_mux: DestMux::First,
+            alu_mux: AluMux::BypassRegisterFile,
+            immediate_mux: ImmediateMux::Immediate,
+            data_mux: DataMux::Alu,
+            set_flags: true,
+            ..Default::default()
+        },
+        // JMP
+        0b1010 => ControlSignals {
+            addr_mux: AddrMux::Jump,
+            ..Default::default()
+        },
+        // BRH
+        0b1011 => ControlSignals {
+            is_branch: true,
+            ..Default::default()
+        },
+        // CAL
+        0b1100 => ControlSignals {
+            call_stack_state: CallStackState::Push,
+            addr_mux: AddrMux::Jump,
+            ..Default::default()
+        },
+        // RET
+        0b1101 => ControlSignals {
+            addr_mux: AddrMux::Return,
+            call_stack_state: CallStackState::Pop,
+            ..Default::default()
+        },
+        // LOD, TODO: implement
+        0b1110 => ControlSignals {
+            immediate_mux: ImmediateMux::Offset,
+            alu_settings: AluSettings::Add,
+            reg_file_enable: true,
+            data_mux: DataMux::Memory,
+            dest_mux: DestMux::Second,
+            alu_mux: AluMux::BypassRegisterFile,
+            memory_access: MemoryAccess::Read,
+            ..Default::default()
+        },
+        // STR, every other opcode is matched above
+        _ => ControlSignals {
+            alu_settings: AluSettings::Add,
+            reg_file_enable: true,
+            alu_mux: AluMux::BypassRegisterFile,
+            immediate_mux: ImmediateMux::Offset,
+            memory_access: MemoryAccess::Write,
+            ..Default::default()
+        },
+    }
+}
+
+impl Default for ControlRom {
+    fn default() -> Self {
+        ControlRom::batpu2()
+    }
 }
 
 impl ControlRom {
-    pub(crate) fn get_control_signals(&self, opcode: Bits<4>) -> ControlSignals {
-        match opcode.to_string().as_str() {
-            // NOP
-            "0000" => ControlSignals {
-                ..Default::default()
-            },
-            // HLT
-            "0001" => ControlSignals {
-                ..Default::default()
-            },
-            // ADD
-            "0010" => ControlSignals {
-                alu_settings: AluSettings::Add,
-                reg_file_enable: true,
-                set_flags: true,
-                ..Default::default()
-            },
-            // SUB
-            "0011" => ControlSignals {
-                alu_settings: AluSettings::Sub,
-                reg_file_enable: true,
-                set_flags: true,
-                ..Default::default()
-            },
-            // NOR
-            "0100" => ControlSignals {
-                alu_settings: AluSettings::Nor,
-                reg_file_enable: true,
-                set_flags: true,
-                ..Default::default()
-            },
-            // AND
-            "0101" => ControlSignals {
-                alu_settings: AluSettings::And,
-                reg_file_enable: true,
-                set_flags: true,
-                ..Default::default()
-            },
-            // XOR
-            "0110" => ControlSignals {
-                alu_settings: AluSettings::Xor,
-                reg_file_enable: true,
-                set_flags: true,
-                ..Default::default()
-            },
-            // RSH
-            "0111" => ControlSignals {
-                alu_settings: AluSettings::Rshift,
-                reg_file_enable: true,
-                set_flags: false, // as per original design, RSH should not set flags
-                ..Default::default()
-            },
-            // LDI
-            "1000" => ControlSignals {
-                reg_file_enable: true,
-                data_mux: DataMux::Immediate,
-                dest_mux: DestMux::First,
-                immediate_mux: ImmediateMux::Immediate,
-                ..Default::default()
-            },
-            // ADI
-            "1001" => ControlSignals {
-                alu_settings: AluSettings::Add,
-                reg_file_enable: true,
-                dest_mux: DestMux::First,
-                alu_mux: AluMux::BypassRegisterFile,
-                immediate_mux: ImmediateMux::Immediate,
-                data_mux: DataMux::Alu,
-                set_flags: true,
-                ..Default::default()
-            },
-            // JMP
-            "1010" => ControlSignals {
-                addr_mux: AddrMux::Jump,
-                ..Default::default()
-            },
-            // BRH
-            "1011" => ControlSignals {
-                is_branch: true,
-                ..Default::default()
-            },
-            // CAL
-            "1100" => ControlSignals {
-                call_stack_state: CallStackState::Push,
-                addr_mux: AddrMux::Jump,
-                ..Default::default()
-            },
-            // RET
-            "1101" => ControlSignals {
-                addr_mux: AddrMux::Return,
-                call_stack_state: CallStackState::Pop,
-                ..Default::default()
-            },
-            // LOD, TODO: implement
-            "1110" => ControlSignals {
-                immediate_mux: ImmediateMux::Offset,
-                alu_settings: AluSettings::Add,
-                reg_file_enable: true,
-                data_mux: DataMux::Memory,
-                dest_mux: DestMux::Second,
-                alu_mux: AluMux::BypassRegisterFile,
-                memory_access: MemoryAccess::Read,
-                ..Default::default()
-            },
-            // STR, TODO: implement
-            "1111" => ControlSignals {
-                alu_settings: AluSettings::Add,
-                reg_file_enable: true,
-                alu_mux: AluMux::BypassRegisterFile,
-                immediate_mux: ImmediateMux::Offset,
-                memory_access: MemoryAccess::Write,
-                ..Default::default()
-            },
-            _ => unreachable!(),
+    pub fn batpu2() -> Self {
+        ControlRom {
+            signals: std::array::from_fn(batpu2_signals),
+            mnemonics: BATPU2_MNEMONICS.map(String::from),
         }
     }
+
+    // Every opcode a NOP, to fill in with `with_instruction`
+    pub fn empty() -> Self {
+        ControlRom {
+            signals: [ControlSignals::default(); OPCODES],
+            mnemonics: Default::default(),
+        }
+    }
+
+    // Replaces the row of `opcode`, which must be below 16
+    pub fn with_instruction(mut self, opcode: u8, mnemonic: &str, signals: ControlSignals) -> Self {
+        assert!(
+            (opcode as usize) < OPCODES,
+            "opcode {opcode} does not fit in 4 bits"
+        );
+        self.signals[opcode as usize] = signals;
+        self.mnemonics[opcode as usize] = mnemonic.to_string();
+        self
+    }
+
+    pub fn signals(&self, opcode: u8) -> ControlSignals {
+        self.signals[opcode as usize % OPCODES]
+    }
+
+    pub fn mnemonic(&self, opcode: u8) -> &str {
+        &self.mnemonics[opcode as usize % OPCODES]
+    }
+
+    pub(crate) fn get_control_signals(&self, opcode: Bits<4>) -> ControlSignals {
+        self.signals[opcode.to_usize()]
+    }
+
+    // The signals for a whole word, with the function select of the extended ALU applied
+    pub(crate) fn decode(
+        &self,
+        instruction: ProgramInstruction,
+        extended_alu: bool,
+    ) -> ControlSignals {
+        let opcode = instruction.slice(12);
+        let function_signals = (extended_alu && opcode == OPCODE_RSH)
+            .then(|| self.get_function_signals(instruction.slice(4)))
+            .flatten();
+        function_signals.unwrap_or_else(|| self.get_control_signals(opcode))
+    }
+
+    // The extended ALU decodes the B field of an RSH as a function select. Function 0 is
+    // the shift itself; the others read their second operand from, and write it back to,
+    // the C register.
+    pub(crate) fn get_function_signals(&self, function: Bits<4>) -> Option<ControlSignals> {
+        let alu_settings = match function.to_usize() {
+            1 => AluSettings::Or,
+            2 => AluSettings::Xnor,
+            3 => AluSettings::Nand,
+            4 => AluSettings::Implies,
+            5 => AluSettings::Nimplies,
+            _ => return None,
+        };
+        Some(ControlSignals {
+            alu_settings,
+            reg_file_enable: true,
+            set_flags: true,
+            read_mux: ReadMux::Third,
+            ..Default::default()
+        })
+    }
 }
 
 #[cfg(test)]
@@ -2,7 +2,7 @@
 use super::*;
 #[test]
 fn add() {
-    let set = ControlRom.get_control_signals(Bits::from(2u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(2u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -16,7 +16,7 @@ fn add() {
 
 #[test]
 fn sub() {
-    let set = ControlRom.get_control_signals(Bits::from(3u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(3u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -30,7 +30,7 @@ fn sub() {
 
 #[test]
 fn nor() {
-    let set = ControlRom.get_control_signals(Bits::from(4u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(4u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -44,7 +44,7 @@ fn nor() {
 
 #[test]
 fn and() {
-    let set = ControlRom.get_control_signals(Bits::from(5u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(5u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -58,7 +58,7 @@ fn and() {
 
 #[test]
 fn xor() {
-    let set = ControlRom.get_control_signals(Bits::from(6u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(6u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -72,7 +72,7 @@ fn xor() {
 
 #[test]
 fn rshift() {
-    let set = ControlRom.get_control_signals(Bits::from(7u8).resize());
+    let set = ControlRom::batpu2().get_control_signals(Bits::from(7u8).resize());
     assert_eq!(
         set,
         ControlSignals {
@@ -83,3 +83,106 @@ fn rshift() {
         }
     );
 }
+
+#[test]
+fn extended_alu_functions() {
+    let functions = [
+        (1, AluSettings::Or),
+        (2, AluSettings::Xnor),
+        (3, AluSettings::Nand),
+        (4, AluSettings::Implies),
+        (5, AluSettings::Nimplies),
+    ];
+    for (function, alu_settings) in functions {
+        let set = ControlRom::batpu2().get_function_signals(Bits::from(function as u8).resize());
+        assert_eq!(
+            set,
+            Some(ControlSignals {
+                alu_settings,
+                reg_file_enable: true,
+                set_flags: true,
+                read_mux: ReadMux::Third,
+                ..Default::default()
+            })
+        );
+    }
+    // function 0 is the shift itself, the rest are unused
+    for function in [0u8, 6, 15] {
+        assert_eq!(
+            ControlRom::batpu2().get_function_signals(Bits::from(function).resize()),
+            None
+        );
+    }
+}
+
+#[test]
+fn batpu2_table_file() {
+    let text = std::fs::read_to_string("control_roms/batpu2.csv").unwrap();
+    assert_eq!(ControlRom::from_csv(&text).unwrap(), ControlRom::batpu2());
+    assert_eq!(
+        ControlRom::from_csv(&ControlRom::batpu2().to_csv()).unwrap(),
+        ControlRom::batpu2()
+    );
+    assert_eq!(ControlRom::batpu2().mnemonic(0b1110), "LOD");
+}
+
+#[test]
+fn csv_columns_can_be_left_out() {
+    let rom = ControlRom::from_csv(
+        "\
+# only what differs from a NOP
+opcode , ALU, register write,set flags?
+0100,OR,Yes,yes
+0001,,,",
+    )
+    .unwrap();
+    let or = ControlSignals {
+        alu_settings: AluSettings::Or,
+        reg_file_enable: true,
+        set_flags: true,
+        ..Default::default()
+    };
+    assert_eq!(rom, ControlRom::empty().with_instruction(0b0100, "", or));
+    assert_eq!(rom.signals(0b0010), ControlSignals::default());
+}
+
+#[test]
+fn csv_errors() {
+    let error = |text: &str| ControlRom::from_csv(text).unwrap_err().to_string();
+    assert_eq!(error(""), "Invalid control ROM: missing the header row");
+    assert_eq!(
+        error("Opcode,Colour"),
+        "Invalid control ROM: unknown column 'Colour'"
+    );
+    assert_eq!(
+        error("ALU\nadd"),
+        "Invalid control ROM: missing the Opcode column"
+    );
+    assert_eq!(
+        error("Opcode,ALU\n0010,add\n\n0011,mul"),
+        "Invalid control ROM: line 4: unknown ALU 'mul', expected one of \
+         add, sub, xor, xnor, or, nor, and, nand, imp, nimp, rsh"
+    );
+    assert_eq!(
+        error("Opcode,ALU\n2,add"),
+        "Invalid control ROM: line 2: opcode '2' is not 4 binary digits"
+    );
+    assert_eq!(
+        error("Opcode,ALU\n0010,add\n0010,sub"),
+        "Invalid control ROM: line 3: opcode 0010 is defined twice"
+    );
+    assert_eq!(
+        error("Opcode,ALU\n0010"),
+        "Invalid control ROM: line 2: expected 2 cells, found 1"
+    );
+}
+
+#[test]
+fn extended_alu_only_replaces_rsh() {
+    let rom = ControlRom::batpu2();
+    let or = Bits::from(0b0111_0001_0001_0010u16);
+    assert_eq!(rom.decode(or, false), rom.signals(0b0111));
+    assert_eq!(rom.decode(or, true).alu_settings, AluSettings::Or);
+    let add = Bits::from(0b0010_0001_0001_0010u16);
+    assert_eq!(rom.decode(add, true), rom.signals(0b0010));
+}
@@ -0,0 +1,506 @@
+use crate::error::Fault;
+use crate::instruction::Instruction;
+use crate::instruction_memory::INSTRUCTION_MEMORY_SIZE;
+use crate::parser::assemble_file;
+use crate::parser::error::ParserError;
+use crate::{Assembly, Result, VmConfig, OPCODE_HLT, VM};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+const DEFAULT_STEP_LIMIT: usize = 10_000_000;
+const DEFAULT_HISTORY: usize = 10_000;
+const LIST_CONTEXT: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Watch {
+    Register(u8),
+    Memory(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Step,
+    Breakpoint(u16),
+    Watchpoint { watch: Watch, old: u8, new: u8 },
+    Halted,
+    Fault(Fault),
+    StepLimit,
+}
+
+// Wraps a VM with breakpoints, watchpoints and the label names of the loaded program
+#[derive(Debug)]
+pub struct Debugger {
+    pub vm: VM,
+    labels: BTreeMap<String, u16>,
+    bank_names: BTreeMap<u8, String>,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: BTreeMap<Watch, u8>, // last seen value of each watch
+    halted: bool,
+    pub step_limit: usize,
+}
+
+impl Debugger {
+    pub fn new(mut vm: VM, labels: BTreeMap<String, u16>) -> Self {
+        vm.enable_history(DEFAULT_HISTORY);
+        Debugger {
+            vm,
+            labels,
+            bank_names: BTreeMap::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            halted: false,
+            step_limit: DEFAULT_STEP_LIMIT,
+        }
+    }
+
+    pub fn from_source(source: &str) -> Result<Self> {
+        let config = VmConfig::default();
+        Self::from_assembly(assemble_file("<input>", source, &config), config)
+    }
+
+    pub fn load(file_path: impl AsRef<Path>) -> Result<Self> {
+        Self::load_with(file_path, false)
+    }
+
+    // On a machine with the extended ALU
+    pub fn load_with(file_path: impl AsRef<Path>, extended_alu: bool) -> Result<Self> {
+        let path = file_path.as_ref();
+        let source = std::fs::read_to_string(path)
+            .map_err(|_| ParserError::FileNotFound(path.display().to_string()))?;
+        let config = VmConfig {
+            extended_alu,
+            ..Default::default()
+        };
+        Self::from_assembly(
+            assemble_file(&path.display().to_string(), &source, &config),
+            config,
+        )
+    }
+
+    // A program that names memory banks gets a machine with enough of them
+    fn from_assembly(mut assembly: Assembly, config: VmConfig) -> Result<Self> {
+        let labels = std::mem::take(&mut assembly.labels);
+        let banks = std::mem::take(&mut assembly.banks);
+        let program = assembly.into_result()?;
+        let mut vm = VM::with_config(VmConfig {
+            memory_banks: banks.values().max().map_or(1, |&last| last as usize + 1),
+            ..config
+        });
+        vm.load_instructions(&program)?;
+        let mut debugger = Debugger::new(vm, labels);
+        debugger.bank_names = banks.into_iter().map(|(name, bank)| (bank, name)).collect();
+        Ok(debugger)
+    }
+
+    pub fn pc(&self) -> u16 {
+        u16::from(self.vm.pc.value.resize::<16>())
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn current_instruction(&self) -> Option<Instruction> {
+        self.vm
+            .instruction_at(self.pc() as usize)
+            .map(|word| Instruction::decode_with(word, self.vm.config().extended_alu))
+    }
+
+    // Accepts a decimal address or a label, with or without its leading '.'
+    pub fn resolve(&self, location: &str) -> Option<u16> {
+        if let Ok(address) = location.parse() {
+            return Some(address);
+        }
+        self.labels
+            .get(location)
+            .or_else(|| self.labels.get(&format!(".{location}")))
+            .copied()
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn add_watchpoint(&mut self, watch: Watch) {
+        let value = self.read(watch);
+        self.watchpoints.insert(watch, value);
+    }
+
+    pub fn remove_watchpoint(&mut self, watch: Watch) -> bool {
+        self.watchpoints.remove(&watch).is_some()
+    }
+
+    pub fn read(&self, watch: Watch) -> u8 {
+        read(&self.vm, watch)
+    }
+
+    pub fn step(&mut self) -> StopReason {
+        if self.halted {
+            return StopReason::Halted;
+        }
+        match self.vm.try_clock() {
+            Ok(opcode) if opcode == OPCODE_HLT => {
+                self.halted = true;
+                return StopReason::Halted;
+            }
+            Ok(_) => {}
+            Err(fault) => return StopReason::Fault(fault),
+        }
+        self.check_watchpoints().unwrap_or(StopReason::Step)
+    }
+
+    // Undoes up to `count` instructions, returning how many were undone
+    pub fn step_back(&mut self, count: usize) -> usize {
+        let undone = (0..count).take_while(|_| self.vm.step_back()).count();
+        self.after_rewind();
+        undone
+    }
+
+    pub fn run_back_to(&mut self, address: u16) -> bool {
+        let found = self.vm.run_back_to(address);
+        self.after_rewind();
+        found
+    }
+
+    fn after_rewind(&mut self) {
+        self.halted = false;
+        for (&watch, value) in self.watchpoints.iter_mut() {
+            *value = read(&self.vm, watch);
+        }
+    }
+
+    // Steps over calls: a CAL runs until its subroutine has returned to the next
+    // instruction. A CAL on a full stack keeps the depth, so the depth alone cannot tell.
+    pub fn step_over(&mut self) -> StopReason {
+        match self.current_instruction() {
+            Some(Instruction::Cal { .. }) => {
+                let depth = self.vm.call_stack().len();
+                let return_address = (self.pc() + 1) % INSTRUCTION_MEMORY_SIZE as u16;
+                self.run_until(|debugger| {
+                    debugger.pc() == return_address && debugger.vm.call_stack().len() <= depth
+                })
+            }
+            _ => self.step(),
+        }
+    }
+
+    pub fn continue_execution(&mut self) -> StopReason {
+        self.run_until(|_| false)
+    }
+
+    fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> StopReason {
+        for _ in 0..self.step_limit {
+            match self.step() {
+                StopReason::Step => {}
+                reason => return reason,
+            }
+            if done(self) {
+                return StopReason::Step;
+            }
+            let pc = self.pc();
+            if self.breakpoints.contains(&pc) {
+                return StopReason::Breakpoint(pc);
+            }
+        }
+        StopReason::StepLimit
+    }
+
+    fn check_watchpoints(&mut self) -> Option<StopReason> {
+        let mut hit = None;
+        for (&watch, value) in self.watchpoints.iter_mut() {
+            let (old, new) = (*value, read(&self.vm, watch));
+            *value = new;
+            if old != new && hit.is_none() {
+                hit = Some(StopReason::Watchpoint { watch, old, new });
+            }
+        }
+        hit
+    }
+
+    fn label_at(&self, address: u16) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|(_, &target)| target == address)
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn format_instruction(&self, address: u16) -> String {
+        let Some(word) = self.vm.instruction_at(address as usize) else {
+            return format!("{address:>4}: <end of program>");
+        };
+        let label = |target: u16| match self.label_at(target) {
+            Some(name) => name.to_string(),
+            None => target.to_string(),
+        };
+        let text = Instruction::decode_with(word, self.vm.config().extended_alu).to_assembly(label);
+        match self.label_at(address) {
+            Some(name) => format!("{address:>4}: {text:<24} {name}"),
+            None => format!("{address:>4}: {text}"),
+        }
+    }
+
+    pub fn describe(&self, reason: StopReason) -> String {
+        let location = self.format_instruction(self.pc());
+        match reason {
+            StopReason::Step => location,
+            StopReason::Breakpoint(address) => format!("breakpoint at {address}\n{location}"),
+            StopReason::Watchpoint { watch, old, new } => {
+                format!("{} changed: {old} -> {new}\n{location}", watch_name(watch))
+            }
+            StopReason::Halted => "program halted".to_string(),
+            StopReason::Fault(fault) => format!("{fault}\n{location}"),
+            StopReason::StepLimit => {
+                format!("stopped after {} steps\n{location}", self.step_limit)
+            }
+        }
+    }
+
+    // Runs one line of debugger input and returns the text to show for it
+    pub fn execute(&mut self, command: &str) -> String {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return String::new();
+        };
+        let args: Vec<&str> = words.collect();
+        match (name, args.as_slice()) {
+            ("step" | "s", []) => {
+                let reason = self.step();
+                self.describe(reason)
+            }
+            ("step" | "s", [count]) => match count.parse::<usize>() {
+                Ok(count) => {
+                    let mut reason = StopReason::Step;
+                    for _ in 0..count {
+                        reason = self.step();
+                        if reason != StopReason::Step {
+                            break;
+                        }
+                    }
+                    self.describe(reason)
+                }
+                Err(_) => format!("invalid step count '{count}'"),
+            },
+            ("back" | "bs", []) => self.execute("back 1"),
+            ("back" | "bs", [count]) => match count.parse::<usize>() {
+                Ok(count) => {
+                    let undone = self.step_back(count);
+                    let location = self.format_instruction(self.pc());
+                    if undone < count {
+                        format!("reached the start of the history\n{location}")
+                    } else {
+                        location
+                    }
+                }
+                Err(_) => format!("invalid step count '{count}'"),
+            },
+            ("rewind", [location]) => match self.resolve(location) {
+                Some(address) if self.run_back_to(address) => self.format_instruction(address),
+                Some(_) => format!(
+                    "{location} is not in the history\n{}",
+                    self.format_instruction(self.pc())
+                ),
+                None => format!("unknown address or label '{location}'"),
+            },
+            ("save", [path]) => match self.vm.save_state(path) {
+                Ok(()) => format!("state saved to {path}"),
+                Err(e) => e.to_string(),
+            },
+            ("load", [path]) => match self.vm.load_state(path) {
+                Ok(()) => {
+                    self.after_rewind();
+                    format!(
+                        "state loaded from {path}\n{}",
+                        self.format_instruction(self.pc())
+                    )
+                }
+                Err(e) => e.to_string(),
+            },
+            ("next" | "n", []) => {
+                let reason = self.step_over();
+                self.describe(reason)
+            }
+            ("continue" | "c", []) => {
+                let reason = self.continue_execution();
+                self.describe(reason)
+            }
+            ("break" | "b", []) => self.list_breakpoints(),
+            ("break" | "b", [location]) => match self.resolve(location) {
+                Some(address) => {
+                    self.add_breakpoint(address);
+                    format!("breakpoint set at {address}")
+                }
+                None => format!("unknown address or label '{location}'"),
+            },
+            ("delete" | "d", [location]) => match self.resolve(location) {
+                Some(address) if self.remove_breakpoint(address) => {
+                    format!("breakpoint at {address} deleted")
+                }
+                _ => format!("no breakpoint at '{location}'"),
+            },
+            ("watch" | "w", [target]) => match parse_watch(target) {
+                Some(watch) => {
+                    self.add_watchpoint(watch);
+                    format!("watching {} = {}", watch_name(watch), self.read(watch))
+                }
+                None => format!("cannot watch '{target}', expected rN or mem[N]"),
+            },
+            ("unwatch", [target]) => match parse_watch(target) {
+                Some(watch) if self.remove_watchpoint(watch) => {
+                    format!("no longer watching {}", watch_name(watch))
+                }
+                _ => format!("not watching '{target}'"),
+            },
+            ("regs" | "r", []) => self.format_registers(),
+            ("mem" | "m", []) => self.format_memory(),
+            ("mem" | "m", [address]) => match address.parse::<u8>() {
+                Ok(address) => format!("mem[{address}] = {}", self.read(Watch::Memory(address))),
+                Err(_) => format!("invalid memory address '{address}'"),
+            },
+            ("flags" | "f", []) => {
+                let flags = self.vm.flags();
+                format!("zero={} carry={}", flags.zero as u8, flags.carry as u8)
+            }
+            ("stack" | "bt", []) => self.format_call_stack(),
+            ("bank", []) => self.format_bank(),
+            ("where" | "pc", []) => self.format_instruction(self.pc()),
+            ("list" | "l", []) => self.format_listing(),
+            ("screen", []) => self.vm.io_devices.screen.render().trim_end().to_string(),
+            ("help" | "h", []) => HELP.to_string(),
+            _ => format!("unknown command '{command}', type 'help' for a list of commands"),
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "no breakpoints".to_string();
+        }
+        let lines: Vec<String> = self
+            .breakpoints
+            .iter()
+            .map(|&address| self.format_instruction(address))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn format_registers(&self) -> String {
+        let mut out = String::new();
+        for index in 0..16u8 {
+            let value = self.read(Watch::Register(index));
+            let _ = write!(out, "r{index:<2} = {value:>3}");
+            out.push(if index % 4 == 3 { '\n' } else { ' ' });
+        }
+        out.trim_end().to_string()
+    }
+
+    fn format_bank(&self) -> String {
+        let memory = &self.vm.data_memory;
+        if memory.bank_count() == 1 {
+            return "memory banking is off".to_string();
+        }
+        let bank = memory.bank();
+        let mut out = format!("bank {bank} of {}", memory.bank_count());
+        if let Some(name) = self.bank_names.get(&(bank as u8)) {
+            let _ = write!(out, " ({name})");
+        }
+        let _ = write!(out, " is at mem[0..{}]", memory.bank_window());
+        out
+    }
+
+    fn format_memory(&self) -> String {
+        let mut out = String::new();
+        if self.vm.data_memory.bank_count() > 1 {
+            out.push_str(&self.format_bank());
+            out.push('\n');
+        }
+        for (row, chunk) in self.vm.data_memory.memory.chunks(16).enumerate() {
+            let _ = write!(out, "{:>3}:", row * 16);
+            for value in chunk {
+                let _ = write!(out, " {:>3}", u8::from(*value));
+            }
+            out.push('\n');
+        }
+        out.trim_end().to_string()
+    }
+
+    fn format_call_stack(&self) -> String {
+        let stack = self.vm.call_stack();
+        if stack.is_empty() {
+            return "call stack is empty".to_string();
+        }
+        let lines: Vec<String> = stack
+            .iter()
+            .enumerate()
+            .map(|(depth, &address)| format!("#{depth} {}", self.format_instruction(address)))
+            .collect();
+        lines.join("\n")
+    }
+
+    fn format_listing(&self) -> String {
+        let pc = self.pc() as usize;
+        let start = pc.saturating_sub(LIST_CONTEXT);
+        let lines: Vec<String> = (start..=pc + LIST_CONTEXT)
+            .filter(|&address| self.vm.instruction_at(address).is_some())
+            .map(|address| {
+                let marker = if address == pc { "=>" } else { "  " };
+                format!("{marker} {}", self.format_instruction(address as u16))
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+fn read(vm: &VM, watch: Watch) -> u8 {
+    match watch {
+        Watch::Register(index) => u8::from(vm.reg_file.register_banks[0][index as usize]),
+        Watch::Memory(address) => u8::from(vm.data_memory.memory[address as usize]),
+    }
+}
+
+fn parse_watch(target: &str) -> Option<Watch> {
+    if let Some(index) = target.strip_prefix(['r', 'R']) {
+        return index
+            .parse()
+            .ok()
+            .filter(|&index| index < 16)
+            .map(Watch::Register);
+    }
+    let address = target.strip_prefix("mem[")?.strip_suffix(']')?;
+    address.parse().ok().map(Watch::Memory)
+}
+
+fn watch_name(watch: Watch) -> String {
+    match watch {
+        Watch::Register(index) => format!("r{index}"),
+        Watch::Memory(address) => format!("mem[{address}]"),
+    }
+}
+
+const HELP: &str = "\
+step [n]         (s)   execute one or n instructions
+back [n]         (bs)  undo one or n instructions
+rewind location        undo instructions until location is reached again
+next             (n)   execute one instruction, stepping over calls
+continue         (c)   run until a breakpoint, watchpoint or HLT
+break [location] (b)   set a breakpoint at an address or label, or list them
+delete location  (d)   remove a breakpoint
+watch target     (w)   stop when rN or mem[N] changes
+unwatch target         stop watching rN or mem[N]
+regs             (r)   show the registers
+mem [address]    (m)   show data memory, or a single address
+flags            (f)   show the zero and carry flags
+stack            (bt)  show the return addresses on the call stack
+bank                   show the selected memory bank
+where            (pc)  show the current instruction
+list             (l)   show the instructions around the current one
+screen                 show the screen
+save path              write the machine state to a file
+load path              replace the machine state with a saved one
+quit             (q)   leave the debugger";
+
+#[cfg(test)]
+mod tests;
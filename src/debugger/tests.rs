@@ -0,0 +1,238 @@
+use super::{Debugger, StopReason, Watch};
+
+const PROGRAM: &str = "\
+LDI r1 3
+.loop
+CAL .double
+DEC r1
+BRH notzero .loop
+LDI r3 7
+STR r0 r3 5
+HLT
+.double
+LSH r2 r2
+INC r2
+RET
+";
+
+fn debugger() -> Debugger {
+    Debugger::from_source(PROGRAM).unwrap()
+}
+
+#[test]
+fn breakpoint_on_label() {
+    let mut debugger = debugger();
+    assert!(debugger.add_breakpoint(debugger.resolve("double").unwrap()));
+    assert_eq!(debugger.continue_execution(), StopReason::Breakpoint(7));
+    assert_eq!(debugger.vm.call_stack(), vec![2]);
+    assert_eq!(debugger.continue_execution(), StopReason::Breakpoint(7));
+}
+
+#[test]
+fn next_steps_over_calls() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.step_over(), StopReason::Step);
+    assert_eq!(debugger.pc(), 1);
+    assert_eq!(debugger.step_over(), StopReason::Step);
+    assert_eq!(debugger.pc(), 2);
+    assert_eq!(debugger.read(Watch::Register(2)), 1);
+    assert!(debugger.vm.call_stack().is_empty());
+}
+
+#[test]
+fn next_steps_over_calls_on_a_full_stack() {
+    let source = "\
+LDI r1 17
+.fill
+DEC r1
+BRH zero .full
+CAL .fill
+RET
+.full
+CAL .sub
+LDI r2 1
+HLT
+.sub
+LDI r3 2
+RET
+";
+    let mut debugger = Debugger::from_source(source).unwrap();
+    assert!(debugger.add_breakpoint(debugger.resolve("full").unwrap()));
+    assert_eq!(debugger.continue_execution(), StopReason::Breakpoint(5));
+    assert_eq!(debugger.vm.call_stack().len(), 16);
+    assert_eq!(debugger.step_over(), StopReason::Step);
+    assert_eq!(debugger.pc(), 6);
+    assert_eq!(debugger.read(Watch::Register(3)), 2);
+    assert_eq!(debugger.vm.call_stack().len(), 15);
+}
+
+#[test]
+fn register_watchpoint() {
+    let mut debugger = debugger();
+    debugger.add_watchpoint(Watch::Register(1));
+    assert_eq!(
+        debugger.continue_execution(),
+        StopReason::Watchpoint {
+            watch: Watch::Register(1),
+            old: 0,
+            new: 3
+        }
+    );
+    assert_eq!(
+        debugger.continue_execution(),
+        StopReason::Watchpoint {
+            watch: Watch::Register(1),
+            old: 3,
+            new: 2
+        }
+    );
+}
+
+#[test]
+fn memory_watchpoint_and_halt() {
+    let mut debugger = debugger();
+    debugger.add_watchpoint(Watch::Memory(5));
+    assert_eq!(
+        debugger.continue_execution(),
+        StopReason::Watchpoint {
+            watch: Watch::Memory(5),
+            old: 0,
+            new: 7
+        }
+    );
+    assert_eq!(debugger.continue_execution(), StopReason::Halted);
+    assert!(debugger.is_halted());
+    assert_eq!(debugger.step(), StopReason::Halted);
+}
+
+#[test]
+fn commands() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.execute("where"), "   0: LDI r1 3");
+    assert_eq!(debugger.execute("b .double"), "breakpoint set at 7");
+    assert_eq!(
+        debugger.execute("c"),
+        "breakpoint at 7\n   7: LSH r2 r2                .double"
+    );
+    assert_eq!(debugger.execute("stack"), "#0    2: DEC r1");
+    assert_eq!(debugger.execute("flags"), "zero=0 carry=0");
+    assert!(debugger.execute("regs").starts_with("r0  =   0 r1  =   3"));
+    assert_eq!(debugger.execute("mem 5"), "mem[5] = 0");
+    assert_eq!(debugger.execute("watch r9"), "watching r9 = 0");
+    assert_eq!(debugger.execute("d 7"), "breakpoint at 7 deleted");
+    assert_eq!(debugger.execute("break"), "no breakpoints");
+    assert!(debugger.execute("list").contains("=>    7: LSH r2 r2"));
+    assert!(debugger
+        .execute("frobnicate")
+        .starts_with("unknown command"));
+}
+
+#[test]
+fn unknown_label() {
+    let mut debugger = debugger();
+    assert_eq!(
+        debugger.execute("break .nowhere"),
+        "unknown address or label '.nowhere'"
+    );
+    assert_eq!(
+        debugger.execute("watch r16"),
+        "cannot watch 'r16', expected rN or mem[N]"
+    );
+}
+
+#[test]
+fn rewind() {
+    let mut debugger = debugger();
+    assert_eq!(debugger.continue_execution(), StopReason::Halted);
+    debugger.add_watchpoint(Watch::Register(1));
+    assert_eq!(debugger.read(Watch::Register(1)), 0);
+    assert_eq!(debugger.execute("back 2"), "   5: STR r0 r3 5");
+    assert!(!debugger.is_halted());
+    assert!(debugger.run_back_to(7));
+    assert_eq!(debugger.read(Watch::Register(1)), 1);
+    assert_eq!(debugger.read(Watch::Register(2)), 3);
+    assert_eq!(debugger.vm.call_stack(), vec![2]);
+    assert_eq!(
+        debugger.execute("rewind .loop"),
+        "   1: CAL .double              .loop"
+    );
+    assert_eq!(debugger.read(Watch::Register(1)), 1);
+    // a location that is not in the history leaves the machine where it was
+    assert!(debugger
+        .execute("rewind 4")
+        .starts_with("4 is not in the history"));
+    assert_eq!(debugger.pc(), 1);
+    assert_eq!(debugger.step_back(100), 13);
+    assert_eq!(debugger.pc(), 0);
+    assert_eq!(
+        debugger.continue_execution(),
+        StopReason::Watchpoint {
+            watch: Watch::Register(1),
+            old: 0,
+            new: 3
+        }
+    );
+}
+
+#[test]
+fn save_and_load() {
+    let path = std::env::temp_dir().join(format!("rust_vm_debugger_{}.bpss", std::process::id()));
+    let path = path.display().to_string();
+    let mut debugger = debugger();
+    debugger.execute("step 3");
+    assert_eq!(
+        debugger.execute(&format!("save {path}")),
+        format!("state saved to {path}")
+    );
+    debugger.continue_execution();
+    assert_eq!(
+        debugger.execute(&format!("load {path}")),
+        format!("state loaded from {path}\n   8: INC r2")
+    );
+    std::fs::remove_file(&path).unwrap();
+    assert!(!debugger.is_halted());
+    assert_eq!(debugger.vm.call_stack(), vec![2]);
+}
+
+#[test]
+fn stops_on_fault() {
+    let mut vm = crate::VM::with_config(crate::VmConfig {
+        fault_policy: crate::FaultPolicy::Trap,
+        ..Default::default()
+    });
+    vm.load_source("LDI r1 1\nRET").unwrap();
+    let mut debugger = Debugger::new(vm, Default::default());
+    assert_eq!(
+        debugger.execute("c"),
+        "call stack underflow at pc 1\n   1: RET"
+    );
+    assert!(!debugger.is_halted());
+}
+
+#[test]
+fn shows_the_selected_bank() {
+    assert_eq!(debugger().execute("bank"), "memory banking is off");
+    let mut debugger =
+        Debugger::from_source("bank scores 3\nLDI r15 bank_select\nLDI r1 scores\nSTR r15 r1\nHLT")
+            .unwrap();
+    assert_eq!(debugger.execute("bank"), "bank 0 of 4 is at mem[0..128]");
+    debugger.continue_execution();
+    assert_eq!(
+        debugger.execute("bank"),
+        "bank 3 of 4 (scores) is at mem[0..128]"
+    );
+    assert!(debugger
+        .execute("mem")
+        .starts_with("bank 3 of 4 (scores) is at mem[0..128]\n  0:"));
+}
+
+#[test]
+fn load_with_the_extended_alu() {
+    let path = std::env::temp_dir().join(format!("rust_vm_debug_{}.as", std::process::id()));
+    std::fs::write(&path, "LDI r1 12\nLDI r2 10\nOR r1 r2\nHLT").unwrap();
+    assert!(Debugger::load(&path).is_err());
+    let mut debugger = Debugger::load_with(&path, true).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(debugger.continue_execution(), StopReason::Halted);
+    assert_eq!(debugger.read(Watch::Register(2)), 14);
+}
@@ -0,0 +1,64 @@
+use crate::instruction::Instruction;
+use crate::parser::error::ParserError;
+use crate::{Bits, Program, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+pub fn disassemble(program: &[Bits<16>]) -> String {
+    disassemble_with(program, false)
+}
+
+// With `extended_alu`, RSH words with a function in their B field come out as OR, XNOR,
+// NAND, IMP and NIMP
+pub fn disassemble_with(program: &[Bits<16>], extended_alu: bool) -> String {
+    let instructions: Vec<Instruction> = program
+        .iter()
+        .map(|w| Instruction::decode_with(*w, extended_alu))
+        .collect();
+
+    // only targets that can be placed in front of an instruction (or at the very end) get a label
+    let labels: BTreeMap<u16, String> = instructions
+        .iter()
+        .filter_map(Instruction::jump_target)
+        .filter(|&target| (target as usize) <= instructions.len())
+        .map(|target| (target, format!(".addr{target}")))
+        .collect();
+    let label = |address: u16| match labels.get(&address) {
+        Some(name) => name.clone(),
+        None => address.to_string(),
+    };
+
+    let mut out = String::new();
+    for (address, instruction) in instructions.iter().enumerate() {
+        if let Some(name) = labels.get(&(address as u16)) {
+            out.push_str(name);
+            out.push('\n');
+        }
+        out.push_str("  ");
+        out.push_str(&instruction.to_assembly(label));
+        out.push('\n');
+    }
+    if let Some(name) = labels.get(&(instructions.len() as u16)) {
+        out.push_str(name);
+        out.push('\n');
+    }
+    out
+}
+
+// Reads the contents of a `.mc` file, one 16-bit binary word per line
+pub fn parse_machine_code(source: &str) -> Result<Program> {
+    source
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            if line.len() != 16 {
+                return Err(ParserError::InvalidInstruction(line.to_string()).into());
+            }
+            Bits::from_str(line)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,97 @@
+use super::*;
+use crate::parser::assemble;
+
+fn roundtrip(source: &str) -> String {
+    disassemble(&assemble(source).unwrap())
+}
+
+#[test]
+fn plain_instructions() {
+    let out = roundtrip("ADD r1 r2 r3\nXOR r4 r5 r6\nRSH r2 r3\nLDI r1 42\nHLT");
+    assert_eq!(
+        out,
+        "  ADD r1 r2 r3\n  XOR r4 r5 r6\n  RSH r2 r3\n  LDI r1 42\n  HLT\n"
+    );
+}
+
+#[test]
+fn pseudo_instructions() {
+    let out = roundtrip("MOV r1 r2\nCMP r3 r4\nDEC r5\nINC r6\nNOT r7 r8\nLSH r9 r10");
+    assert_eq!(
+        out,
+        "  MOV r1 r2\n  CMP r3 r4\n  DEC r5\n  INC r6\n  NOT r7 r8\n  LSH r9 r10\n"
+    );
+}
+
+#[test]
+fn port_names() {
+    let out = roundtrip("LDI r15 pixel_x\nLDI r14 controller_input\nLDI r13 239");
+    assert_eq!(
+        out,
+        "  LDI r15 pixel_x\n  LDI r14 controller_input\n  LDI r13 239\n"
+    );
+}
+
+#[test]
+fn memory_offsets() {
+    let out = roundtrip("LOD r1 r2 -3\nSTR r1 r2 7\nSTR r1 r2");
+    assert_eq!(out, "  LOD r1 r2 -3\n  STR r1 r2 7\n  STR r1 r2\n");
+}
+
+#[test]
+fn synthesises_labels() {
+    let source = ".loop\nINC r1\nCMP r1 r2\nBRH ne .loop\nCAL .done\nJMP 900\n.done\nRET";
+    let out = roundtrip(source);
+    assert_eq!(
+        out,
+        ".addr0\n  INC r1\n  CMP r1 r2\n  BRH notzero .addr0\n  CAL .addr5\n  JMP 900\n.addr5\n  RET\n"
+    );
+}
+
+#[test]
+fn reassembles_to_same_machine_code() {
+    for name in [
+        "2048",
+        "calculator",
+        "connect4",
+        "dvd",
+        "gol",
+        "helloworld",
+        "maze",
+        "minesweeper",
+        "tetris",
+    ] {
+        let mc = std::fs::read_to_string(format!("check_mc/{name}.mc")).unwrap();
+        let program = parse_machine_code(&mc).unwrap();
+        let source = disassemble(&program);
+        assert_eq!(
+            assemble(&source).unwrap(),
+            program,
+            "{name} did not round-trip"
+        );
+    }
+}
+
+#[test]
+fn machine_code_with_bad_line() {
+    assert!(parse_machine_code("0001000000000000\n101").is_err());
+    assert!(parse_machine_code("000100000000000x").is_err());
+}
+
+#[test]
+fn extended_alu_functions() {
+    let program = crate::assemble_with(
+        "OR r1 r2\nXNOR r3 r4\nNAND r5 r6\nIMP r7 r8\nNIMP r9 r10\nRSH r1 r2",
+        true,
+    )
+    .unwrap();
+    assert_eq!(
+        disassemble_with(&program, true),
+        "  OR r1 r2\n  XNOR r3 r4\n  NAND r5 r6\n  IMP r7 r8\n  NIMP r9 r10\n  RSH r1 r2\n"
+    );
+    // the BatPU-2 ignores the function field
+    assert_eq!(
+        disassemble(&program),
+        "  RSH r1 r2\n  RSH r3 r4\n  RSH r5 r6\n  RSH r7 r8\n  RSH r9 r10\n  RSH r1 r2\n"
+    );
+}
@@ -1,31 +1,97 @@
+use crate::parser::diagnostic::Diagnostic;
 use crate::BitsParseError;
 use crate::ParserError;
 use std::fmt;
 use std::io;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeError {
+    CallStackUnderflow,
+    CallStackOverflow,
+    PcOutOfBounds,
+    WriteToReadOnlyPort(u8),
+    ReadFromWriteOnlyPort(u8),
+}
+
+// A runtime error together with the address of the instruction that caused it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fault {
+    pub pc: u16,
+    pub error: RuntimeError,
+}
+
 #[derive(Debug)]
 pub enum VmError {
     Parser(ParserError),
+    Assembly(Vec<Diagnostic>),
     Bits(BitsParseError),
     Io(io::Error),
     NumberParse(std::num::ParseIntError),
     InstructionMemoryOverflow,
+    Trace(String),
+    State(String),
+    Spec(String),
+    Screen(String),
+    Input(String),
+    Config(String),
+    ControlRom(String),
+    Runtime(Fault),
 }
 
 impl fmt::Display for VmError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             VmError::Parser(e) => write!(f, "Parser error: {e}"),
+            VmError::Assembly(diagnostics) => {
+                for (i, d) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                        writeln!(f)?;
+                    }
+                    write!(f, "{d}")?;
+                }
+                Ok(())
+            }
             VmError::Bits(e) => write!(f, "Bits error: {e}"),
             VmError::Io(e) => write!(f, "IO error: {e}"),
             VmError::NumberParse(e) => write!(f, "Number parse error: {e}"),
             VmError::InstructionMemoryOverflow => write!(f, "Instruction memory overflow. This error occurs when trying to load more instructions than the instruction memory can hold."),
+            VmError::Trace(reason) => write!(f, "Invalid trace: {reason}"),
+            VmError::State(reason) => write!(f, "Invalid state file: {reason}"),
+            VmError::Spec(reason) => write!(f, "Invalid spec: {reason}"),
+            VmError::Screen(reason) => write!(f, "Invalid screen file: {reason}"),
+            VmError::Input(reason) => write!(f, "Invalid input script: {reason}"),
+            VmError::Config(reason) => write!(f, "Invalid machine configuration: {reason}"),
+            VmError::ControlRom(reason) => write!(f, "Invalid control ROM: {reason}"),
+            VmError::Runtime(fault) => write!(f, "Runtime error: {fault}"),
         }
     }
 }
 
 impl std::error::Error for VmError {}
 
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::CallStackUnderflow => write!(f, "call stack underflow"),
+            RuntimeError::CallStackOverflow => write!(f, "call stack overflow"),
+            RuntimeError::PcOutOfBounds => write!(f, "ran past the end of the program"),
+            RuntimeError::WriteToReadOnlyPort(port) => {
+                write!(f, "store to load-only port {port}")
+            }
+            RuntimeError::ReadFromWriteOnlyPort(port) => {
+                write!(f, "load from store-only port {port}")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at pc {}", self.error, self.pc)
+    }
+}
+
 impl From<ParserError> for VmError {
     fn from(e: ParserError) -> Self {
         VmError::Parser(e)
@@ -41,6 +107,11 @@ impl From<io::Error> for VmError {
         VmError::Io(e)
     }
 }
+impl From<Fault> for VmError {
+    fn from(fault: Fault) -> Self {
+        VmError::Runtime(fault)
+    }
+}
 impl From<std::num::ParseIntError> for VmError {
     fn from(e: std::num::ParseIntError) -> Self {
         VmError::NumberParse(e)
@@ -52,8 +123,17 @@ impl PartialEq for VmError {
         use VmError::*;
         match (self, other) {
             (Parser(a), Parser(b)) => a == b,
+            (Assembly(a), Assembly(b)) => a == b,
             (Bits(a), Bits(b)) => a == b,
             (InstructionMemoryOverflow, InstructionMemoryOverflow) => true,
+            (Trace(a), Trace(b)) => a == b,
+            (State(a), State(b)) => a == b,
+            (Spec(a), Spec(b)) => a == b,
+            (Screen(a), Screen(b)) => a == b,
+            (Input(a), Input(b)) => a == b,
+            (Config(a), Config(b)) => a == b,
+            (ControlRom(a), ControlRom(b)) => a == b,
+            (Runtime(a), Runtime(b)) => a == b,
             // Io and NumberParse are not comparable
             _ => false,
         }
@@ -0,0 +1,222 @@
+// Controller input scripts. One event per line, `#` starts a comment:
+//
+//     at cycle 12000 press right for 3000 cycles
+//     at cycle 20000 press a+start
+//     at cycle 26000 release start
+//
+// A press without `for` holds the buttons until they are released. Cycles count executed
+// instructions, so an event at cycle N takes effect before instruction N + 1 runs. A
+// `seed N` line records the RNG seed of the session, so `run --input` replays it exactly.
+use crate::error::VmError;
+use crate::io_devices::controller::{Button, Controller};
+use crate::Result;
+use std::fmt::Write as _;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputEvent {
+    pub cycle: u64,
+    pub button: Button,
+    pub pressed: bool,
+}
+
+// Events in cycle order, replayed with `advance`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputScript {
+    pub seed: Option<u64>,
+    pub events: Vec<InputEvent>,
+    position: usize,
+}
+
+impl InputScript {
+    pub fn new(mut events: Vec<InputEvent>) -> Self {
+        // stable, so a release written before a press on the same cycle stays first
+        events.sort_by_key(|event| event.cycle);
+        InputScript {
+            seed: None,
+            events,
+            position: 0,
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut events = Vec::new();
+        let mut seed = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let error = |reason| VmError::Input(format!("line {}: {reason}", number + 1));
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(value) = line.strip_prefix("seed ") {
+                let value = value.trim();
+                seed = Some(
+                    value
+                        .parse()
+                        .map_err(|_| error(format!("invalid seed '{value}'")))?,
+                );
+                continue;
+            }
+            parse_line(line, &mut events).map_err(error)?;
+        }
+        Ok(InputScript {
+            seed,
+            ..InputScript::new(events)
+        })
+    }
+
+    pub fn load(file_path: impl AsRef<Path>) -> Result<Self> {
+        InputScript::parse(&std::fs::read_to_string(file_path)?)
+    }
+
+    pub fn save(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(file_path, self.to_text())?;
+        Ok(())
+    }
+
+    // Applies every event up to and including `cycle` that has not been applied yet
+    pub fn advance(&mut self, cycle: u64, controller: &mut Controller) {
+        while let Some(event) = self.events.get(self.position) {
+            if event.cycle > cycle {
+                break;
+            }
+            controller.set(event.button, event.pressed);
+            self.position += 1;
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position == self.events.len()
+    }
+
+    // A press followed by a release of the same button is written as one `for` line
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        if let Some(seed) = self.seed {
+            let _ = writeln!(out, "seed {seed}");
+        }
+        for (index, event) in self.events.iter().enumerate() {
+            if !event.pressed {
+                let pressed_before = self.events[..index]
+                    .iter()
+                    .rev()
+                    .find(|earlier| earlier.button == event.button)
+                    .is_some_and(|earlier| earlier.pressed);
+                if !pressed_before {
+                    let _ = writeln!(
+                        out,
+                        "at cycle {} release {}",
+                        event.cycle,
+                        event.button.name()
+                    );
+                }
+                continue;
+            }
+            let release = self.events[index + 1..]
+                .iter()
+                .find(|later| later.button == event.button);
+            let _ = match release {
+                Some(later) if !later.pressed => writeln!(
+                    out,
+                    "at cycle {} press {} for {} cycles",
+                    event.cycle,
+                    event.button.name(),
+                    later.cycle - event.cycle
+                ),
+                _ => writeln!(
+                    out,
+                    "at cycle {} press {}",
+                    event.cycle,
+                    event.button.name()
+                ),
+            };
+        }
+        out
+    }
+}
+
+fn parse_line(line: &str, events: &mut Vec<InputEvent>) -> std::result::Result<(), String> {
+    let words: Vec<String> = line.split_whitespace().map(str::to_lowercase).collect();
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    let (cycle, action, buttons, duration) = match words.as_slice() {
+        ["at", "cycle", cycle, action, buttons] => (cycle, action, buttons, None),
+        ["at", "cycle", cycle, action, buttons, "for", duration, "cycles" | "cycle"] => {
+            (cycle, action, buttons, Some(duration))
+        }
+        _ => {
+            return Err(format!(
+                "expected 'at cycle N press|release BUTTONS [for M cycles]', found '{line}'"
+            ))
+        }
+    };
+    let cycle: u64 = cycle
+        .parse()
+        .map_err(|_| format!("invalid cycle '{cycle}'"))?;
+    let pressed = match *action {
+        "press" => true,
+        "release" if duration.is_none() => false,
+        "release" => return Err("only a press can last for a number of cycles".to_string()),
+        _ => return Err(format!("expected press or release, found '{action}'")),
+    };
+    let duration = match duration {
+        Some(duration) => Some(
+            duration
+                .parse::<u64>()
+                .map_err(|_| format!("invalid duration '{duration}'"))?,
+        ),
+        None => None,
+    };
+    for name in buttons.split('+') {
+        let button = Button::from_name(name).ok_or(format!(
+            "unknown button '{name}', expected one of {}",
+            Button::ALL.map(Button::name).join(", ")
+        ))?;
+        events.push(InputEvent {
+            cycle,
+            button,
+            pressed,
+        });
+        if let Some(duration) = duration {
+            events.push(InputEvent {
+                cycle: cycle + duration,
+                button,
+                pressed: false,
+            });
+        }
+    }
+    Ok(())
+}
+
+// Builds a script from the controller state sampled once per frame
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InputRecorder {
+    previous: Controller,
+    events: Vec<InputEvent>,
+}
+
+impl InputRecorder {
+    pub fn new() -> Self {
+        InputRecorder::default()
+    }
+
+    pub fn record(&mut self, cycle: u64, controller: &Controller) {
+        for button in Button::ALL {
+            let pressed = controller.is_pressed(button);
+            if pressed != self.previous.is_pressed(button) {
+                self.events.push(InputEvent {
+                    cycle,
+                    button,
+                    pressed,
+                });
+            }
+        }
+        self.previous = *controller;
+    }
+
+    pub fn finish(self) -> InputScript {
+        InputScript::new(self.events)
+    }
+}
+
+#[cfg(test)]
+mod tests;
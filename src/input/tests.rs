@@ -0,0 +1,121 @@
+use super::{InputEvent, InputRecorder, InputScript};
+use crate::error::VmError;
+use crate::io_devices::controller::{Button, Controller};
+
+const SCRIPT: &str = "\
+# open the menu, then hold right
+at cycle 100 press A+Start for 20 cycles
+at cycle 300 press right
+at cycle 500 release right # done
+";
+
+fn event(cycle: u64, button: Button, pressed: bool) -> InputEvent {
+    InputEvent {
+        cycle,
+        button,
+        pressed,
+    }
+}
+
+#[test]
+fn parses_events() {
+    let script = InputScript::parse(SCRIPT).unwrap();
+    assert_eq!(
+        script.events,
+        vec![
+            event(100, Button::A, true),
+            event(100, Button::Start, true),
+            event(120, Button::A, false),
+            event(120, Button::Start, false),
+            event(300, Button::Right, true),
+            event(500, Button::Right, false),
+        ]
+    );
+}
+
+#[test]
+fn replays_in_order() {
+    let mut script = InputScript::parse(SCRIPT).unwrap();
+    let mut controller = Controller::new();
+    script.advance(99, &mut controller);
+    assert_eq!(u8::from(controller.value), 0);
+    script.advance(100, &mut controller);
+    assert!(controller.a && controller.start);
+    assert_eq!(u8::from(controller.value), 0b1010_0000);
+    script.advance(450, &mut controller);
+    assert!(!controller.a && controller.right);
+    assert!(!script.is_finished());
+    script.advance(u64::MAX, &mut controller);
+    assert_eq!(controller, Controller::new());
+    assert!(script.is_finished());
+}
+
+#[test]
+fn text_round_trip() {
+    let script = InputScript::parse(SCRIPT).unwrap();
+    assert_eq!(
+        script.to_text(),
+        "at cycle 100 press a for 20 cycles\n\
+         at cycle 100 press start for 20 cycles\n\
+         at cycle 300 press right for 200 cycles\n"
+    );
+    assert_eq!(InputScript::parse(&script.to_text()).unwrap(), script);
+
+    let held = InputScript::parse("at cycle 5 release b\nat cycle 9 press up").unwrap();
+    assert_eq!(
+        held.to_text(),
+        "at cycle 5 release b\nat cycle 9 press up\n"
+    );
+}
+
+#[test]
+fn seed_line() {
+    let script = InputScript::parse("seed 77\nat cycle 1 press up").unwrap();
+    assert_eq!(script.seed, Some(77));
+    assert_eq!(script.to_text(), "seed 77\nat cycle 1 press up\n");
+    assert_eq!(
+        InputScript::parse("seed x"),
+        Err(VmError::Input("line 1: invalid seed 'x'".to_string()))
+    );
+}
+
+#[test]
+fn records_changes() {
+    let mut recorder = InputRecorder::new();
+    let mut controller = Controller::new();
+    recorder.record(150, &controller);
+    controller.set(Button::Left, true);
+    recorder.record(300, &controller);
+    recorder.record(450, &controller);
+    controller.set(Button::Left, false);
+    controller.set(Button::B, true);
+    recorder.record(600, &controller);
+    assert_eq!(
+        recorder.finish().to_text(),
+        "at cycle 300 press left for 300 cycles\nat cycle 600 press b\n"
+    );
+}
+
+#[test]
+fn invalid_scripts() {
+    assert_eq!(
+        InputScript::parse("\nat cycle 10 press jump"),
+        Err(VmError::Input(
+            "line 2: unknown button 'jump', expected one of left, down, right, up, b, a, select, start"
+                .to_string()
+        ))
+    );
+    assert_eq!(
+        InputScript::parse("at cycle 10 release a for 5 cycles"),
+        Err(VmError::Input(
+            "line 1: only a press can last for a number of cycles".to_string()
+        ))
+    );
+    assert_eq!(
+        InputScript::parse("press a"),
+        Err(VmError::Input(
+            "line 1: expected 'at cycle N press|release BUTTONS [for M cycles]', found 'press a'"
+                .to_string()
+        ))
+    );
+}
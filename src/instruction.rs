@@ -0,0 +1,173 @@
+use crate::ProgramInstruction;
+use std::fmt;
+
+const PORT_OFFSET: u8 = 240;
+
+// Mnemonics by the function number in the B field of an RSH word. Everything past RSH
+// needs the extended ALU.
+pub const ALU_FUNCTIONS: [&str; 6] = ["RSH", "OR", "XNOR", "NAND", "IMP", "NIMP"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition {
+    Zero,
+    NotZero,
+    Carry,
+    NotCarry,
+}
+
+// A machine word split into its fields, using the operand layout of the BatPU-2 ISA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    Nop,
+    Hlt,
+    Add { a: u8, b: u8, c: u8 },
+    Sub { a: u8, b: u8, c: u8 },
+    Nor { a: u8, b: u8, c: u8 },
+    And { a: u8, b: u8, c: u8 },
+    Xor { a: u8, b: u8, c: u8 },
+    Rsh { a: u8, c: u8 },
+    // Only decoded with the extended ALU, from RSH words with a function in the B field.
+    // They write `c = a op c`.
+    Or { a: u8, c: u8 },
+    Xnor { a: u8, c: u8 },
+    Nand { a: u8, c: u8 },
+    Imp { a: u8, c: u8 },
+    Nimp { a: u8, c: u8 },
+    Ldi { a: u8, immediate: u8 },
+    Adi { a: u8, immediate: u8 },
+    Jmp { address: u16 },
+    Brh { condition: Condition, address: u16 },
+    Cal { address: u16 },
+    Ret,
+    Lod { a: u8, b: u8, offset: i8 },
+    Str { a: u8, b: u8, offset: i8 },
+}
+
+impl Condition {
+    pub(crate) fn from_bits(bits: u16) -> Self {
+        match bits & 0b11 {
+            0 => Condition::Zero,
+            1 => Condition::NotZero,
+            2 => Condition::Carry,
+            _ => Condition::NotCarry,
+        }
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Condition::Zero => "zero",
+            Condition::NotZero => "notzero",
+            Condition::Carry => "carry",
+            Condition::NotCarry => "notcarry",
+        }
+    }
+}
+
+impl Instruction {
+    pub fn decode(word: ProgramInstruction) -> Self {
+        Instruction::decode_with(word, false)
+    }
+
+    // With `extended_alu`, the B field of an RSH selects the ALU function, see
+    // `ALU_FUNCTIONS`. Without it the field is ignored like on the BatPU-2.
+    pub fn decode_with(word: ProgramInstruction, extended_alu: bool) -> Self {
+        let word = u16::from(word);
+        let a = ((word >> 8) & 0xF) as u8;
+        let b = ((word >> 4) & 0xF) as u8;
+        let c = (word & 0xF) as u8;
+        let immediate = (word & 0xFF) as u8;
+        let address = word & 0x3FF;
+        // 4-bit two's complement offset, -8 to 7
+        let offset = ((c << 4) as i8) >> 4;
+        match word >> 12 {
+            0x0 => Instruction::Nop,
+            0x1 => Instruction::Hlt,
+            0x2 => Instruction::Add { a, b, c },
+            0x3 => Instruction::Sub { a, b, c },
+            0x4 => Instruction::Nor { a, b, c },
+            0x5 => Instruction::And { a, b, c },
+            0x6 => Instruction::Xor { a, b, c },
+            0x7 if extended_alu => match b {
+                1 => Instruction::Or { a, c },
+                2 => Instruction::Xnor { a, c },
+                3 => Instruction::Nand { a, c },
+                4 => Instruction::Imp { a, c },
+                5 => Instruction::Nimp { a, c },
+                _ => Instruction::Rsh { a, c },
+            },
+            0x7 => Instruction::Rsh { a, c },
+            0x8 => Instruction::Ldi { a, immediate },
+            0x9 => Instruction::Adi { a, immediate },
+            0xA => Instruction::Jmp { address },
+            0xB => Instruction::Brh {
+                condition: Condition::from_bits(word >> 10),
+                address,
+            },
+            0xC => Instruction::Cal { address },
+            0xD => Instruction::Ret,
+            0xE => Instruction::Lod { a, b, offset },
+            _ => Instruction::Str { a, b, offset },
+        }
+    }
+
+    pub fn jump_target(&self) -> Option<u16> {
+        match self {
+            Instruction::Jmp { address }
+            | Instruction::Brh { address, .. }
+            | Instruction::Cal { address } => Some(*address),
+            _ => None,
+        }
+    }
+
+    // Formats the instruction as assembly, folding encodings the assembler emits for
+    // pseudo-instructions back into their mnemonic. `label` names jump targets.
+    pub(crate) fn to_assembly(self, label: impl Fn(u16) -> String) -> String {
+        match self {
+            Instruction::Nop => "NOP".to_string(),
+            Instruction::Hlt => "HLT".to_string(),
+            Instruction::Add { a, b: 0, c } => format!("MOV r{a} r{c}"),
+            Instruction::Add { a, b, c } if a == b => format!("LSH r{a} r{c}"),
+            Instruction::Add { a, b, c } => format!("ADD r{a} r{b} r{c}"),
+            Instruction::Sub { a, b, c: 0 } => format!("CMP r{a} r{b}"),
+            Instruction::Sub { a, b, c } => format!("SUB r{a} r{b} r{c}"),
+            Instruction::Nor { a, b: 0, c } => format!("NOT r{a} r{c}"),
+            Instruction::Nor { a, b, c } => format!("NOR r{a} r{b} r{c}"),
+            Instruction::And { a, b, c } => format!("AND r{a} r{b} r{c}"),
+            Instruction::Xor { a, b, c } => format!("XOR r{a} r{b} r{c}"),
+            Instruction::Rsh { a, c } => format!("RSH r{a} r{c}"),
+            Instruction::Or { a, c } => format!("OR r{a} r{c}"),
+            Instruction::Xnor { a, c } => format!("XNOR r{a} r{c}"),
+            Instruction::Nand { a, c } => format!("NAND r{a} r{c}"),
+            Instruction::Imp { a, c } => format!("IMP r{a} r{c}"),
+            Instruction::Nimp { a, c } => format!("NIMP r{a} r{c}"),
+            Instruction::Ldi { a, immediate } => match port_name(immediate) {
+                Some(port) => format!("LDI r{a} {port}"),
+                None => format!("LDI r{a} {immediate}"),
+            },
+            Instruction::Adi { a, immediate: 1 } => format!("INC r{a}"),
+            Instruction::Adi { a, immediate: 255 } => format!("DEC r{a}"),
+            Instruction::Adi { a, immediate } => format!("ADI r{a} {immediate}"),
+            Instruction::Jmp { address } => format!("JMP {}", label(address)),
+            Instruction::Brh { condition, address } => {
+                format!("BRH {} {}", condition.mnemonic(), label(address))
+            }
+            Instruction::Cal { address } => format!("CAL {}", label(address)),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::Lod { a, b, offset: 0 } => format!("LOD r{a} r{b}"),
+            Instruction::Lod { a, b, offset } => format!("LOD r{a} r{b} {offset}"),
+            Instruction::Str { a, b, offset: 0 } => format!("STR r{a} r{b}"),
+            Instruction::Str { a, b, offset } => format!("STR r{a} r{b} {offset}"),
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_assembly(|address| address.to_string()))
+    }
+}
+
+pub(crate) fn port_name(immediate: u8) -> Option<&'static str> {
+    let idx = immediate.checked_sub(PORT_OFFSET)?;
+    crate::parser::PORTNAMES.get(idx as usize).copied()
+}
@@ -1,25 +1,34 @@
 use crate::{bits::Bits, ProgramInstruction};
 
+// 10-bit addresses
+pub(crate) const INSTRUCTION_MEMORY_SIZE: usize = 1024;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) struct InstructionMemory {
-    pub(crate) instructions: [ProgramInstruction; 1024],
+    pub(crate) instructions: [ProgramInstruction; INSTRUCTION_MEMORY_SIZE],
+    pub(crate) size: usize, // words a program may use, up to INSTRUCTION_MEMORY_SIZE
 }
 
 impl Default for InstructionMemory {
     fn default() -> Self {
-        Self {
-            instructions: [Bits::from(0u16); 1024],
-        }
+        InstructionMemory::with_size(INSTRUCTION_MEMORY_SIZE)
     }
 }
 
 impl InstructionMemory {
+    pub(crate) fn with_size(size: usize) -> Self {
+        Self {
+            instructions: [Bits::from(0u16); INSTRUCTION_MEMORY_SIZE],
+            size: size.min(INSTRUCTION_MEMORY_SIZE),
+        }
+    }
+
     pub(crate) fn load_instructions(
         &mut self,
         instructions: Vec<ProgramInstruction>,
     ) -> crate::Result<()> {
         for (i, instruction) in instructions.into_iter().enumerate() {
-            if i < self.instructions.len() {
+            if i < self.size {
                 self.instructions[i] = instruction;
             } else {
                 return Err(crate::Error::InstructionMemoryOverflow);
@@ -48,4 +57,14 @@ mod tests {
             _ => panic!("Expected InstructionMemoryOverflow error"),
         }
     }
+
+    #[test]
+    fn load_instructions_respects_size() {
+        let mut mem = InstructionMemory::with_size(4);
+        assert!(mem.load_instructions(vec![make_instruction(1); 4]).is_ok());
+        assert_eq!(
+            mem.load_instructions(vec![make_instruction(1); 5]),
+            Err(crate::Error::InstructionMemoryOverflow)
+        );
+    }
 }
@@ -0,0 +1,131 @@
+// Which device answers each IO port. The built-in devices are registered like any other,
+// they just live in `IoDevices` so that frontends can reach them directly.
+use super::{AttachedDevices, Device, IO_BASE};
+use crate::error::VmError;
+use crate::Result;
+use std::any::Any;
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinDevice {
+    Screen,
+    CharacterDisplay,
+    NumberDisplay,
+    Rng,
+    Controller,
+}
+
+impl BuiltinDevice {
+    pub const ALL: [BuiltinDevice; 5] = [
+        BuiltinDevice::Screen,
+        BuiltinDevice::CharacterDisplay,
+        BuiltinDevice::NumberDisplay,
+        BuiltinDevice::Rng,
+        BuiltinDevice::Controller,
+    ];
+
+    pub fn ports(self) -> RangeInclusive<u8> {
+        match self {
+            BuiltinDevice::Screen => 240..=246,
+            BuiltinDevice::CharacterDisplay => 247..=249,
+            BuiltinDevice::NumberDisplay => 250..=253,
+            BuiltinDevice::Rng => 254..=254,
+            BuiltinDevice::Controller => 255..=255,
+        }
+    }
+
+    fn is_attached(self, attached: &AttachedDevices) -> bool {
+        match self {
+            BuiltinDevice::Screen => attached.screen,
+            BuiltinDevice::CharacterDisplay => attached.character_display,
+            BuiltinDevice::NumberDisplay => attached.number_display,
+            BuiltinDevice::Rng => attached.rng,
+            BuiltinDevice::Controller => attached.controller,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Target {
+    Builtin(BuiltinDevice),
+    Custom(Box<dyn Device>),
+}
+
+// User devices have no notion of equality, so only their types are compared
+impl PartialEq for Target {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Target::Builtin(a), Target::Builtin(b)) => a == b,
+            (Target::Custom(a), Target::Custom(b)) => {
+                (a.as_ref() as &dyn Any).type_id() == (b.as_ref() as &dyn Any).type_id()
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Mapping {
+    pub(crate) ports: RangeInclusive<u8>,
+    pub(crate) target: Target,
+}
+
+// Later registrations shadow earlier ones on the ports they share
+#[derive(Debug, Clone, PartialEq)]
+pub struct IoBus {
+    pub(crate) mappings: Vec<Mapping>,
+}
+
+impl Default for IoBus {
+    fn default() -> Self {
+        IoBus::new(AttachedDevices::default())
+    }
+}
+
+impl IoBus {
+    pub fn new(attached: AttachedDevices) -> Self {
+        let mappings = BuiltinDevice::ALL
+            .into_iter()
+            .filter(|device| device.is_attached(&attached))
+            .map(|device| Mapping {
+                ports: device.ports(),
+                target: Target::Builtin(device),
+            })
+            .collect();
+        IoBus { mappings }
+    }
+
+    // Ports are numbered 240..=255 as on the original machine, wherever the IO window is
+    pub fn attach(&mut self, ports: RangeInclusive<u8>, device: Box<dyn Device>) -> Result<()> {
+        if ports.is_empty() || *ports.start() < IO_BASE {
+            return Err(VmError::Config(format!(
+                "device ports {}..={} are not within {IO_BASE}..=255",
+                ports.start(),
+                ports.end()
+            )));
+        }
+        self.mappings.push(Mapping {
+            ports,
+            target: Target::Custom(device),
+        });
+        Ok(())
+    }
+
+    pub(crate) fn custom_devices(&self) -> impl Iterator<Item = &dyn Device> {
+        self.mappings
+            .iter()
+            .filter_map(|mapping| match &mapping.target {
+                Target::Custom(device) => Some(device.as_ref()),
+                Target::Builtin(_) => None,
+            })
+    }
+
+    pub(crate) fn custom_devices_mut(&mut self) -> impl Iterator<Item = &mut Box<dyn Device>> {
+        self.mappings
+            .iter_mut()
+            .filter_map(|mapping| match &mut mapping.target {
+                Target::Custom(device) => Some(device),
+                Target::Builtin(_) => None,
+            })
+    }
+}
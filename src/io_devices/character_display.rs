@@ -6,7 +6,7 @@ use crate::{bits::Bits, io_devices::Device};
 
 const BUFFER_SIZE: usize = 10; // Maximum number of characters in the buffer
 
-const CHARACTERS: &str = " abcdefghijklmnopqrstuvwxyz.!?";
+pub(crate) const CHARACTERS: &str = " abcdefghijklmnopqrstuvwxyz.!?";
 
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CharacterDisplay {
@@ -47,15 +47,14 @@ impl Device for CharacterDisplay {
 
     fn on_write(&mut self, addr: crate::MemoryAddress, value: Bits<8>) {
         match addr.to_usize() {
-            247 => {
-                if self.buffer.len() < BUFFER_SIZE {
-                    if let Some(c) = CHARACTERS.chars().nth(value.to_usize()) {
-                        self.buffer.push(c);
-                    }
+            247 if self.buffer.len() < BUFFER_SIZE => {
+                if let Some(c) = CHARACTERS.chars().nth(value.to_usize()) {
+                    self.buffer.push(c);
                 }
             }
             248 => {
-                self.active.push_str(&self.buffer);
+                // the buffer replaces the line on display, as on the original hardware
+                self.active.clone_from(&self.buffer);
             }
             249 => {
                 self.buffer.clear(); // Clear character buffer
@@ -63,4 +62,8 @@ impl Device for CharacterDisplay {
             _ => {}
         }
     }
+
+    fn can_load(&self, _port: u8) -> bool {
+        false
+    }
 }
@@ -13,6 +13,52 @@ pub struct Controller {
     pub value: Bits<8>,
 }
 
+// In the order of their bits in the controller byte
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub enum Button {
+    Left,
+    Down,
+    Right,
+    Up,
+    B,
+    A,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Left,
+        Button::Down,
+        Button::Right,
+        Button::Up,
+        Button::B,
+        Button::A,
+        Button::Select,
+        Button::Start,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Left => "left",
+            Button::Down => "down",
+            Button::Right => "right",
+            Button::Up => "up",
+            Button::B => "b",
+            Button::A => "a",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+
+    // case insensitive
+    pub fn from_name(name: &str) -> Option<Button> {
+        Button::ALL
+            .into_iter()
+            .find(|button| button.name().eq_ignore_ascii_case(name))
+    }
+}
+
 impl Device for Controller {
     fn on_read(&mut self, _address: MemoryAddress) -> Bits<8> {
         self.value
@@ -21,6 +67,10 @@ impl Device for Controller {
     fn on_write(&mut self, _address: MemoryAddress, _value: Bits<8>) {
         // Controller is read-only; ignore writes
     }
+
+    fn can_store(&self, _port: u8) -> bool {
+        false
+    }
 }
 
 impl Controller {
@@ -28,6 +78,32 @@ impl Controller {
         Self::default()
     }
 
+    pub fn set(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Left => self.set_left(pressed),
+            Button::Down => self.set_down(pressed),
+            Button::Right => self.set_right(pressed),
+            Button::Up => self.set_up(pressed),
+            Button::B => self.set_b(pressed),
+            Button::A => self.set_a(pressed),
+            Button::Select => self.set_select(pressed),
+            Button::Start => self.set_start(pressed),
+        }
+    }
+
+    pub fn is_pressed(&self, button: Button) -> bool {
+        match button {
+            Button::Left => self.left,
+            Button::Down => self.down,
+            Button::Right => self.right,
+            Button::Up => self.up,
+            Button::B => self.b,
+            Button::A => self.a,
+            Button::Select => self.select,
+            Button::Start => self.start,
+        }
+    }
+
     pub fn set_a(&mut self, pressed: bool) {
         self.a = pressed;
         if pressed {
@@ -0,0 +1,152 @@
+// A 5x7 bitmap font for the character display's character set, plus the digits and minus
+// sign the number display needs. Letters are drawn in upper case, like on the original
+// display.
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+
+pub type Glyph = [u8; GLYPH_HEIGHT];
+
+// Rows from top to bottom, the leftmost pixel in bit 4. Case insensitive.
+pub fn glyph(c: char) -> Option<Glyph> {
+    Some(match c.to_ascii_lowercase() {
+        ' ' => [0; GLYPH_HEIGHT],
+        'a' => rows([
+            "01110", "10001", "10001", "11111", "10001", "10001", "10001",
+        ]),
+        'b' => rows([
+            "11110", "10001", "10001", "11110", "10001", "10001", "11110",
+        ]),
+        'c' => rows([
+            "01110", "10001", "10000", "10000", "10000", "10001", "01110",
+        ]),
+        'd' => rows([
+            "11100", "10010", "10001", "10001", "10001", "10010", "11100",
+        ]),
+        'e' => rows([
+            "11111", "10000", "10000", "11110", "10000", "10000", "11111",
+        ]),
+        'f' => rows([
+            "11111", "10000", "10000", "11110", "10000", "10000", "10000",
+        ]),
+        'g' => rows([
+            "01110", "10001", "10000", "10111", "10001", "10001", "01111",
+        ]),
+        'h' => rows([
+            "10001", "10001", "10001", "11111", "10001", "10001", "10001",
+        ]),
+        'i' => rows([
+            "01110", "00100", "00100", "00100", "00100", "00100", "01110",
+        ]),
+        'j' => rows([
+            "00111", "00010", "00010", "00010", "00010", "10010", "01100",
+        ]),
+        'k' => rows([
+            "10001", "10010", "10100", "11000", "10100", "10010", "10001",
+        ]),
+        'l' => rows([
+            "10000", "10000", "10000", "10000", "10000", "10000", "11111",
+        ]),
+        'm' => rows([
+            "10001", "11011", "10101", "10101", "10001", "10001", "10001",
+        ]),
+        'n' => rows([
+            "10001", "10001", "11001", "10101", "10011", "10001", "10001",
+        ]),
+        'o' => rows([
+            "01110", "10001", "10001", "10001", "10001", "10001", "01110",
+        ]),
+        'p' => rows([
+            "11110", "10001", "10001", "11110", "10000", "10000", "10000",
+        ]),
+        'q' => rows([
+            "01110", "10001", "10001", "10001", "10101", "10010", "01101",
+        ]),
+        'r' => rows([
+            "11110", "10001", "10001", "11110", "10100", "10010", "10001",
+        ]),
+        's' => rows([
+            "01111", "10000", "10000", "01110", "00001", "00001", "11110",
+        ]),
+        't' => rows([
+            "11111", "00100", "00100", "00100", "00100", "00100", "00100",
+        ]),
+        'u' => rows([
+            "10001", "10001", "10001", "10001", "10001", "10001", "01110",
+        ]),
+        'v' => rows([
+            "10001", "10001", "10001", "10001", "10001", "01010", "00100",
+        ]),
+        'w' => rows([
+            "10001", "10001", "10001", "10101", "10101", "10101", "01010",
+        ]),
+        'x' => rows([
+            "10001", "10001", "01010", "00100", "01010", "10001", "10001",
+        ]),
+        'y' => rows([
+            "10001", "10001", "10001", "01010", "00100", "00100", "00100",
+        ]),
+        'z' => rows([
+            "11111", "00001", "00010", "00100", "01000", "10000", "11111",
+        ]),
+        '.' => rows([
+            "00000", "00000", "00000", "00000", "00000", "01100", "01100",
+        ]),
+        '!' => rows([
+            "00100", "00100", "00100", "00100", "00100", "00000", "00100",
+        ]),
+        '?' => rows([
+            "01110", "10001", "00001", "00010", "00100", "00000", "00100",
+        ]),
+        '-' => rows([
+            "00000", "00000", "00000", "11111", "00000", "00000", "00000",
+        ]),
+        '0' => rows([
+            "01110", "10001", "10011", "10101", "11001", "10001", "01110",
+        ]),
+        '1' => rows([
+            "00100", "01100", "00100", "00100", "00100", "00100", "01110",
+        ]),
+        '2' => rows([
+            "01110", "10001", "00001", "00010", "00100", "01000", "11111",
+        ]),
+        '3' => rows([
+            "11111", "00010", "00100", "00010", "00001", "10001", "01110",
+        ]),
+        '4' => rows([
+            "00010", "00110", "01010", "10010", "11111", "00010", "00010",
+        ]),
+        '5' => rows([
+            "11111", "10000", "11110", "00001", "00001", "10001", "01110",
+        ]),
+        '6' => rows([
+            "00110", "01000", "10000", "11110", "10001", "10001", "01110",
+        ]),
+        '7' => rows([
+            "11111", "00001", "00010", "00100", "01000", "01000", "01000",
+        ]),
+        '8' => rows([
+            "01110", "10001", "10001", "01110", "10001", "10001", "01110",
+        ]),
+        '9' => rows([
+            "01110", "10001", "10001", "01111", "00001", "00010", "01100",
+        ]),
+        _ => return None,
+    })
+}
+
+fn rows(rows: [&str; GLYPH_HEIGHT]) -> Glyph {
+    rows.map(|row| u8::from_str_radix(row, 2).unwrap_or_default())
+}
+
+// Lit pixels of `text` as (x, y), y growing downwards, with one column between glyphs.
+// Characters without a glyph are left blank.
+pub fn pixels(text: &str) -> impl Iterator<Item = (usize, usize)> + '_ {
+    text.chars().enumerate().flat_map(|(index, c)| {
+        let glyph = glyph(c).unwrap_or_default();
+        (0..GLYPH_HEIGHT).flat_map(move |y| {
+            (0..GLYPH_WIDTH)
+                .filter(move |x| glyph[y] >> (GLYPH_WIDTH - 1 - x) & 1 == 1)
+                .map(move |x| (index * (GLYPH_WIDTH + 1) + x, y))
+        })
+    })
+}
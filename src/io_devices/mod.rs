@@ -1,43 +1,163 @@
-use crate::{bits::Bits, MemoryAddress};
+use crate::bits::Bits;
+use crate::Result;
+use bus::{BuiltinDevice, IoBus, Target};
+use std::any::Any;
+use std::ops::RangeInclusive;
 
+pub mod bus;
 pub mod character_display;
 pub mod controller;
+pub mod font;
 pub mod number_display;
 pub mod rng;
 pub mod screen;
 
-#[derive(Debug, Clone, Eq, PartialEq, Default)]
+// Devices see the ports at their original addresses, wherever the IO window is mapped
+pub(crate) const IO_BASE: u8 = 240;
+pub(crate) const IO_PORTS: u8 = 16;
+
+// Which built-in devices are wired to their ports; a missing device reads 0 and ignores
+// writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttachedDevices {
+    pub screen: bool,
+    pub character_display: bool,
+    pub number_display: bool,
+    pub rng: bool,
+    pub controller: bool,
+}
+
+impl Default for AttachedDevices {
+    fn default() -> Self {
+        AttachedDevices {
+            screen: true,
+            character_display: true,
+            number_display: true,
+            rng: true,
+            controller: true,
+        }
+    }
+}
+
+impl AttachedDevices {
+    pub fn none() -> Self {
+        AttachedDevices {
+            screen: false,
+            character_display: false,
+            number_display: false,
+            rng: false,
+            controller: false,
+        }
+    }
+}
+
+// A memory-mapped device. `addr` is the port being accessed, numbered 240..=255.
+pub trait Device: Any + std::fmt::Debug + DeviceClone {
+    fn on_read(&mut self, addr: Bits<8>) -> Bits<8>;
+    fn on_write(&mut self, addr: Bits<8>, value: Bits<8>);
+
+    // Loading from a port that cannot be loaded, or storing to one that cannot be stored
+    // to, is a fault
+    fn can_load(&self, _port: u8) -> bool {
+        true
+    }
+
+    fn can_store(&self, _port: u8) -> bool {
+        true
+    }
+
+    // Called after every executed instruction, e.g. for timers. `VM::step_back` only
+    // undoes it for instructions that accessed an IO port.
+    fn tick(&mut self) {}
+}
+
+// Lets the IO devices, and with them the undo history, be cloned
+pub trait DeviceClone {
+    fn clone_box(&self) -> Box<dyn Device>;
+}
+
+impl<T: Device + Clone> DeviceClone for T {
+    fn clone_box(&self) -> Box<dyn Device> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn Device> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct IoDevices {
     pub character_display: character_display::CharacterDisplay,
     pub number_display: number_display::NumberDisplay,
     pub rng: rng::RNG,
     pub screen: screen::Screen,
     pub controller: controller::Controller,
+    pub bus: IoBus,
 }
 
-impl Device for IoDevices {
-    fn on_read(&mut self, addr: MemoryAddress) -> Bits<8> {
-        match addr.to_usize() {
-            244 => self.screen.on_read(addr),     // Load Pixel
-            254 => self.rng.on_read(addr),        // RNG operations
-            255 => self.controller.on_read(addr), // Read Controller
-            _ => Bits::default(),                 // Default for other addresses
-        }
+impl IoDevices {
+    pub fn attach(&mut self, ports: RangeInclusive<u8>, device: impl Device) -> Result<()> {
+        self.bus.attach(ports, Box::new(device))
     }
 
-    fn on_write(&mut self, addr: MemoryAddress, value: Bits<8>) {
-        match addr.to_usize() {
-            240..=246 => self.screen.on_write(addr, value), // Screen operations
-            247..=249 => self.character_display.on_write(addr, value), // Character Display operations
-            250..=253 => self.number_display.on_write(addr, value),    // Number Display operations
-            _ => {} // No operation for other addresses
-        }
+    // The first attached device of type `T`
+    pub fn device<T: Device>(&self) -> Option<&T> {
+        self.bus
+            .custom_devices()
+            .find_map(|device| (device as &dyn Any).downcast_ref())
     }
-}
 
-pub(crate) trait Device {
-    fn on_read(&mut self, addr: MemoryAddress) -> Bits<8>;
-    fn on_write(&mut self, addr: MemoryAddress, value: Bits<8>);
+    pub fn device_mut<T: Device>(&mut self) -> Option<&mut T> {
+        self.bus
+            .custom_devices_mut()
+            .find_map(|device| (device.as_mut() as &mut dyn Any).downcast_mut())
+    }
+
+    fn with_device<R>(&mut self, port: u8, f: impl FnOnce(&mut dyn Device) -> R) -> Option<R> {
+        let index = self
+            .bus
+            .mappings
+            .iter()
+            .rposition(|mapping| mapping.ports.contains(&port))?;
+        let device: &mut dyn Device = match &mut self.bus.mappings[index].target {
+            Target::Builtin(BuiltinDevice::Screen) => &mut self.screen,
+            Target::Builtin(BuiltinDevice::CharacterDisplay) => &mut self.character_display,
+            Target::Builtin(BuiltinDevice::NumberDisplay) => &mut self.number_display,
+            Target::Builtin(BuiltinDevice::Rng) => &mut self.rng,
+            Target::Builtin(BuiltinDevice::Controller) => &mut self.controller,
+            Target::Custom(device) => device.as_mut(),
+        };
+        Some(f(device))
+    }
+
+    // Ports nothing is attached to read 0, ignore writes and never fault
+    pub(crate) fn on_read(&mut self, port: u8) -> Bits<8> {
+        self.with_device(port, |device| device.on_read(Bits::from(port)))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn on_write(&mut self, port: u8, value: Bits<8>) {
+        self.with_device(port, |device| device.on_write(Bits::from(port), value));
+    }
+
+    pub(crate) fn can_load(&mut self, port: u8) -> bool {
+        self.with_device(port, |device| device.can_load(port))
+            .unwrap_or(true)
+    }
+
+    pub(crate) fn can_store(&mut self, port: u8) -> bool {
+        self.with_device(port, |device| device.can_store(port))
+            .unwrap_or(true)
+    }
+
+    pub(crate) fn tick(&mut self) {
+        for device in self.bus.custom_devices_mut() {
+            device.tick();
+        }
+    }
 }
 
 #[cfg(test)]
@@ -101,5 +221,26 @@ mod test {
 
         display.on_write(MemoryAddress::from(249u8), Bits::default());
         assert!(display.buffer.is_empty());
+
+        // a second push replaces the line instead of appending to it
+        display.on_write(MemoryAddress::from(247u8), Bits::from(2u8)); // 'b'
+        display.on_write(MemoryAddress::from(248u8), Bits::default());
+        assert_eq!(display.active, "b");
+    }
+
+    #[test]
+    fn font_covers_the_displays() {
+        let characters = super::character_display::CHARACTERS;
+        for c in characters.chars().chain("0123456789-".chars()) {
+            assert!(super::font::glyph(c).is_some(), "no glyph for {c:?}");
+        }
+        assert_eq!(super::font::glyph('A'), super::font::glyph('a'));
+        assert_eq!(super::font::glyph('#'), None);
+
+        // '!' is one column wide, in the middle of the cell, with a gap above the dot
+        let pixels: Vec<_> = super::font::pixels(" !").collect();
+        assert_eq!(pixels.len(), 6);
+        assert!(pixels.iter().all(|&(x, _)| x == 8));
+        assert!(!pixels.contains(&(8, 5)));
     }
 }
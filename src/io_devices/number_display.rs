@@ -66,4 +66,8 @@ impl Device for NumberDisplay {
             _ => {}
         }
     }
+
+    fn can_load(&self, _port: u8) -> bool {
+        false
+    }
 }
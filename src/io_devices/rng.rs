@@ -1,29 +1,97 @@
 use crate::{bits::Bits, io_devices::Device, MemoryAddress};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Where the values read from the RNG port come from
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum RngSource {
+    // The 8-bit linear feedback shift register of the original hardware
+    #[default]
+    Lfsr,
+    // Replays these values in order, starting over after the last one
+    Sequence(Vec<u8>),
+    // The host's standard PRNG
+    Host,
+}
+
+// The host PRNG starts over from a new seed every this many draws, so that restoring it
+// never replays more than one block
+const HOST_BLOCK: u64 = 1 << 16;
 
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct RNG {
-    seed: Bits<8>,
-    state: Bits<8>,
+    pub(crate) seed: Bits<8>,
+    pub(crate) state: Bits<8>,
+    pub(crate) source: RngSource,
+    pub(crate) host_seed: u64,
+    pub(crate) draws: u64, // values generated so far
+    host: StdRng,
 }
 
 impl Default for RNG {
     fn default() -> Self {
-        let mut seed = Bits::from(rand::random::<u8>());
-        while seed == Bits::from(0u8) {
-            seed = Bits::from(rand::random::<u8>());
-        }
-        RNG { seed, state: seed }
+        RNG::with_seed(rand::random(), RngSource::Lfsr)
     }
 }
 
 impl RNG {
     pub fn new(seed: Bits<8>) -> Self {
         assert!(seed > Bits::from(0u8), "Seed must be greater than 0");
-        Self { seed, state: seed }
+        RNG {
+            seed,
+            state: seed,
+            ..RNG::with_seed(u8::from(seed) as u64, RngSource::Lfsr)
+        }
+    }
+
+    // The LFSR takes the low byte of the seed, where zero (which it would never leave)
+    // becomes one. The host PRNG uses all of it.
+    pub fn with_seed(seed: u64, source: RngSource) -> Self {
+        let lfsr_seed = Bits::from((seed as u8).max(1));
+        RNG {
+            seed: lfsr_seed,
+            state: lfsr_seed,
+            source,
+            host_seed: seed,
+            draws: 0,
+            host: host_block(seed, 0),
+        }
+    }
+
+    pub fn source(&self) -> &RngSource {
+        &self.source
+    }
+
+    // Rebuilds the host PRNG from its seed and the number of values drawn so far
+    pub(crate) fn restore_host(&mut self) {
+        self.host = host_block(self.host_seed, self.draws / HOST_BLOCK);
+        if self.source == RngSource::Host {
+            for _ in 0..self.draws % HOST_BLOCK {
+                self.host.random::<u8>();
+            }
+        }
     }
 
     pub fn generate_next(&mut self) -> Bits<8> {
+        let position = self.draws;
+        self.draws = self.draws.wrapping_add(1);
+        match &self.source {
+            RngSource::Lfsr => self.next_lfsr(),
+            RngSource::Sequence(values) if values.is_empty() => Bits::default(),
+            RngSource::Sequence(values) => {
+                Bits::from(values[(position % values.len() as u64) as usize])
+            }
+            RngSource::Host => {
+                if position > 0 && position.is_multiple_of(HOST_BLOCK) {
+                    self.host = host_block(self.host_seed, position / HOST_BLOCK);
+                }
+                Bits::from(self.host.random::<u8>())
+            }
+        }
+    }
+
+    fn next_lfsr(&mut self) -> Bits<8> {
         // Linear shift generator algorithm
         let mut lfsr = self.state;
         let bit = ((lfsr >> Bits::from(7u8))
@@ -37,10 +105,19 @@ impl RNG {
     }
 }
 
+// The first block uses the seed itself
+fn host_block(seed: u64, block: u64) -> StdRng {
+    StdRng::seed_from_u64(seed ^ block.wrapping_mul(0x9E37_79B9_7F4A_7C15))
+}
+
 impl Device for RNG {
     fn on_read(&mut self, _addr: MemoryAddress) -> Bits<8> {
         self.generate_next()
     }
 
     fn on_write(&mut self, _addr: MemoryAddress, _value: Bits<8>) {} // RNG does not support writing
+
+    fn can_store(&self, _port: u8) -> bool {
+        false
+    }
 }
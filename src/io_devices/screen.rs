@@ -9,36 +9,57 @@
 
 use crate::{bits::Bits, io_devices::Device};
 
-#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+// 5-bit coordinates
+pub(crate) const SCREEN_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub struct Screen {
     pub current_x: usize,
     pub current_y: usize,
     pub buffer: [[bool; 32]; 32],
     pub active: [[bool; 32]; 32],
+    // pixels outside width x height do not exist: they are never drawn and load as 0
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen::new()
+    }
 }
 
 impl Screen {
     pub fn new() -> Self {
+        Screen::with_size(SCREEN_SIZE, SCREEN_SIZE)
+    }
+
+    pub fn with_size(width: usize, height: usize) -> Self {
         Screen {
             buffer: [[false; 32]; 32],
             active: [[false; 32]; 32],
             current_x: 0,
             current_y: 0,
+            width: width.min(SCREEN_SIZE),
+            height: height.min(SCREEN_SIZE),
         }
     }
+
+    fn on_screen(&self) -> bool {
+        self.current_x < self.width && self.current_y < self.height
+    }
 }
 
 impl Device for Screen {
     fn on_read(&mut self, addr: crate::MemoryAddress) -> Bits<8> {
-        assert_eq!(
-            addr.to_usize(),
-            244,
-            "Load Pixel should only be called with address 244"
-        );
+        // the other ports are store only and read as 0 when a fault is emulated
+        if addr.to_usize() != 244 {
+            return Bits::from(0u8);
+        }
         // Return the pixel value at the current coordinates
         let x = self.current_x & 0x1F; // Ensure X is within bounds
         let y = self.current_y & 0x1F; // Ensure Y is within bounds
-        Bits::from(self.active[y][x] as u8) // Convert bool to Bits<8>
+        Bits::from((self.on_screen() && self.active[y][x]) as u8) // Convert bool to Bits<8>
     }
 
     fn on_write(&mut self, addr: crate::MemoryAddress, value: Bits<8>) {
@@ -48,61 +69,58 @@ impl Device for Screen {
             match addr.to_usize() {
                 240 => self.current_x = val, // set Pixel X
                 241 => self.current_y = val, // set Pixel Y
-                242 => {
+                242 if self.on_screen() => {
                     self.buffer[self.current_y][self.current_x] = true;
                     // self.display_buffer();
                 } // Draw pixel
-                243 => self.buffer[self.current_y][self.current_x] = false, // Clear pixel
+                243 if self.on_screen() => self.buffer[self.current_y][self.current_x] = false, // Clear pixel
                 245 => self.active = self.buffer, // Buffer screen
                 246 => self.buffer = [[false; 32]; 32], // Clear screen buffer
                 _ => {}
             }
         }
     }
+
+    // only Load Pixel can be read
+    fn can_load(&self, port: u8) -> bool {
+        port == 244
+    }
+
+    fn can_store(&self, port: u8) -> bool {
+        port != 244
+    }
 }
 
 impl Screen {
     pub fn display(&self) {
-        // Top border
-        print!("+");
-        for _ in 0..32 {
-            print!("-");
-        }
-        println!("+");
-        for row in self.active.iter().rev() {
-            print!("|");
-            for &pixel in row {
-                print!("{}", if pixel { "█" } else { " " });
-            }
-            println!("|");
-        }
-        // Bottom border
-        print!("+");
-        for _ in 0..32 {
-            print!("-");
-        }
-        println!("+");
+        print!("{}", self.render());
     }
 
     pub fn display_buffer(&self) {
-        // Top border
-        print!("+");
-        for _ in 0..32 {
-            print!("-");
-        }
-        println!("+");
-        for row in self.buffer.iter().rev() {
-            print!("|");
-            for &pixel in row {
-                print!("{}", if pixel { "█" } else { " " });
-            }
-            println!("|");
-        }
-        // Bottom border
-        print!("+");
-        for _ in 0..32 {
-            print!("-");
+        print!("{}", render_area(&self.buffer, self.width, self.height));
+    }
+
+    // The visible screen as text, top row first, framed by a border
+    pub fn render(&self) -> String {
+        render_area(&self.active, self.width, self.height)
+    }
+}
+
+pub(crate) fn render(pixels: &[[bool; 32]; 32]) -> String {
+    render_area(pixels, SCREEN_SIZE, SCREEN_SIZE)
+}
+
+// Only the bottom left `width` x `height` pixels
+fn render_area(pixels: &[[bool; 32]; 32], width: usize, height: usize) -> String {
+    let border = format!("+{}+\n", "-".repeat(width));
+    let mut out = border.clone();
+    for row in pixels[..height].iter().rev() {
+        out.push('|');
+        for &pixel in &row[..width] {
+            out.push(if pixel { '█' } else { ' ' });
         }
-        println!("+");
+        out.push_str("|\n");
     }
+    out.push_str(&border);
+    out
 }
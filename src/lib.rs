@@ -3,17 +3,25 @@
 
 mod alu;
 pub mod bits;
-mod control_rom;
+pub mod control_rom;
+pub mod debugger;
+pub mod disassembler;
 mod error;
+pub mod input;
+pub mod instruction;
 mod instruction_memory;
 pub mod io_devices;
+pub mod lockstep;
 mod parser;
 mod program_counter;
 pub mod registers;
+pub mod snapshot;
+pub mod spec;
+pub mod trace;
 mod vm;
 
-pub(crate) type ProgramInstruction = Bits<16>;
-pub(crate) type Program = Vec<ProgramInstruction>;
+pub type ProgramInstruction = Bits<16>;
+pub type Program = Vec<ProgramInstruction>;
 pub(crate) type OpCode = Bits<4>;
 pub(crate) type Immediate = Bits<8>;
 pub(crate) type Address = Bits<10>;
@@ -23,9 +31,26 @@ pub const OPCODE_HLT: Bits<4> = Bits {
     bit_array: [true, false, false, false],
 };
 
+pub use crate::alu::alu_flags::AluFlags;
 pub use crate::bits::Bits;
 pub use crate::bits::BitsParseError;
+pub use crate::control_rom::ControlRom;
+pub use crate::debugger::Debugger;
+pub use crate::disassembler::{disassemble, disassemble_with, parse_machine_code};
+pub use crate::error::{Fault, RuntimeError};
+pub use crate::instruction::Instruction;
+pub use crate::io_devices::rng::RngSource;
+pub use crate::io_devices::{AttachedDevices, Device};
+pub use crate::parser::diagnostic::{Diagnostic, Severity, SourceLocation};
 pub use crate::parser::error::ParserError;
+pub use crate::parser::{assemble, assemble_with, assemble_with_diagnostics, Assembly};
+pub use crate::spec::{parse_number, Spec, SpecReport};
+pub use crate::trace::Tracer;
+pub use crate::vm::builder::VmBuilder;
+pub use crate::vm::config::{FaultPolicy, VmConfig};
+pub use crate::vm::engine::{Engine, MachineState};
+pub use crate::vm::fast::FastVm;
+pub use crate::vm::observer::{Access, Control, Observer, RunOutcome, StepEvent};
 pub use crate::vm::VM;
 
 type Error = crate::error::VmError;
@@ -0,0 +1,172 @@
+// Runs two engines side by side on the same program and controller input, comparing
+// everything a program can observe after every instruction. The first divergence is
+// reported together with the instructions that led up to it:
+//
+//     let mut vm = VM::builder().rng_seed(1).build()?;
+//     vm.load_program("programs/tetris.as")?;
+//     let fast = FastVm::from_vm(&vm);
+//     LockStep::new(vm, fast).run(100_000)?;
+use crate::input::InputScript;
+use crate::instruction::Instruction;
+use crate::vm::engine::Engine;
+use crate::ProgramInstruction;
+use std::collections::VecDeque;
+use std::fmt;
+
+pub const DEFAULT_WINDOW: usize = 16;
+
+// Why both engines stopped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Halted,
+    Faulted(String), // both failed with this error
+    StepLimit,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceLine {
+    pub step: u64,
+    pub pc: u16,
+    pub word: ProgramInstruction,
+    pub instruction: Instruction, // as the first engine decodes it
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub step: u64, // instructions both engines had run, the last one included
+    pub differences: Vec<String>,
+    pub window: Vec<TraceLine>, // oldest first, ending with the instruction that diverged
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.step {
+            0 => writeln!(f, "the engines differ before the first instruction:")?,
+            step => writeln!(f, "the engines diverged on instruction {step}:")?,
+        }
+        for difference in &self.differences {
+            writeln!(f, "  {difference}")?;
+        }
+        if !self.window.is_empty() {
+            writeln!(f, "after:")?;
+        }
+        for line in &self.window {
+            writeln!(
+                f,
+                "  {:>8}  {:>4}  {}",
+                line.step, line.pc, line.instruction
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+pub struct LockStep<A: Engine, B: Engine> {
+    pub a: A,
+    pub b: B,
+    inputs: [InputScript; 2],
+    window: VecDeque<TraceLine>,
+    window_size: usize,
+    steps: u64,
+}
+
+impl<A: Engine, B: Engine> LockStep<A, B> {
+    pub fn new(a: A, b: B) -> Self {
+        LockStep {
+            a,
+            b,
+            inputs: Default::default(),
+            window: VecDeque::new(),
+            window_size: DEFAULT_WINDOW,
+            steps: 0,
+        }
+    }
+
+    // Both controllers follow the script
+    pub fn with_inputs(mut self, inputs: InputScript) -> Self {
+        self.inputs = [inputs.clone(), inputs];
+        self
+    }
+
+    pub fn with_window(mut self, size: usize) -> Self {
+        self.window_size = size;
+        self
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    // Runs until both engines halt or fail the same way, or for `max_steps` instructions
+    pub fn run(&mut self, max_steps: u64) -> Result<Outcome, Divergence> {
+        if self.steps == 0 {
+            self.compare()?;
+        }
+        for _ in 0..max_steps {
+            if let Some(outcome) = self.step()? {
+                return Ok(outcome);
+            }
+        }
+        Ok(Outcome::StepLimit)
+    }
+
+    // Runs one instruction on both engines, returning why they stopped if they did
+    pub fn step(&mut self) -> Result<Option<Outcome>, Divergence> {
+        let pc = self.a.pc();
+        let word = self.a.instruction_at(pc as usize).unwrap_or_default();
+        self.inputs[0].advance(self.steps, &mut self.a.io_devices_mut().controller);
+        self.inputs[1].advance(self.steps, &mut self.b.io_devices_mut().controller);
+        self.steps += 1;
+        if self.window.len() == self.window_size {
+            self.window.pop_front();
+        }
+        if self.window_size > 0 {
+            self.window.push_back(TraceLine {
+                step: self.steps,
+                pc,
+                word,
+                instruction: Instruction::decode_with(word, self.a.config().extended_alu),
+            });
+        }
+
+        let (a, b) = (self.a.step(), self.b.step());
+        if a != b {
+            let describe = |result: &crate::Result<_>| match result {
+                Ok(opcode) => format!("ran opcode {opcode}"),
+                Err(error) => format!("failed with '{error}'"),
+            };
+            return Err(self.divergence(vec![format!(
+                "step: {} vs {}",
+                describe(&a),
+                describe(&b)
+            )]));
+        }
+        self.compare()?;
+        Ok(match a {
+            Ok(opcode) if opcode == crate::OPCODE_HLT => Some(Outcome::Halted),
+            Ok(_) => None,
+            Err(error) => Some(Outcome::Faulted(error.to_string())),
+        })
+    }
+
+    fn compare(&self) -> Result<(), Divergence> {
+        let (a, b) = (self.a.state(), self.b.state());
+        if a == b {
+            return Ok(());
+        }
+        Err(self.divergence(a.differences(&b)))
+    }
+
+    fn divergence(&self, differences: Vec<String>) -> Divergence {
+        Divergence {
+            step: self.steps,
+            differences,
+            window: self.window.iter().copied().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
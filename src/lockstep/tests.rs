@@ -0,0 +1,179 @@
+use super::{LockStep, Outcome};
+use crate::input::InputScript;
+use crate::io_devices::IoDevices;
+use crate::vm::engine::{Engine, MachineState};
+use crate::{FastVm, OpCode, ProgramInstruction, Result, VmConfig, VM};
+use std::path::Path;
+
+const COUNTDOWN: &str = "\
+LDI r1 3
+LDI r15 255
+.loop
+LOD r15 r2 0
+STR r0 r2 0
+DEC r1
+BRH notzero .loop
+HLT";
+
+fn machines(source: &str) -> (VM, FastVm) {
+    let mut vm = VM::builder().rng_seed(5).build().unwrap();
+    vm.load_source(source).unwrap();
+    let fast = FastVm::from_vm(&vm);
+    (vm, fast)
+}
+
+#[test]
+fn engines_agree_on_a_program_with_input() {
+    let (vm, fast) = machines(COUNTDOWN);
+    let inputs = InputScript::parse("at cycle 4 press a+left").unwrap();
+    let mut lock_step = LockStep::new(vm, fast).with_inputs(inputs);
+    assert_eq!(lock_step.run(100), Ok(Outcome::Halted));
+    assert_eq!(lock_step.steps(), 15);
+    assert_eq!(u8::from(lock_step.a.data_memory.memory[0]), 0b10_0001);
+    assert_eq!(lock_step.b.memory()[0], 0b10_0001);
+
+    let (vm, fast) = machines(COUNTDOWN);
+    assert_eq!(LockStep::new(vm, fast).run(5), Ok(Outcome::StepLimit));
+}
+
+#[test]
+fn engines_that_fault_alike_agree() {
+    let mut vm = VM::builder()
+        .fault_policy(crate::FaultPolicy::Trap)
+        .build()
+        .unwrap();
+    vm.load_source("RET").unwrap();
+    let fast = FastVm::from_vm(&vm);
+    assert_eq!(
+        LockStep::new(vm, fast).run(10),
+        Ok(Outcome::Faulted(
+            "Runtime error: call stack underflow at pc 0".to_string()
+        ))
+    );
+}
+
+#[test]
+fn reports_differences_before_the_first_instruction() {
+    let (vm, mut fast) = machines(COUNTDOWN);
+    fast.set_register(4, 9);
+    let divergence = LockStep::new(vm, fast).run(100).unwrap_err();
+    assert_eq!(divergence.step, 0);
+    assert_eq!(
+        divergence.differences,
+        vec![
+            "r4 (register bank 0): 0 vs 9".to_string(),
+            "r4 (register bank 1): 0 vs 9".to_string()
+        ]
+    );
+    assert!(divergence.window.is_empty());
+}
+
+// Every LOD loads one more than it should
+#[derive(Debug)]
+struct OffByOne(FastVm);
+
+impl Engine for OffByOne {
+    fn step(&mut self) -> Result<OpCode> {
+        let word = u16::from(self.0.instruction_at(self.0.pc() as usize).unwrap());
+        let opcode = self.0.step()?;
+        if word >> 12 == 0xE {
+            let register = (word >> 4 & 0xF) as u8;
+            self.0
+                .set_register(register, self.0.register(register).wrapping_add(1));
+        }
+        Ok(opcode)
+    }
+
+    fn io_devices(&self) -> &IoDevices {
+        &self.0.io_devices
+    }
+
+    fn io_devices_mut(&mut self) -> &mut IoDevices {
+        &mut self.0.io_devices
+    }
+
+    fn save_state(&self, file_path: &Path) -> Result<()> {
+        self.0.save_state(file_path)
+    }
+
+    fn load_state(&mut self, file_path: &Path) -> Result<()> {
+        self.0.load_state(file_path)
+    }
+
+    fn config(&self) -> &VmConfig {
+        self.0.config()
+    }
+
+    fn pc(&self) -> u16 {
+        self.0.pc()
+    }
+
+    fn instruction_at(&self, address: usize) -> Option<ProgramInstruction> {
+        self.0.instruction_at(address)
+    }
+
+    fn state(&self) -> MachineState {
+        self.0.state()
+    }
+}
+
+#[test]
+fn reports_the_first_divergence_with_a_trace_window() {
+    let (vm, fast) = machines(COUNTDOWN);
+    let divergence = LockStep::new(vm, OffByOne(fast))
+        .with_window(2)
+        .run(100)
+        .unwrap_err();
+    assert_eq!(divergence.step, 3);
+    assert_eq!(
+        divergence.differences,
+        vec![
+            "r2 (register bank 0): 0 vs 1".to_string(),
+            "r2 (register bank 1): 0 vs 1".to_string()
+        ]
+    );
+    assert_eq!(
+        divergence.to_string(),
+        "\
+the engines diverged on instruction 3:
+  r2 (register bank 0): 0 vs 1
+  r2 (register bank 1): 0 vs 1
+after:
+         2     1  LDI r15 controller_input
+         3     2  LOD r15 r2
+"
+    );
+}
+
+// remembers the last value stored to it
+#[derive(Debug, Clone, Default)]
+struct Latch(u8);
+
+impl crate::Device for Latch {
+    fn on_read(&mut self, _addr: crate::Bits<8>) -> crate::Bits<8> {
+        crate::Bits::from(self.0)
+    }
+
+    fn on_write(&mut self, _addr: crate::Bits<8>, value: crate::Bits<8>) {
+        self.0 = value.into();
+    }
+}
+
+#[test]
+fn reports_differences_inside_attached_devices() {
+    let mut vm = VM::builder()
+        .device(248..=248, Latch::default())
+        .build()
+        .unwrap();
+    vm.load_source("LDI r15 248\nLDI r1 7\nSTR r15 r1\nHLT")
+        .unwrap();
+    let fast = FastVm::from_vm(&vm);
+    let mut lock_step = LockStep::new(vm, fast);
+    assert_eq!(lock_step.run(3), Ok(Outcome::StepLimit));
+    lock_step.b.io_devices.device_mut::<Latch>().unwrap().0 = 1;
+    let divergence = lock_step.run(10).unwrap_err();
+    assert_eq!(divergence.step, 4);
+    assert_eq!(divergence.differences.len(), 1);
+    assert!(divergence.differences[0].starts_with("attached devices: "));
+    assert!(divergence.differences[0].contains("Latch(7)"));
+}
@@ -0,0 +1,66 @@
+use crate::parser::error::ParserError;
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub file: String,
+    pub line: usize,           // 1-based line number
+    pub columns: Range<usize>, // 1-based, end exclusive
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub kind: ParserError,
+    pub location: SourceLocation,
+    pub source_line: String,
+}
+
+impl Diagnostic {
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.columns.start)
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.location.line.to_string().len());
+        writeln!(f, "{}: {}", self.severity, self.kind)?;
+        writeln!(f, "{gutter}--> {}", self.location)?;
+        writeln!(f, "{gutter} |")?;
+        writeln!(f, "{} | {}", self.location.line, self.source_line)?;
+
+        // keep tabs so the carets line up with the source line
+        let start = self.location.columns.start.saturating_sub(1);
+        let padding: String = self
+            .source_line
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.location.columns.len().max(1);
+        write!(f, "{gutter} | {padding}{}", "^".repeat(width))
+    }
+}
@@ -1,5 +1,4 @@
-// TODO: Add more specific error types as needed
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ParserError {
     FileNotFound(String),
     InvalidInstruction(String),
@@ -8,6 +7,14 @@ pub enum ParserError {
     TooManyOperands(String),
     BadlyDefinedDefinition(String),
     InvalidLabel(String),
+    InvalidRegister(String),
+    InvalidImmediate(String),
+    ImmediateOutOfRange { value: String, max: usize },
+    InvalidCondition(String),
+    UnknownPortName(String),
+    RedefinedLabel(String),
+    RedefinedDefinition(String),
+    ExtendedAluInstruction(String),
 }
 
 impl std::fmt::Display for ParserError {
@@ -22,6 +29,27 @@ impl std::fmt::Display for ParserError {
                 write!(f, "Badly defined definition in line: {line}")
             }
             ParserError::InvalidLabel(label) => write!(f, "Invalid label: {label}"),
+            ParserError::InvalidRegister(reg) => {
+                write!(f, "Invalid register: {reg} (expected r0 to r15)")
+            }
+            ParserError::InvalidImmediate(imm) => write!(f, "Invalid immediate: {imm}"),
+            ParserError::ImmediateOutOfRange { value, max } => {
+                write!(f, "Immediate out of range: {value} (max: {max})")
+            }
+            ParserError::InvalidCondition(cond) => write!(f, "Invalid condition: {cond}"),
+            ParserError::UnknownPortName(name) => {
+                write!(f, "Unknown port name or definition: {name}")
+            }
+            ParserError::RedefinedLabel(label) => write!(f, "Label '{label}' is redefined"),
+            ParserError::RedefinedDefinition(name) => {
+                write!(f, "Definition '{name}' is redefined")
+            }
+            ParserError::ExtendedAluInstruction(name) => {
+                write!(
+                    f,
+                    "{name} needs the extended ALU, a BatPU-2 would run it as RSH"
+                )
+            }
         }
     }
 }
@@ -1,186 +1,245 @@
+use std::collections::{BTreeMap, HashMap};
 use std::path::Path;
 
-use crate::parser::utils::{is_comment, parse_immediate, parse_offset};
-use crate::Result;
-use crate::{bits::Bits, parser::utils::extract_n_operands};
+use crate::{bits::Bits, Program, Result, VmConfig};
+use diagnostic::Diagnostic;
 use error::ParserError;
 use std::str::FromStr;
 
-use utils::{parse_address, parse_cond, parse_instruction, parse_register_string};
+use crate::instruction::ALU_FUNCTIONS;
+use crate::{Address, Immediate};
+use utils::{parse_instruction, SourceLine};
 
-pub(crate) use utils::parse_as_instruction;
+pub(crate) use utils::{parse_as_instruction, PORTNAMES};
 
+pub mod diagnostic;
 pub mod error;
 mod utils;
 
-// TODO: improve error handling
-#[allow(clippy::unwrap_used)]
-pub(crate) fn parse_program(file_path: impl AsRef<Path>) -> Result<()> {
-    let mut output_lines = vec![];
+pub(crate) fn parse_program(file_path: impl AsRef<Path>) -> Result<Program> {
+    parse_program_with(file_path, &VmConfig::default())
+}
 
+pub(crate) fn parse_program_with(
+    file_path: impl AsRef<Path>,
+    config: &VmConfig,
+) -> Result<Program> {
     let path = file_path.as_ref();
-    let mut content = std::fs::read_to_string(path)
+    let content = std::fs::read_to_string(path)
         .map_err(|_| ParserError::FileNotFound(path.display().to_string()))?;
+    let program = assemble_file(&path.display().to_string(), &content, config).into_result()?;
+
+    use std::io::Write;
+    let mut output_file = std::fs::File::create(path.with_extension("mc"))?;
+    for instruction in program.iter() {
+        writeln!(output_file, "{instruction}")?
+    }
+    Ok(program)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Assembly {
+    pub program: Program,
+    pub diagnostics: Vec<Diagnostic>,
+    pub labels: BTreeMap<String, u16>,
+    pub banks: BTreeMap<String, u8>, // named with the `bank` directive
+}
+
+impl Assembly {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(Diagnostic::is_error)
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| d.is_error())
+    }
 
-    let mut labels = std::collections::HashMap::new();
-    let mut symbols = std::collections::HashMap::new();
-    let content = utils::find_and_remove_symbols(&mut content, &mut labels, &mut symbols)?;
-    for line in content.iter() {
-        let mut out = vec![];
-        let mut splitted = line.split_whitespace();
-        let instruction = match splitted.next() {
-            Some(instr) => instr,
-            None => continue, // Skip empty lines
-        };
-        let instruction = instruction.to_uppercase();
-        if is_comment(line) {
-            continue;
+    pub fn warnings(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter().filter(|d| !d.is_error())
+    }
+
+    pub fn into_result(self) -> Result<Program> {
+        if self.has_errors() {
+            let errors = self.diagnostics.into_iter().filter(Diagnostic::is_error);
+            return Err(crate::Error::Assembly(errors.collect()));
         }
-        let mut operands = splitted;
-        match instruction.as_str() {
-            "NOP" | "HLT" | "RET" => {
-                let ops = extract_n_operands(0, &mut operands, line)?;
-                let [] = ops.as_slice() else {
-                    return Err(ParserError::MissingOperand(line.to_string()).into());
-                };
-                let instruction_bits = parse_instruction(&instruction).unwrap();
-                out.push(instruction_bits.to_string());
-                out.push("000000000000".to_string());
-            }
-            "INC" | "DEC" => {
-                let ops = extract_n_operands(1, &mut operands, line)?;
-                let [r1] = ops.as_slice() else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                };
-                if instruction == "INC" {
-                    out.push(parse_instruction("ADI").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push(Bits::from(1u8).to_string());
-                } else {
-                    out.push(parse_instruction("ADI").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push(Bits::from(255u8).to_string());
-                }
+        Ok(self.program)
+    }
+}
+
+/// Assembles program source into machine code without touching the filesystem.
+pub fn assemble(source: &str) -> Result<Program> {
+    assemble_with(source, false)
+}
+
+// For a machine with the extended ALU, whose OR, XNOR, NAND, IMP and NIMP are errors on
+// the BatPU-2
+pub fn assemble_with(source: &str, extended_alu: bool) -> Result<Program> {
+    let config = VmConfig {
+        extended_alu,
+        ..Default::default()
+    };
+    assemble_file("<input>", source, &config).into_result()
+}
+
+pub fn assemble_with_diagnostics(file: &str, source: &str) -> Assembly {
+    assemble_file(file, source, &VmConfig::default())
+}
+
+// Keeps assembling after an error so that every problem in the file is reported at once.
+// Lines that fail still take up an address, which keeps the labels after them correct.
+// The port names and `bank_select` follow the machine's IO base.
+pub(crate) fn assemble_file(file: &str, source: &str, config: &VmConfig) -> Assembly {
+    let mut labels = HashMap::new();
+    let mut symbols = HashMap::new();
+    let mut banks = BTreeMap::new();
+    let mut diagnostics = vec![];
+    let lines = utils::find_and_remove_symbols(
+        file,
+        source,
+        &mut labels,
+        &mut symbols,
+        &mut banks,
+        &mut diagnostics,
+    );
+    let mut program = Vec::with_capacity(lines.len());
+    for line in lines.iter() {
+        match assemble_line(line, &labels, &symbols, config) {
+            Ok(instruction) => program.push(instruction),
+            Err(diagnostic) => {
+                diagnostics.push(diagnostic);
+                program.push(Bits::default());
             }
-            "JMP" | "CAL" => {
-                let ops = extract_n_operands(1, &mut operands, line)?;
-                let [addr] = ops.as_slice() else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                };
-                out.push(parse_instruction(&instruction).unwrap().to_string());
-                out.push(Bits::<2>::from_str("00").unwrap().to_string());
-                out.push(parse_address(addr, &mut labels)?.to_string());
+        }
+    }
+    diagnostics.sort_by_key(|d| d.location.line);
+    let labels = labels
+        .into_iter()
+        .map(|(name, address)| (name, u16::from(address.resize::<16>())))
+        .collect();
+    Assembly {
+        program,
+        diagnostics,
+        labels,
+        banks,
+    }
+}
+
+#[allow(clippy::unwrap_used)]
+fn assemble_line(
+    line: &SourceLine,
+    labels: &HashMap<String, Address>,
+    symbols: &HashMap<String, Immediate>,
+    config: &VmConfig,
+) -> std::result::Result<Bits<16>, Diagnostic> {
+    let mut out = vec![];
+    let instruction = line.mnemonic().text.to_uppercase();
+    match instruction.as_str() {
+        "NOP" | "HLT" | "RET" => {
+            line.operands::<0>()?;
+            let instruction_bits = parse_instruction(&instruction).unwrap();
+            out.push(instruction_bits.to_string());
+            out.push("000000000000".to_string());
+        }
+        "INC" | "DEC" => {
+            let [r1] = line.operands()?;
+            out.push(parse_instruction("ADI").unwrap().to_string());
+            out.push(line.register(&r1)?.to_string());
+            if instruction == "INC" {
+                out.push(Bits::from(1u8).to_string());
+            } else {
+                out.push(Bits::from(255u8).to_string());
             }
-            "CMP" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                let [r1, r2] = ops.as_slice() else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                };
-                // CMP rx ry -> SUB rx ry r0
-                out.push(parse_instruction("SUB").unwrap().to_string());
-                out.push(parse_register_string(r1)?.to_string());
-                out.push(parse_register_string(r2)?.to_string());
+        }
+        "JMP" | "CAL" => {
+            let [addr] = line.operands()?;
+            out.push(parse_instruction(&instruction).unwrap().to_string());
+            out.push(Bits::<2>::from_str("00").unwrap().to_string());
+            out.push(line.address(&addr, labels)?.to_string());
+        }
+        "CMP" => {
+            let [r1, r2] = line.operands()?;
+            // CMP rx ry -> SUB rx ry r0
+            out.push(parse_instruction("SUB").unwrap().to_string());
+            out.push(line.register(&r1)?.to_string());
+            out.push(line.register(&r2)?.to_string());
+            out.push("0000".to_string());
+        }
+        "MOV" | "LSH" | "NOT" => {
+            let [r1, r2] = line.operands()?;
+            if instruction == "MOV" {
+                out.push(parse_instruction("ADD").unwrap().to_string());
+                out.push(line.register(&r1)?.to_string());
                 out.push("0000".to_string());
+                out.push(line.register(&r2)?.to_string());
+            } else if instruction == "NOT" {
+                out.push(parse_instruction("NOR").unwrap().to_string());
+                out.push(line.register(&r1)?.to_string());
+                out.push("0000".to_string());
+                out.push(line.register(&r2)?.to_string());
+            } else {
+                out.push(parse_instruction("ADD").unwrap().to_string());
+                out.push(line.register(&r1)?.to_string());
+                out.push(line.register(&r1)?.to_string());
+                out.push(line.register(&r2)?.to_string());
             }
-            "MOV" | "LSH" | "NOT" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                let [r1, r2] = ops.as_slice() else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                };
-                if instruction == "MOV" {
-                    out.push(parse_instruction("ADD").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push("0000".to_string());
-                    out.push(parse_register_string(r2)?.to_string());
-                } else if instruction == "NOT" {
-                    out.push(parse_instruction("NOR").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push("0000".to_string());
-                    out.push(parse_register_string(r2)?.to_string());
-                } else {
-                    out.push(parse_instruction("ADD").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push(parse_register_string(r2)?.to_string());
-                }
-            }
-            "LDI" | "ADI" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                match ops.as_slice() {
-                    [r1, immediate] => {
-                        out.push(parse_instruction(&instruction).unwrap().to_string());
-                        out.push(parse_register_string(r1)?.to_string());
-                        out.push(parse_immediate(immediate.to_string(), &mut symbols)?.to_string());
-                    }
-                    [r1, immediate1, immediate2] => {
-                        out.push(parse_instruction(&instruction).unwrap().to_string());
-                        out.push(parse_register_string(r1)?.to_string());
-                        let immediate = format!("{immediate1} {immediate2}");
-                        out.push(parse_immediate(immediate.to_string(), &mut symbols)?.to_string());
-                    }
-                    _ => return Err(ParserError::TooManyOperands(line.to_string()).into()),
-                }
-            }
-            "BRH" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                let [cond, addr] = ops.as_slice() else {
-                    return Err(ParserError::MissingOperand(line.to_string()).into());
-                };
-                out.push(parse_instruction("BRH").unwrap().to_string());
-                out.push(parse_cond(cond)?.to_string());
-                out.push(parse_address(addr, &mut labels)?.to_string());
-            }
-            "RSH" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                let ops_slice = ops.as_slice();
-                if let [r1, write] = ops_slice {
-                    out.push(parse_instruction("RSH").unwrap().to_string());
-                    out.push(parse_register_string(r1)?.to_string());
-                    out.push("0000".to_string());
-                    out.push(parse_register_string(write)?.to_string());
-                } else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                }
-            }
-            "LOD" | "STR" => {
-                let ops = extract_n_operands(2, &mut operands, line)?;
-                match ops.as_slice() {
-                    [r1, r2] => {
-                        out.push(parse_instruction(&instruction).unwrap().to_string());
-                        out.push(parse_register_string(r1)?.to_string());
-                        out.push(parse_register_string(r2)?.to_string());
-                        out.push("0000".to_string());
-                    }
-                    [r1, r2, offset] => {
-                        out.push(parse_instruction(&instruction).unwrap().to_string());
-                        out.push(parse_register_string(r1)?.to_string());
-                        out.push(parse_register_string(r2)?.to_string());
-                        out.push(parse_offset(offset, &mut symbols)?.to_string());
-                    }
-                    _ => return Err(ParserError::TooManyOperands(line.to_string()).into()),
-                }
+        }
+        "LDI" | "ADI" => {
+            let [r1, immediate] = line.operands()?;
+            out.push(parse_instruction(&instruction).unwrap().to_string());
+            out.push(line.register(&r1)?.to_string());
+            out.push(
+                line.immediate(&immediate, symbols, config.io_base)?
+                    .to_string(),
+            );
+        }
+        "BRH" => {
+            let [cond, addr] = line.operands()?;
+            out.push(parse_instruction("BRH").unwrap().to_string());
+            out.push(line.condition(&cond)?.to_string());
+            out.push(line.address(&addr, labels)?.to_string());
+        }
+        // the extended ALU functions are RSH words with the function in the B field
+        "RSH" | "OR" | "XNOR" | "NAND" | "IMP" | "NIMP" => {
+            if instruction != "RSH" && !config.extended_alu {
+                let kind = ParserError::ExtendedAluInstruction(instruction);
+                return Err(line.error(kind, line.mnemonic()));
             }
-            "ADD" | "SUB" | "AND" | "NOR" | "XOR" => {
-                let ops = extract_n_operands(3, &mut operands, line)?;
-                let [r1, r2, write] = ops.as_slice() else {
-                    return Err(ParserError::TooManyOperands(line.to_string()).into());
-                };
-                out.push(parse_instruction(&instruction).unwrap().to_string());
-                out.push(parse_register_string(r1)?.to_string());
-                out.push(parse_register_string(r2)?.to_string());
-                out.push(parse_register_string(write)?.to_string());
+            let [r1, write] = line.operands()?;
+            let function = ALU_FUNCTIONS
+                .iter()
+                .position(|&f| f == instruction)
+                .unwrap();
+            out.push(parse_instruction("RSH").unwrap().to_string());
+            out.push(line.register(&r1)?.to_string());
+            out.push(format!("{function:04b}"));
+            out.push(line.register(&write)?.to_string());
+        }
+        "LOD" | "STR" => {
+            let ops = line.operands_between(2, 3)?;
+            out.push(parse_instruction(&instruction).unwrap().to_string());
+            out.push(line.register(&ops[0])?.to_string());
+            out.push(line.register(&ops[1])?.to_string());
+            match ops.get(2) {
+                Some(offset) => out.push(line.offset(offset, symbols)?.to_string()),
+                None => out.push("0000".to_string()),
             }
-            _ => return Err(ParserError::InvalidInstruction(instruction).into()),
         }
-        output_lines.push(out.join(""));
-    }
-
-    use std::io::Write;
-    let mut output_file = std::fs::File::create(path.with_extension("mc"))?;
-    for l in output_lines {
-        writeln!(output_file, "{l}")?
+        "ADD" | "SUB" | "AND" | "NOR" | "XOR" => {
+            let [r1, r2, write] = line.operands()?;
+            out.push(parse_instruction(&instruction).unwrap().to_string());
+            out.push(line.register(&r1)?.to_string());
+            out.push(line.register(&r2)?.to_string());
+            out.push(line.register(&write)?.to_string());
+        }
+        _ => {
+            return Err(line.error(
+                ParserError::InvalidInstruction(instruction),
+                line.mnemonic(),
+            ))
+        }
     }
-    Ok(())
+    Ok(parse_as_instruction(&out.join("")))
 }
 
 #[cfg(test)]
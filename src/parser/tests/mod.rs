@@ -1,4 +1,6 @@
 mod operands;
 mod program;
+mod test_assemble;
+mod test_diagnostics;
 mod test_file_handling;
 mod test_instructions;
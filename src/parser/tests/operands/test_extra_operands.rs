@@ -11,6 +11,6 @@ fn parse_program_nop_with_extra_operands() {
     let result = parse_program(test_file);
     assert!(result.is_err());
     let err = result.unwrap_err().to_string();
-    assert!(err.contains("Missing operand"));
+    assert!(err.contains("Too many operands"));
     std::fs::remove_file(test_file).unwrap();
 }
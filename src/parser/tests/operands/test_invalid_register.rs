@@ -1,6 +1,6 @@
 #![allow(clippy::panic)]
 use super::super::super::*;
-use crate::{bits::BitsParseError, error::VmError};
+use crate::error::VmError;
 
 #[test]
 fn parse_register_string_value_too_large() {
@@ -8,11 +8,13 @@ fn parse_register_string_value_too_large() {
     std::fs::write(test_file, "ADD r16 r1 r2").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::OutOfBounds { value, max }) => {
-            assert_eq!(value, 16);
-            assert_eq!(max, 15);
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("r16".to_string()));
+            assert_eq!(ds[0].location.file, test_file);
+            assert_eq!(ds[0].location.line, 1);
+            assert_eq!(ds[0].location.columns, 5..8);
         }
-        _ => panic!("Expected OutOfBounds error"),
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -23,8 +25,10 @@ fn parse_register_string_negative_value() {
     std::fs::write(test_file, "ADD r-1 r1 r2").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Parser(ParserError::InvalidInstruction(ref s)) => assert_eq!(s, "r-1"),
-        _ => panic!("Expected InvalidInstruction error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("r-1".to_string()))
+        }
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -35,8 +39,10 @@ fn parse_register_string_non_numeric_value() {
     std::fs::write(test_file, "ADD rX r1 r2").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::Number { .. }) => (),
-        _ => panic!("Expected Number error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("rX".to_string()))
+        }
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -47,8 +53,10 @@ fn parse_register_string_invalid_prefix() {
     std::fs::write(test_file, "ADD x1 r1 r2").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Parser(ParserError::InvalidInstruction(ref s)) => assert_eq!(s, "x1"),
-        _ => panic!("Expected InvalidInstruction error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("x1".to_string()))
+        }
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -59,8 +67,10 @@ fn parse_register_string_too_short() {
     std::fs::write(test_file, "ADD r r1 r2").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Parser(ParserError::InvalidInstruction(ref s)) => assert_eq!(s, "r"),
-        _ => panic!("Expected InvalidInstruction error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("r".to_string()))
+        }
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -71,11 +81,11 @@ fn ldi_register_parse_fail() {
     std::fs::write(test_file, "LDI r16 8").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::OutOfBounds { value, max }) => {
-            assert_eq!(value, 16);
-            assert_eq!(max, 15);
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("r16".to_string()));
+            assert_eq!(ds[0].location.columns, 5..8);
         }
-        _ => panic!("Expected OutOfBounds error"),
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -86,8 +96,11 @@ fn rsh_invalid_first_operand() {
     std::fs::write(test_file, "RSH rX r1").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::Number { .. }) => (),
-        _ => panic!("Expected Number error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::InvalidRegister("rX".to_string()));
+            assert_eq!(ds[0].location.columns, 5..7);
+        }
+        _ => panic!("Expected InvalidRegister error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -3,7 +3,7 @@ use std::fs::File;
 use std::io::Write;
 
 use super::super::super::*;
-use crate::{bits::BitsParseError, error::VmError};
+use crate::error::VmError;
 
 #[test]
 fn ldi_missing_value_operand() {
@@ -11,8 +11,12 @@ fn ldi_missing_value_operand() {
     std::fs::write(test_file, "LDI r1").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Parser(ParserError::MissingOperand(ref line)) => {
-            assert!(line.contains("LDI r1"));
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(
+                ds[0].kind,
+                ParserError::MissingOperand("LDI r1".to_string())
+            );
+            assert_eq!(ds[0].location.columns, 1..4);
         }
         _ => panic!("Expected MissingOperand error"),
     }
@@ -27,7 +31,9 @@ fn ldi_only_instruction() {
     drop(file);
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Parser(ParserError::MissingOperand(ref line)) => assert!(line.contains("LDI")),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::MissingOperand("LDI".to_string()))
+        }
         _ => panic!("Expected MissingOperand error"),
     }
     std::fs::remove_file(test_file).unwrap();
@@ -39,8 +45,11 @@ fn ldi_invalid_value_operand() {
     std::fs::write(test_file, "LDI r1 na").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::Number { .. }) => (),
-        _ => panic!("Expected Number error"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(ds[0].kind, ParserError::UnknownPortName("na".to_string()));
+            assert_eq!(ds[0].location.columns, 8..10);
+        }
+        _ => panic!("Expected UnknownPortName error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
@@ -51,11 +60,14 @@ fn ldi_value_too_large() {
     std::fs::write(test_file, "LDI r1 300").unwrap();
     let err = parse_program(test_file).unwrap_err();
     match err {
-        VmError::Bits(BitsParseError::OutOfBounds { value, max }) => {
-            assert_eq!(value, 300);
-            assert_eq!(max, 255);
-        }
-        _ => panic!("Expected OutOfBounds error"),
+        VmError::Assembly(ds) if ds.len() == 1 => assert_eq!(
+            ds[0].kind,
+            ParserError::ImmediateOutOfRange {
+                value: "300".to_string(),
+                max: 255
+            }
+        ),
+        _ => panic!("Expected ImmediateOutOfRange error"),
     }
     std::fs::remove_file(test_file).unwrap();
 }
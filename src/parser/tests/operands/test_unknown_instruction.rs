@@ -15,7 +15,13 @@ fn unknown_operand() {
     std::fs::remove_file(test_file).unwrap();
 
     match result.unwrap_err() {
-        VmError::Parser(ParserError::InvalidInstruction(instr)) => assert_eq!(instr, "FOO"),
+        VmError::Assembly(ds) if ds.len() == 1 => {
+            assert_eq!(
+                ds[0].kind,
+                ParserError::InvalidInstruction("FOO".to_string())
+            );
+            assert_eq!(ds[0].location.columns, 1..4);
+        }
         _ => panic!("Expected InvalidInstruction error"),
     }
 }
@@ -0,0 +1,88 @@
+use super::super::*;
+
+#[test]
+fn assemble_from_source() {
+    let program = assemble("LDI r1 5\nADD r1 r1 r2\nHLT").unwrap();
+    let words: Vec<String> = program.iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        words,
+        vec!["1000000100000101", "0010000100010010", "0001000000000000"]
+    );
+}
+
+#[test]
+fn assemble_resolves_labels_and_definitions() {
+    let source = "define five 5\n.start LDI r1 five\nJMP .start";
+    let program = assemble(source).unwrap();
+    assert_eq!(program.len(), 2);
+    assert_eq!(program[0].to_string(), "1000000100000101");
+    assert_eq!(program[1].to_string(), "1010000000000000");
+}
+
+#[test]
+fn assemble_matches_parse_program() {
+    let source = std::fs::read_to_string("programs/dvd.as").unwrap();
+    let expected = std::fs::read_to_string("check_mc/dvd.mc").unwrap();
+    let program = assemble(&source).unwrap();
+    let generated: Vec<String> = program.iter().map(|i| i.to_string()).collect();
+    let expected: Vec<&str> = expected.lines().collect();
+    assert_eq!(generated, expected);
+}
+
+#[test]
+fn assemble_reports_errors() {
+    let err = assemble("FOO r1 r2 r3").unwrap_err().to_string();
+    assert!(err.contains("Invalid instruction"));
+}
+
+#[test]
+fn assemble_bank_directive() {
+    let source = "bank level 2\nLDI r1 level\nLDI r2 bank_select";
+    let assembly = assemble_with_diagnostics("<input>", source);
+    assert!(!assembly.has_errors());
+    assert_eq!(assembly.banks, BTreeMap::from([("level".to_string(), 2)]));
+    let words: Vec<String> = assembly.program.iter().map(|i| i.to_string()).collect();
+    assert_eq!(words, vec!["1000000100000010", "1000001011101111"]);
+}
+
+#[test]
+fn definitions_shadow_bank_select() {
+    let program = assemble("define bank_select 5\nLDI r1 bank_select").unwrap();
+    assert_eq!(program[0].to_string(), "1000000100000101");
+}
+
+#[test]
+fn assemble_extended_alu_functions() {
+    let source = "RSH r1 r2\nOR r1 r2\nXNOR r3 r4\nNAND r5 r6\nIMP r7 r8\nnimp r9 r10";
+    let program = assemble_with(source, true).unwrap();
+    let words: Vec<String> = program.iter().map(|i| i.to_string()).collect();
+    assert_eq!(
+        words,
+        vec![
+            "0111000100000010",
+            "0111000100010010",
+            "0111001100100100",
+            "0111010100110110",
+            "0111011101001000",
+            "0111100101011010"
+        ]
+    );
+    assert!(assemble_with("OR r1 r2 r3", true).is_err());
+}
+
+#[test]
+fn extended_alu_functions_need_the_extended_alu() {
+    let assembly = assemble_with_diagnostics("test.as", "RSH r1 r2\n  NAND r5 r6");
+    let errors: Vec<_> = assembly.errors().collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(
+        errors[0].kind,
+        ParserError::ExtendedAluInstruction("NAND".to_string())
+    );
+    assert_eq!(errors[0].location.line, 2);
+    assert_eq!(errors[0].location.columns, 3..7);
+    assert_eq!(
+        errors[0].kind.to_string(),
+        "NAND needs the extended ALU, a BatPU-2 would run it as RSH"
+    );
+}
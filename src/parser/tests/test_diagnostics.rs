@@ -0,0 +1,139 @@
+#![allow(clippy::panic)]
+use super::super::*;
+use crate::error::VmError;
+use crate::parser::diagnostic::Severity;
+
+fn diagnostic(source: &str) -> Diagnostic {
+    match assemble(source).unwrap_err() {
+        VmError::Assembly(mut ds) => ds.remove(0),
+        e => panic!("Expected assembly diagnostic, got {e:?}"),
+    }
+}
+
+#[test]
+fn reports_line_and_columns() {
+    let d = diagnostic("LDI r1 5\n\n  ADD r1 r16 r2");
+    assert_eq!(d.kind, ParserError::InvalidRegister("r16".to_string()));
+    assert_eq!(d.location.file, "<input>");
+    assert_eq!(d.location.line, 3);
+    assert_eq!(d.location.columns, 10..13);
+    assert_eq!(d.source_line, "  ADD r1 r16 r2");
+}
+
+#[test]
+fn renders_caret_under_token() {
+    let d = diagnostic("NOP\n.loop ADD r1 r16 r2 // comment");
+    let expected = "\
+error: Invalid register: r16 (expected r0 to r15)
+ --> <input>:2:14
+  |
+2 | .loop ADD r1 r16 r2 // comment
+  |              ^^^";
+    assert_eq!(d.to_string(), expected);
+}
+
+#[test]
+fn invalid_condition() {
+    let d = diagnostic(".start\nBRH maybe .start");
+    assert_eq!(d.kind, ParserError::InvalidCondition("maybe".to_string()));
+    assert_eq!(d.location.columns, 5..10);
+}
+
+#[test]
+fn unknown_port_name() {
+    let d = diagnostic("LDI r1 pixel_z");
+    assert_eq!(d.kind, ParserError::UnknownPortName("pixel_z".to_string()));
+}
+
+#[test]
+fn offset_out_of_range() {
+    let d = diagnostic("LOD r1 r2 -9");
+    assert_eq!(
+        d.kind,
+        ParserError::ImmediateOutOfRange {
+            value: "-9".to_string(),
+            max: 8
+        }
+    );
+    assert_eq!(d.location.columns, 11..13);
+    assert!(assemble("LOD r1 r2 -8").is_ok());
+}
+
+#[test]
+fn undefined_label() {
+    let d = diagnostic("JMP .nowhere");
+    assert_eq!(d.kind, ParserError::UndefinedLabel(".nowhere".to_string()));
+    assert_eq!(d.location.columns, 5..13);
+}
+
+#[test]
+fn too_many_operands_spans_extra_operands() {
+    let d = diagnostic("RSH r1 r2 r3 r4");
+    assert_eq!(
+        d.kind,
+        ParserError::TooManyOperands("RSH r1 r2 r3 r4".to_string())
+    );
+    assert_eq!(d.location.columns, 11..16);
+}
+
+#[test]
+fn badly_defined_definition() {
+    let d = diagnostic("define five");
+    assert_eq!(
+        d.kind,
+        ParserError::BadlyDefinedDefinition("define five".to_string())
+    );
+}
+
+#[test]
+fn space_character_immediate() {
+    let program = assemble("LDI r1 \" \"\nLDI r2 ' '").unwrap();
+    assert_eq!(program[0].to_string(), "1000000100000000");
+    assert_eq!(program[1].to_string(), "1000001000000000");
+}
+
+#[test]
+fn collects_every_error() {
+    let source = "ADD r1 r16 r2\nLDI r1 300\nFOO\nHLT";
+    let err = assemble(source).unwrap_err();
+    let VmError::Assembly(diagnostics) = err else {
+        panic!("Expected assembly diagnostics");
+    };
+    let lines: Vec<usize> = diagnostics.iter().map(|d| d.location.line).collect();
+    assert_eq!(lines, vec![1, 2, 3]);
+    assert!(diagnostics.iter().all(Diagnostic::is_error));
+}
+
+#[test]
+fn failed_lines_keep_their_address() {
+    let source = "ADD r1 r16 r2\n.end HLT\nJMP .end";
+    let assembly = assemble_with_diagnostics("test.as", source);
+    assert!(assembly.has_errors());
+    assert_eq!(assembly.program.len(), 3);
+    assert_eq!(assembly.program[2].to_string(), "1010000000000001");
+}
+
+#[test]
+fn redefinitions_are_warnings() {
+    let source = "define x 1\ndefine x 2\n.a NOP\n.a HLT";
+    let assembly = assemble_with_diagnostics("test.as", source);
+    assert!(!assembly.has_errors());
+    let warnings: Vec<&ParserError> = assembly.warnings().map(|d| &d.kind).collect();
+    assert_eq!(
+        warnings,
+        vec![
+            &ParserError::RedefinedDefinition("x".to_string()),
+            &ParserError::RedefinedLabel(".a".to_string()),
+        ]
+    );
+    assert!(assembly.warnings().all(|d| d.severity == Severity::Warning));
+    assert!(assembly.into_result().is_ok());
+}
+
+#[test]
+fn warning_renders_with_severity() {
+    let assembly = assemble_with_diagnostics("test.as", "define x 1\ndefine x 2");
+    let rendered = assembly.diagnostics[0].to_string();
+    assert!(rendered.starts_with("warning: Definition 'x' is redefined"));
+    assert!(rendered.contains("--> test.as:2:8"));
+}
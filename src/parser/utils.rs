@@ -1,10 +1,13 @@
 use crate::bits::Bits;
+use crate::parser::diagnostic::{Diagnostic, Severity, SourceLocation};
 use crate::parser::error::ParserError;
-use crate::{Address, Immediate, Result};
+use crate::{Address, BitsParseError, Immediate};
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
 use std::str::FromStr;
 
 const CHARSET: &str = " abcdefghijklmnopqrstuvwxyz.!?";
-const PORTNAMES: [&str; 16] = [
+pub(crate) const PORTNAMES: [&str; 16] = [
     "pixel_x",
     "pixel_y",
     "draw_pixel",
@@ -22,45 +25,204 @@ const PORTNAMES: [&str; 16] = [
     "rng",
     "controller_input",
 ];
+// the bank-select register of machines with memory banks, just below the ports
+pub(crate) const BANK_SELECT: &str = "bank_select";
 
-pub(super) fn parse_register_string(s: &str) -> Result<Bits<4>> {
-    let s_lower = s.to_lowercase();
-    if s_lower.len() < 2 || !s_lower.starts_with('r') {
-        return Err(ParserError::InvalidInstruction(s.to_string()).into());
+type ParseResult<T> = std::result::Result<T, ParserError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Token<'a> {
+    pub(crate) text: &'a str,
+    pub(crate) start: usize, // byte offset into the source line
+}
+
+impl Token<'_> {
+    pub(crate) fn span(&self) -> Range<usize> {
+        self.start..self.start + self.text.len()
+    }
+}
+
+// A line of source that holds an instruction, with its label and comment stripped
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SourceLine<'a> {
+    pub(crate) file: &'a str,
+    pub(crate) number: usize,
+    pub(crate) text: &'a str,
+    pub(crate) tokens: Vec<Token<'a>>,
+}
+
+impl<'a> SourceLine<'a> {
+    fn diagnostic(&self, severity: Severity, kind: ParserError, span: Range<usize>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            kind,
+            location: SourceLocation {
+                file: self.file.to_string(),
+                line: self.number,
+                columns: span.start + 1..span.end + 1,
+            },
+            source_line: self.text.to_string(),
+        }
+    }
+
+    pub(crate) fn error_at(&self, kind: ParserError, span: Range<usize>) -> Diagnostic {
+        self.diagnostic(Severity::Error, kind, span)
+    }
+
+    pub(crate) fn error(&self, kind: ParserError, token: &Token) -> Diagnostic {
+        self.error_at(kind, token.span())
+    }
+
+    pub(crate) fn warning(&self, kind: ParserError, token: &Token) -> Diagnostic {
+        self.diagnostic(Severity::Warning, kind, token.span())
+    }
+
+    pub(crate) fn mnemonic(&self) -> &Token<'a> {
+        &self.tokens[0]
+    }
+
+    pub(crate) fn instruction_text(&self) -> &'a str {
+        let start = self.tokens[0].start;
+        let end = self.tokens[self.tokens.len() - 1].span().end;
+        &self.text[start..end]
+    }
+
+    pub(crate) fn operands<const N: usize>(&self) -> Result<[Token<'a>; N], Diagnostic> {
+        let ops = self.operands_between(N, N)?;
+        Ok(std::array::from_fn(|i| ops[i]))
+    }
+
+    pub(crate) fn operands_between(
+        &self,
+        min: usize,
+        max: usize,
+    ) -> Result<&[Token<'a>], Diagnostic> {
+        let ops = &self.tokens[1..];
+        if ops.len() < min {
+            let text = self.instruction_text().to_string();
+            return Err(self.error(ParserError::MissingOperand(text), self.mnemonic()));
+        }
+        if ops.len() > max {
+            let text = self.instruction_text().to_string();
+            let span = ops[max].start..ops[ops.len() - 1].span().end;
+            return Err(self.error_at(ParserError::TooManyOperands(text), span));
+        }
+        Ok(ops)
+    }
+
+    pub(crate) fn register(&self, token: &Token) -> Result<Bits<4>, Diagnostic> {
+        parse_register_string(token.text).map_err(|e| self.error(e, token))
     }
-    let num_s = &s[1..];
-    if num_s.starts_with("-") {
-        return Err(ParserError::InvalidInstruction(s.to_string()).into());
+
+    pub(crate) fn condition(&self, token: &Token) -> Result<Bits<2>, Diagnostic> {
+        parse_cond(token.text).map_err(|e| self.error(e, token))
+    }
+
+    pub(crate) fn address(
+        &self,
+        token: &Token,
+        labels: &HashMap<String, Address>,
+    ) -> Result<Address, Diagnostic> {
+        parse_address(token.text, labels).map_err(|e| self.error(e, token))
+    }
+
+    pub(crate) fn immediate(
+        &self,
+        token: &Token,
+        symbols: &HashMap<String, Immediate>,
+        io_base: u8,
+    ) -> Result<Immediate, Diagnostic> {
+        parse_immediate(token.text, symbols, io_base).map_err(|e| self.error(e, token))
+    }
+
+    pub(crate) fn offset(
+        &self,
+        token: &Token,
+        symbols: &HashMap<String, Immediate>,
+    ) -> Result<Bits<4>, Diagnostic> {
+        parse_offset(token.text, symbols).map_err(|e| self.error(e, token))
     }
-    Bits::from_str(num_s)
+}
+
+// Splits a line into whitespace separated tokens, stopping at a comment.
+// Quoted characters are kept as a single token so that `" "` is a valid immediate.
+pub(crate) fn tokenize(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i].is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        if is_comment(&line[start..]) {
+            break;
+        }
+        let quote = bytes[i];
+        let closing = match quote {
+            b'"' | b'\'' => line[start + 1..].find(quote as char),
+            _ => None,
+        };
+        i = match closing {
+            Some(idx) => start + idx + 2,
+            None => line[start..]
+                .find(|c: char| c.is_ascii_whitespace())
+                .map_or(bytes.len(), |idx| start + idx),
+        };
+        tokens.push(Token {
+            text: &line[start..i],
+            start,
+        });
+    }
+    tokens
+}
+
+fn number_error(value: &str) -> impl Fn(crate::Error) -> ParserError + '_ {
+    move |e| match e {
+        crate::Error::Bits(BitsParseError::OutOfBounds { max, .. }) => {
+            ParserError::ImmediateOutOfRange {
+                value: value.to_string(),
+                max,
+            }
+        }
+        _ => ParserError::InvalidImmediate(value.to_string()),
+    }
+}
+
+pub(super) fn parse_register_string(s: &str) -> ParseResult<Bits<4>> {
+    let invalid = || ParserError::InvalidRegister(s.to_string());
+    let num_s = s.strip_prefix(['r', 'R']).ok_or_else(invalid)?;
+    if num_s.is_empty() || !num_s.chars().all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let num = num_s.parse::<u8>().map_err(|_| invalid())?;
+    Bits::try_from_unsigned_number(num).map_err(|_| invalid())
 }
 
 #[allow(clippy::unwrap_used)]
-pub(super) fn parse_cond(a: &str) -> Result<Bits<2>> {
+pub(super) fn parse_cond(a: &str) -> ParseResult<Bits<2>> {
     match a.to_lowercase().as_str() {
         "=" | "eq" | "z" | "zero" => Ok(Bits::from_str("00").unwrap()),
         "!=" | "ne" | "nz" | "notzero" => Ok(Bits::from_str("01").unwrap()),
         ">=" | "ge" | "c" | "carry" => Ok(Bits::from_str("10").unwrap()),
         "<" | "lt" | "nc" | "notcarry" => Ok(Bits::from_str("11").unwrap()),
-        _ => Err(ParserError::InvalidInstruction(a.to_string()).into()),
+        _ => Err(ParserError::InvalidCondition(a.to_string())),
     }
 }
 
-pub(super) fn parse_address(
-    addr: &str,
-    labels: &mut std::collections::HashMap<String, Address>,
-) -> Result<Address> {
+pub(super) fn parse_address(addr: &str, labels: &HashMap<String, Address>) -> ParseResult<Address> {
     if addr.starts_with(".") {
         if let Some(addr) = labels.get(addr) {
             return Ok(*addr);
         }
-        return Err(ParserError::UndefinedLabel(addr.to_string()).into());
+        return Err(ParserError::UndefinedLabel(addr.to_string()));
     }
-    Bits::from_str(addr)
+    Bits::from_str(addr).map_err(number_error(addr))
 }
 
 #[allow(clippy::unwrap_used)]
-pub(super) fn parse_instruction(instruction: &str) -> Result<Bits<4>> {
+pub(super) fn parse_instruction(instruction: &str) -> ParseResult<Bits<4>> {
     let instruction_bits: Bits<4> = match instruction.to_uppercase().as_str() {
         "NOP" => Bits::from_str("0000").unwrap(),
         "HLT" => Bits::from_str("0001").unwrap(),
@@ -78,7 +240,7 @@ pub(super) fn parse_instruction(instruction: &str) -> Result<Bits<4>> {
         "RET" => Bits::from_str("1101").unwrap(),
         "LOD" => Bits::from_str("1110").unwrap(),
         "STR" => Bits::from_str("1111").unwrap(),
-        &_ => return Err(ParserError::InvalidInstruction(instruction.to_string()).into()),
+        &_ => return Err(ParserError::InvalidInstruction(instruction.to_string())),
     };
     Ok(instruction_bits)
 }
@@ -89,12 +251,17 @@ pub(crate) fn parse_as_instruction(line: &str) -> Bits<16> {
     Bits::from_str(no_ws.as_str()).unwrap()
 }
 
-pub(crate) fn is_label(line: &str) -> bool {
-    line.starts_with('.')
+pub(crate) fn is_label(token: &str) -> bool {
+    token.starts_with('.')
 }
 
-pub(crate) fn is_definition(line: &str) -> bool {
-    line.to_lowercase().starts_with("define")
+pub(crate) fn is_definition(token: &str) -> bool {
+    token.eq_ignore_ascii_case("define")
+}
+
+// `bank NAME N` is a definition that also names a memory bank
+pub(crate) fn is_bank(token: &str) -> bool {
+    token.eq_ignore_ascii_case("bank")
 }
 
 pub(crate) fn is_comment(line: &str) -> bool {
@@ -102,146 +269,132 @@ pub(crate) fn is_comment(line: &str) -> bool {
 }
 
 // TODO: add program too long error
-#[allow(unused_assignments)]
-pub(crate) fn find_and_remove_symbols(
-    lines: &mut str,
-    labels: &mut std::collections::HashMap<String, Address>,
-    symbols: &mut std::collections::HashMap<String, Immediate>,
-) -> Result<Vec<String>> {
+pub(crate) fn find_and_remove_symbols<'a>(
+    file: &'a str,
+    source: &'a str,
+    labels: &mut HashMap<String, Address>,
+    symbols: &mut HashMap<String, Immediate>,
+    banks: &mut BTreeMap<String, u8>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Vec<SourceLine<'a>> {
     let mut out = vec![];
-    let mut undefined_labels = vec![];
-    let mut pc = 0;
-    for mut line in lines.lines() {
-        line = line.trim();
-        if is_label(line) {
-            let idx = match line.find(' ') {
-                Some(idx) => idx,
-                None => line.len(),
-            };
-            let label = line[..idx].trim_end();
-            let rest = line[idx..].trim_start();
-
-            if !rest.is_empty()
-                && labels
-                    .insert(label.to_string(), Bits::from(pc).resize())
-                    .is_some()
-            {
-                eprintln!("Label '{label}' is redefined");
+    let mut pending_labels: Vec<&str> = vec![];
+    let mut pc = 0u16;
+    for (idx, text) in source.lines().enumerate() {
+        let mut line = SourceLine {
+            file,
+            number: idx + 1,
+            text,
+            tokens: tokenize(text),
+        };
+        if line.tokens.first().is_some_and(|t| is_label(t.text)) {
+            let label = line.tokens.remove(0);
+            if label.text.len() < 2 {
+                let kind = ParserError::InvalidLabel(label.text.to_string());
+                diagnostics.push(line.error(kind, &label));
             } else {
-                undefined_labels.push(label.to_string());
-            }
-            if !rest.is_empty() {
-                out.push(rest.to_string());
+                if labels.contains_key(label.text) || pending_labels.contains(&label.text) {
+                    let kind = ParserError::RedefinedLabel(label.text.to_string());
+                    diagnostics.push(line.warning(kind, &label));
+                }
+                pending_labels.push(label.text);
             }
-        } else if is_definition(line) {
-            let mut operands = line.split_whitespace();
-            operands.next(); // skip "define"
-            let ops = extract_n_operands(2, &mut operands, line)?;
-            let [name, value] = ops.as_slice() else {
-                return Err(ParserError::BadlyDefinedDefinition(line.to_string()).into());
+        }
+        let Some(first) = line.tokens.first() else {
+            continue; // skip empty lines and comments
+        };
+        if is_definition(first.text) || is_bank(first.text) {
+            let [_, name, value] = line.tokens.as_slice() else {
+                let text = line.instruction_text().to_string();
+                let span = first.start..line.tokens[line.tokens.len() - 1].span().end;
+                diagnostics.push(line.error_at(ParserError::BadlyDefinedDefinition(text), span));
+                continue;
             };
-            if symbols
-                .insert(name.to_string(), Bits::from_str(value)?)
-                .is_some()
-            {
-                eprintln!("Definition '{name}' is redefined");
-            }
-            out.push("".to_string());
-        } else if is_comment(line) {
-            // skip comments
-        } else if line.is_empty() {
-            // skip empty lines
-        } else {
-            if !undefined_labels.is_empty() {
-                for undefined_label in undefined_labels.iter() {
-                    if let Some(_label) =
-                        labels.insert(undefined_label.clone(), Bits::from(pc).resize())
-                    {
-                        // eprint!("redefined addr: {label}");
-                    }
+            let bits = match Bits::from_str(value.text).map_err(number_error(value.text)) {
+                Ok(bits) => bits,
+                Err(kind) => {
+                    diagnostics.push(line.error(kind, value));
+                    continue;
                 }
+            };
+            if symbols.insert(name.text.to_string(), bits).is_some() {
+                let kind = ParserError::RedefinedDefinition(name.text.to_string());
+                diagnostics.push(line.warning(kind, name));
+            }
+            if is_bank(first.text) {
+                banks.insert(name.text.to_string(), u8::from(bits));
             }
-            undefined_labels.clear();
-            line = line.trim_start();
-            out.push(line.to_string());
-            pc += 1;
+            continue;
         }
-    }
-    Ok(out)
-}
-
-pub(crate) fn extract_n_operands<'a>(
-    n: usize,
-    operands: &mut impl Iterator<Item = &'a str>,
-    line: &str,
-) -> Result<Vec<&'a str>> {
-    let mut out = Vec::with_capacity(n);
-    for _ in 0..n {
-        match operands.next() {
-            Some(op) => out.push(op),
-            None => return Err(ParserError::MissingOperand(line.to_string()).into()),
+        for label in pending_labels.drain(..) {
+            labels.insert(label.to_string(), Bits::from(pc).resize());
         }
+        out.push(line);
+        pc += 1;
     }
-    for op in operands.by_ref() {
-        if is_comment(op) {
-            break; // stop at comment
-        }
-        out.push(op);
+    for label in pending_labels {
+        labels.insert(label.to_string(), Bits::from(pc).resize());
     }
-    Ok(out)
+    out
 }
 
 //TODO: improve error handling
 pub(crate) fn parse_offset(
     offset: &str,
-    symbols: &mut std::collections::HashMap<String, Immediate>,
-) -> Result<Bits<4>> {
+    symbols: &HashMap<String, Immediate>,
+) -> ParseResult<Bits<4>> {
     if let Some(rest) = offset.strip_prefix('-') {
         let num = rest
             .parse::<u8>()
-            .map_err(|_| ParserError::InvalidInstruction(offset.to_string()))?;
+            .map_err(|_| ParserError::InvalidImmediate(offset.to_string()))?;
+        // two's complement in 4 bits reaches down to -8
+        if num > 8 {
+            return Err(ParserError::ImmediateOutOfRange {
+                value: offset.to_string(),
+                max: 8,
+            });
+        }
         let complement = Bits::from(16 - num);
         Ok(complement.resize())
     } else if let Some(value) = symbols.get(offset) {
         Ok(value.resize())
     } else {
-        Bits::<4>::from_str(offset)
-            .map(|b| b.resize())
-            .map_err(|_| ParserError::InvalidInstruction(offset.to_string()).into())
+        Bits::<4>::from_str(offset).map_err(number_error(offset))
     }
 }
 
 pub(crate) fn parse_immediate(
-    imm: String,
-    symbols: &mut std::collections::HashMap<String, Immediate>,
-) -> Result<Bits<8>> {
+    imm: &str,
+    symbols: &HashMap<String, Immediate>,
+    io_base: u8,
+) -> ParseResult<Bits<8>> {
     // parse chars
-    if let Some(stripped) = imm.strip_prefix("\"") {
-        if let Some(char) = stripped.strip_suffix("\"") {
-            if let Some(idx) = CHARSET.find(&char.to_ascii_lowercase()) {
-                return Ok(Bits::from(idx as u8));
-            }
-        }
-    };
-
-    if let Some(stripped) = imm.strip_prefix("\'") {
-        if let Some(char) = stripped.strip_suffix("\'") {
-            if let Some(idx) = CHARSET.find(&char.to_lowercase()) {
-                return Ok(Bits::from(idx as u8));
-            }
+    for quote in ['"', '\''] {
+        if let Some(char) = imm
+            .strip_prefix(quote)
+            .and_then(|stripped| stripped.strip_suffix(quote))
+        {
+            return match CHARSET.find(&char.to_ascii_lowercase()) {
+                Some(idx) => Ok(Bits::from(idx as u8)),
+                None => Err(ParserError::InvalidImmediate(imm.to_string())),
+            };
         }
-    };
+    }
 
     // parse port names
     if let Some(idx) = PORTNAMES.iter().position(|&p| p == imm) {
-        const PORT_OFFSET: usize = 240;
-        let imm = idx + PORT_OFFSET;
-        assert!(imm < 256);
-        return Ok(Bits::from(imm).resize());
+        return Ok(Bits::from(io_base.wrapping_add(idx as u8)));
     }
 
-    if let Some(value) = symbols.get(&imm) {
+    if let Some(value) = symbols.get(imm) {
         return Ok(value.resize());
     }
-    Bits::from_str(&imm)
+    // only meaningful on machines with memory banks, so a program may use the name itself
+    if imm == BANK_SELECT {
+        return Ok(Bits::from(io_base.wrapping_sub(1)));
+    }
+    if imm.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+        return Err(ParserError::UnknownPortName(imm.to_string()));
+    }
+    Bits::from_str(imm).map_err(number_error(imm))
 }
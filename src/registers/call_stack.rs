@@ -1,8 +1,10 @@
 use crate::control_rom::CallStackState;
 use crate::registers::Register;
 use crate::Address;
-// TODO: refactor this
-const MAX_STACK_SIZE: usize = 16;
+use std::collections::VecDeque;
+
+pub(crate) const DEFAULT_STACK_DEPTH: usize = 16;
+pub(crate) const MAX_STACK_DEPTH: usize = 1 << 16;
 
 #[derive(Debug, PartialEq, Clone, Copy, Default, Eq)]
 pub enum StackState {
@@ -20,9 +22,9 @@ pub(crate) struct CallStack {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub(crate) struct BiDirectionalShiftRegister {
-    pub(crate) stack: [Address; MAX_STACK_SIZE],
+    pub(crate) stack: VecDeque<Address>, // the return addresses, most recent first
+    pub(crate) max_depth: usize,
     enabled: bool,
-    pub(crate) stack_top: Option<Address>,
     write_buffer: Option<Address>,
 }
 
@@ -40,11 +42,14 @@ impl Register for BiDirectionalShiftRegister {
         let Some(address) = self.write_buffer.take() else {
             return; // No write scheduled
         };
-        for i in (1..MAX_STACK_SIZE).rev() {
-            self.stack[i] = self.stack[i - 1];
+        if self.max_depth == 0 {
+            return;
         }
-        self.stack[0] = address;
-        self.stack_top = Some(address);
+        // a full stack shifts its oldest return address out
+        if self.stack.len() == self.max_depth {
+            self.stack.pop_back();
+        }
+        self.stack.push_front(address);
     }
 
     fn schedule_write(&mut self, write_info: Self::WriteInformation) {
@@ -62,11 +67,15 @@ impl Default for CallStack {
 
 impl CallStack {
     pub(crate) fn new() -> Self {
+        CallStack::with_depth(DEFAULT_STACK_DEPTH)
+    }
+
+    pub(crate) fn with_depth(max_depth: usize) -> Self {
         CallStack {
             stack: BiDirectionalShiftRegister {
-                stack: [Address::default(); MAX_STACK_SIZE],
+                stack: VecDeque::new(),
+                max_depth,
                 enabled: true,
-                stack_top: None,
                 write_buffer: None,
             },
             state: CallStackState::Disabled,
@@ -80,23 +89,28 @@ impl CallStack {
         self.stack.schedule_write(address);
     }
 
+    pub(crate) fn max_depth(&self) -> usize {
+        self.stack.max_depth
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.stack.stack.len()
+    }
+
+    // Another push would shift the oldest return address out
+    pub(crate) fn is_full(&self) -> bool {
+        self.depth() == self.max_depth()
+    }
+
+    // The return addresses currently on the stack, most recent first
+    pub(crate) fn contents(&self) -> impl Iterator<Item = &Address> {
+        self.stack.stack.iter()
+    }
+
     pub(crate) fn pop(&mut self) -> Option<Address> {
         if self.state != CallStackState::Pop {
             return None;
         }
-        if let Some(top) = self.stack.stack_top {
-            for i in 0..MAX_STACK_SIZE - 1 {
-                self.stack.stack[i] = self.stack.stack[i + 1];
-            }
-            self.stack.stack[MAX_STACK_SIZE - 1] = Address::default();
-            self.stack.stack_top = if top == Address::default() {
-                None
-            } else {
-                Some(self.stack.stack[0])
-            };
-            Some(top)
-        } else {
-            None
-        }
+        self.stack.stack.pop_front()
     }
 }
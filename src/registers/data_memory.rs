@@ -1,6 +1,6 @@
 use crate::{bits::Bits, registers::Register, MemoryAddress};
 
-const MEMORY_SIZE: usize = 256; // Size of the data memory in bytes
+pub(crate) const MEMORY_SIZE: usize = 256; // Size of the data memory in bytes
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub(crate) enum MemoryState {
@@ -15,18 +15,20 @@ pub struct DataMemory {
     // TODO: find appropriate type alias
     pub memory: [Bits<8>; MEMORY_SIZE], // 256 bytes of memory
     pub enabled: bool,
+    pub(crate) size: usize, // addresses from here on are unmapped: they read 0 and drop writes
+    // With more than one bank, addresses below `window` show the selected bank. `memory`
+    // always holds the visible bytes; the other banks are kept here, the selected one's
+    // slot is stale.
+    pub(crate) banks: Vec<Vec<Bits<8>>>,
+    pub(crate) bank: usize,
+    pub(crate) window: usize,
     state: MemoryState,
     write_buffer: Option<(MemoryAddress, Bits<8>)>,
 }
 
 impl Default for DataMemory {
     fn default() -> Self {
-        DataMemory {
-            memory: [Bits::from(0u8); MEMORY_SIZE],
-            enabled: true,
-            state: MemoryState::Read,
-            write_buffer: None,
-        }
+        DataMemory::with_size(MEMORY_SIZE)
     }
 }
 
@@ -46,7 +48,9 @@ impl Register for DataMemory {
                 self.write_buffer = None; // No write scheduled
                 return;
             };
-            self.memory[address.to_usize()] = data;
+            if address.to_usize() < self.size {
+                self.memory[address.to_usize()] = data;
+            }
         } else {
             self.write_buffer = None; // Clear the write buffer if not enabled
         }
@@ -60,14 +64,76 @@ impl Register for DataMemory {
 }
 
 impl DataMemory {
+    pub(crate) fn with_size(size: usize) -> Self {
+        DataMemory {
+            memory: [Bits::from(0u8); MEMORY_SIZE],
+            enabled: true,
+            size: size.min(MEMORY_SIZE),
+            banks: Vec::new(),
+            bank: 0,
+            window: 0,
+            state: MemoryState::Read,
+            write_buffer: None,
+        }
+    }
+
     pub fn read(&self, address: MemoryAddress) -> Bits<8> {
-        if self.enabled && self.state == MemoryState::Read {
+        if self.enabled && self.state == MemoryState::Read && address.to_usize() < self.size {
             self.memory[address.to_usize()]
         } else {
             Bits::from(0u8) // Return zero if not enabled or not in read state
         }
     }
 
+    // A single bank turns banking off
+    pub(crate) fn set_banks(&mut self, count: usize, window: usize) {
+        let window = window.min(MEMORY_SIZE);
+        self.banks = if count > 1 {
+            vec![vec![Bits::from(0u8); window]; count]
+        } else {
+            Vec::new()
+        };
+        self.bank = 0;
+        self.window = window;
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len().max(1)
+    }
+
+    pub fn bank_window(&self) -> usize {
+        if self.banks.is_empty() {
+            0
+        } else {
+            self.window
+        }
+    }
+
+    // Numbers past the last bank wrap around
+    pub(crate) fn select_bank(&mut self, bank: usize) {
+        let bank = bank % self.bank_count();
+        if bank == self.bank {
+            return;
+        }
+        let window = self.window;
+        self.banks[self.bank].copy_from_slice(&self.memory[..window]);
+        self.memory[..window].copy_from_slice(&self.banks[bank]);
+        self.bank = bank;
+    }
+
+    // The contents of a bank, whether it is selected or not
+    pub fn bank_contents(&self, bank: usize) -> &[Bits<8>] {
+        if bank == self.bank {
+            &self.memory[..self.bank_window()]
+        } else {
+            &self.banks[bank]
+        }
+    }
+
     pub(crate) fn set_state(&mut self, state: MemoryState) {
         self.state = state;
     }
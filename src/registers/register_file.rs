@@ -2,7 +2,7 @@ use crate::bits::Bits;
 use crate::registers::Register;
 use crate::Immediate;
 
-const REGISTER_BANK_SIZE: usize = 16; // Number of registers in each bank
+pub(crate) const REGISTER_BANK_SIZE: usize = 16; // Number of registers in each bank
 const REGISTER_SIZE: usize = 8; // Size of each register in bits
 
 type DataRegister = Bits<REGISTER_SIZE>;
@@ -12,6 +12,7 @@ type RegisterIndex = Bits<4>;
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct RegisterFile {
     pub register_banks: [RegisterBank; 2], // Two sets of 16 registers, for simulated dual read
+    pub(crate) count: usize, // registers past this one are missing: they read 0 and drop writes
     enabled: bool,
     pub(crate) write_buffer: Option<(RegisterIndex, Immediate)>,
     read_addresses: [RegisterIndex; 2],
@@ -22,6 +23,7 @@ impl Default for RegisterFile {
     fn default() -> Self {
         RegisterFile {
             register_banks: [[Bits::from(0u8); REGISTER_BANK_SIZE]; 2],
+            count: REGISTER_BANK_SIZE,
             enabled: true,
             write_buffer: None,
             read_addresses: [Bits::default(); 2],
@@ -40,10 +42,8 @@ impl Register for RegisterFile {
             self.write_buffer = None;
         } else {
             self.enabled = true;
-            for (r, index) in self.read_addresses.iter().enumerate() {
-                if self.is_valid_index(*index) {
-                    self.read_outputs[r] = self.register_banks[r][index.to_usize()];
-                }
+            for (r, &index) in self.read_addresses.iter().enumerate() {
+                self.read_outputs[r] = self.read(r, index);
             }
         }
     }
@@ -83,19 +83,31 @@ impl RegisterFile {
             ..Default::default()
         }
     }
+    pub(crate) fn with_count(count: usize) -> Self {
+        RegisterFile {
+            count: count.min(REGISTER_BANK_SIZE),
+            ..Default::default()
+        }
+    }
+
     #[inline]
     fn is_valid_index(&self, index: RegisterIndex) -> bool {
-        let index = index.to_usize();
-        index < self.register_banks[0].len()
+        index.to_usize() < self.count
+    }
+
+    fn read(&self, bank: usize, index: RegisterIndex) -> DataRegister {
+        if self.is_valid_index(index) {
+            self.register_banks[bank][index.to_usize()]
+        } else {
+            Bits::from(0u8)
+        }
     }
 
     pub(crate) fn set_read_addresses(&mut self, indexes: [RegisterIndex; 2]) {
         if self.enabled {
             for (r, &index) in indexes.iter().enumerate() {
-                if self.is_valid_index(index) {
-                    self.read_addresses[r] = index;
-                    self.read_outputs[r] = self.register_banks[r][index.to_usize()];
-                }
+                self.read_addresses[r] = index;
+                self.read_outputs[r] = self.read(r, index);
             }
         }
     }
@@ -35,3 +35,15 @@ fn read_from_0() {
     assert_eq!(reg_file.read_outputs[0], Bits::from(0u8));
     assert_eq!(reg_file.read_outputs[0], Bits::from(0u8));
 }
+
+#[test]
+fn missing_registers_read_zero() {
+    let mut reg_file = RegisterFile::with_count(4);
+
+    reg_file.schedule_write((Bits::from_str("6").unwrap(), Bits::from_str("15").unwrap()));
+    reg_file.clock();
+    reg_file.set_read_addresses([Bits::from_str("6").unwrap(), Bits::from_str("0").unwrap()]);
+
+    assert_eq!(reg_file.register_banks[0][6], Bits::from(0u8));
+    assert_eq!(reg_file.read_outputs[0], Bits::from(0u8));
+}
@@ -0,0 +1,202 @@
+// Screen snapshots for golden tests. A snapshot file is either the ASCII art printed by
+// `Screen::render` (top row first, inside a border) or a plain PBM (`P1`) image.
+use crate::error::VmError;
+use crate::io_devices::screen;
+use crate::{Access, Control, Observer, Result, StepEvent, VM};
+use std::fmt::Write as _;
+use std::path::Path;
+
+pub type Pixels = [[bool; 32]; 32];
+
+const BUFFER_SCREEN_PORT: u8 = 5; // from the IO base
+
+// set to rewrite golden files from the current output instead of comparing
+pub const UPDATE_ENV: &str = "RUST_VM_UPDATE_GOLDEN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub cycle: u64, // instructions executed when the frame was taken
+    pub pixels: Pixels,
+}
+
+// Captures the visible screen after the given instruction counts and, optionally, after
+// every write to the buffer screen port.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScreenCapture {
+    at_cycles: Vec<u64>,
+    on_buffer_screen: bool,
+    cycle: u64,
+    pub frames: Vec<Frame>,
+}
+
+impl Observer for ScreenCapture {
+    fn after_instruction(&mut self, vm: &VM, event: &StepEvent) -> Control {
+        self.cycle += 1;
+        let buffer_screen = vm.config().io_base + BUFFER_SCREEN_PORT;
+        let buffered = matches!(
+            event.io,
+            Some(Access::Write { address, .. }) if address == buffer_screen
+        );
+        if (self.on_buffer_screen && buffered) || self.at_cycles.contains(&self.cycle) {
+            self.frames.push(Frame {
+                cycle: self.cycle,
+                pixels: vm.io_devices.screen.active,
+            });
+        }
+        Control::Continue
+    }
+}
+
+impl ScreenCapture {
+    pub fn new(at_cycles: Vec<u64>, on_buffer_screen: bool) -> Self {
+        ScreenCapture {
+            at_cycles,
+            on_buffer_screen,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn to_ascii(pixels: &Pixels) -> String {
+    screen::render(pixels)
+}
+
+// lit pixels are black (1), as PBM has it
+pub fn to_pbm(pixels: &Pixels) -> String {
+    let mut out = String::from("P1\n32 32\n");
+    for row in pixels.iter().rev() {
+        let bits: Vec<&str> = row.iter().map(|&on| if on { "1" } else { "0" }).collect();
+        out.push_str(&bits.join(" "));
+        out.push('\n');
+    }
+    out
+}
+
+pub fn parse(text: &str) -> Result<Pixels> {
+    if text.starts_with("P1") {
+        parse_pbm(text)
+    } else {
+        parse_ascii(text)
+    }
+}
+
+fn parse_pbm(text: &str) -> Result<Pixels> {
+    let mut tokens = text
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .flat_map(str::split_whitespace)
+        .skip(1);
+    if (tokens.next(), tokens.next()) != (Some("32"), Some("32")) {
+        return Err(VmError::Screen("PBM image is not 32x32".to_string()));
+    }
+    // plain PBM may also write the bits without spaces
+    let mut bits = tokens.flat_map(str::chars);
+    let mut pixels = [[false; 32]; 32];
+    for row in pixels.iter_mut().rev() {
+        for pixel in row.iter_mut() {
+            *pixel = match bits.next() {
+                Some('1') => true,
+                Some('0') => false,
+                Some(other) => {
+                    return Err(VmError::Screen(format!(
+                        "unexpected '{other}' in PBM image"
+                    )))
+                }
+                None => return Err(VmError::Screen("PBM image is too short".to_string())),
+            };
+        }
+    }
+    Ok(pixels)
+}
+
+// anything other than a space inside the border is a lit pixel
+fn parse_ascii(text: &str) -> Result<Pixels> {
+    let rows: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix('|')?.strip_suffix('|'))
+        .collect();
+    if rows.len() != 32 {
+        return Err(VmError::Screen(format!(
+            "expected 32 rows between the borders, found {}",
+            rows.len()
+        )));
+    }
+    let mut pixels = [[false; 32]; 32];
+    for (row, line) in pixels.iter_mut().rev().zip(rows) {
+        let cells: Vec<char> = line.chars().collect();
+        if cells.len() != 32 {
+            return Err(VmError::Screen(format!(
+                "expected rows of 32 pixels, found {}",
+                cells.len()
+            )));
+        }
+        for (pixel, cell) in row.iter_mut().zip(cells) {
+            *pixel = cell != ' ';
+        }
+    }
+    Ok(pixels)
+}
+
+// The format follows the extension: `.pbm` or ASCII art for anything else
+pub fn save(file_path: impl AsRef<Path>, pixels: &Pixels) -> Result<()> {
+    let file_path = file_path.as_ref();
+    let text = match file_path.extension() {
+        Some(extension) if extension == "pbm" => to_pbm(pixels),
+        _ => to_ascii(pixels),
+    };
+    std::fs::write(file_path, text)?;
+    Ok(())
+}
+
+pub fn load(file_path: impl AsRef<Path>) -> Result<Pixels> {
+    parse(&std::fs::read_to_string(file_path)?)
+}
+
+// None when the screens match. Otherwise lists the differing pixels as (x, y) and draws
+// the actual screen with `+` for unexpected and `-` for missing pixels.
+pub fn diff(expected: &Pixels, actual: &Pixels) -> Option<String> {
+    let mut differing = Vec::new();
+    let mut art = String::new();
+    for y in (0..32).rev() {
+        art.push('|');
+        for x in 0..32 {
+            art.push(match (expected[y][x], actual[y][x]) {
+                (true, true) => '█',
+                (false, false) => ' ',
+                (false, true) => '+',
+                (true, false) => '-',
+            });
+            if expected[y][x] != actual[y][x] {
+                differing.push((x, y));
+            }
+        }
+        art.push_str("|\n");
+    }
+    if differing.is_empty() {
+        return None;
+    }
+    let mut out = format!("{} differing pixels:", differing.len());
+    for (x, y) in differing.iter().take(16) {
+        let _ = write!(out, " ({x}, {y})");
+    }
+    if differing.len() > 16 {
+        out.push_str(" ...");
+    }
+    let border = format!("+{}+\n", "-".repeat(32));
+    let _ = write!(out, "\n{border}{art}{border}");
+    Some(out)
+}
+
+// Compares a screen with a golden file, or rewrites the file when `UPDATE_ENV` is set.
+// Returns the diff on a mismatch.
+pub fn check_golden(file_path: impl AsRef<Path>, actual: &Pixels) -> Result<Option<String>> {
+    let file_path = file_path.as_ref();
+    if std::env::var_os(UPDATE_ENV).is_some() {
+        save(file_path, actual)?;
+        return Ok(None);
+    }
+    Ok(diff(&load(file_path)?, actual))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,83 @@
+use super::{diff, parse, to_ascii, to_pbm, Pixels, ScreenCapture};
+use crate::error::VmError;
+use crate::VM;
+
+// an L in the bottom left corner
+fn corner() -> Pixels {
+    let mut pixels = [[false; 32]; 32];
+    pixels[0][0] = true;
+    pixels[0][1] = true;
+    pixels[1][0] = true;
+    pixels
+}
+
+#[test]
+fn formats_round_trip() {
+    let pixels = corner();
+    assert_eq!(parse(&to_ascii(&pixels)).unwrap(), pixels);
+    assert_eq!(parse(&to_pbm(&pixels)).unwrap(), pixels);
+    let pbm = to_pbm(&pixels);
+    assert!(pbm.ends_with("1 1 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0 0\n"));
+}
+
+#[test]
+fn compact_pbm_with_comments() {
+    let mut text = String::from("P1\n# golden\n32 32\n");
+    for _ in 0..31 {
+        text.push_str(&"0".repeat(32));
+        text.push('\n');
+    }
+    text.push_str(&format!("1{}\n", "0".repeat(31)));
+    let mut expected = [[false; 32]; 32];
+    expected[0][0] = true;
+    assert_eq!(parse(&text).unwrap(), expected);
+}
+
+#[test]
+fn invalid_files() {
+    assert_eq!(
+        parse("P1\n16 16\n"),
+        Err(VmError::Screen("PBM image is not 32x32".to_string()))
+    );
+    assert_eq!(
+        parse("P1\n32 32\n1 0"),
+        Err(VmError::Screen("PBM image is too short".to_string()))
+    );
+    assert_eq!(
+        parse("|  |\n"),
+        Err(VmError::Screen(
+            "expected 32 rows between the borders, found 1".to_string()
+        ))
+    );
+}
+
+#[test]
+fn readable_diff() {
+    let expected = corner();
+    assert_eq!(diff(&expected, &expected), None);
+    let mut actual = expected;
+    actual[1][0] = false;
+    actual[31][31] = true;
+    let diff = diff(&expected, &actual).unwrap();
+    assert!(diff.starts_with("2 differing pixels: (31, 31) (0, 1)\n"));
+    assert!(diff.contains(&format!("|{}+|", " ".repeat(31))));
+    assert!(diff.contains(&format!("|-{}|", " ".repeat(31))));
+    assert!(diff.contains(&format!("|██{}|", " ".repeat(30))));
+}
+
+#[test]
+fn captures_frames() {
+    let mut vm = VM::new();
+    vm.load_source(
+        "LDI r15 240\nSTR r15 r0 2\nSTR r15 r0 5\nLDI r1 1\nSTR r15 r1 0\nSTR r15 r0 2\nSTR r15 r0 5\nHLT",
+    )
+    .unwrap();
+    vm.add_observer(ScreenCapture::new(vec![2, 7], true));
+    vm.run().unwrap();
+    let frames = vm.remove_observer::<ScreenCapture>().unwrap().frames;
+    let cycles: Vec<u64> = frames.iter().map(|frame| frame.cycle).collect();
+    assert_eq!(cycles, vec![2, 3, 7]);
+    assert!(!frames[0].pixels[0][0]);
+    assert!(frames[1].pixels[0][0]);
+    assert!(frames[2].pixels[0][1]);
+}
@@ -0,0 +1,302 @@
+// Expectations written next to a program as comments, e.g.
+//
+//     // @set r1 = 7
+//     // @set mem[0] = 6
+//     // @cycles 5000
+//     // @seed 42
+//     // @expect r3 == 9
+//     // @expect mem[200] == 5
+//     // @expect chars == "hello"
+//     // @expect number == -55
+//     // @expect screen == logo.screen
+//     // @expect halted
+//
+// A screen file holds the output of `Screen::render`, as printed by `run --screen-ascii`,
+// or a PBM image, see `snapshot`.
+use crate::error::VmError;
+use crate::snapshot::{self, Pixels};
+use crate::{Result, VM};
+use std::path::Path;
+
+pub const DEFAULT_CYCLES: u64 = 100_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Preset {
+    Register(u8, u8),
+    Memory(u8, u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expectation {
+    Register(u8, u8),
+    Memory(u8, u8),
+    Chars(String),
+    Number(String),
+    Screen(Box<Pixels>),
+    Halted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Spec {
+    pub cycles: u64,
+    pub seed: Option<u64>, // RNG seed, random when not given
+    pub extended_alu: bool,
+    pub presets: Vec<Preset>,
+    pub expectations: Vec<Expectation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpecReport {
+    pub cycles: u64,
+    pub halted: bool,
+    pub failures: Vec<String>,
+}
+
+impl SpecReport {
+    pub fn passed(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+impl Spec {
+    // None when the source has no directives. Screen files are looked up in `dir`.
+    pub fn parse(source: &str, dir: &Path) -> Result<Option<Spec>> {
+        let mut spec = Spec {
+            cycles: DEFAULT_CYCLES,
+            seed: None,
+            extended_alu: false,
+            presets: Vec::new(),
+            expectations: Vec::new(),
+        };
+        let mut found = false;
+        for (number, line) in source.lines().enumerate() {
+            let Some(directive) = directive(line) else {
+                continue;
+            };
+            found = true;
+            spec.add(directive, dir)
+                .map_err(|reason| VmError::Spec(format!("line {}: {reason}", number + 1)))?;
+        }
+        Ok(found.then_some(spec))
+    }
+
+    fn add(&mut self, directive: &str, dir: &Path) -> std::result::Result<(), String> {
+        let (keyword, rest) = directive
+            .split_once(char::is_whitespace)
+            .unwrap_or((directive, ""));
+        let rest = rest.trim();
+        match keyword {
+            "cycles" => self.cycles = parse_number(rest)?,
+            "seed" => self.seed = Some(parse_number(rest)?),
+            "set" => {
+                let (target, value) = split(rest, "=")?;
+                let value = parse_byte(value)?;
+                let preset = match parse_location(target)? {
+                    Location::Register(0) => return Err("r0 cannot be set".to_string()),
+                    Location::Register(register) => Preset::Register(register, value),
+                    Location::Memory(address) => Preset::Memory(address, value),
+                };
+                self.presets.push(preset);
+            }
+            "expect" if rest == "halted" => self.expectations.push(Expectation::Halted),
+            "expect" => {
+                let (target, value) = split(rest, "==")?;
+                let expectation = match target {
+                    "chars" => Expectation::Chars(unquote(value).to_string()),
+                    "number" => Expectation::Number(unquote(value).to_string()),
+                    "screen" => {
+                        let path = dir.join(unquote(value));
+                        let screen = snapshot::load(&path)
+                            .map_err(|e| format!("cannot read {}: {e}", path.display()))?;
+                        Expectation::Screen(Box::new(screen))
+                    }
+                    _ => match parse_location(target)? {
+                        Location::Register(register) => {
+                            Expectation::Register(register, parse_byte(value)?)
+                        }
+                        Location::Memory(address) => {
+                            Expectation::Memory(address, parse_byte(value)?)
+                        }
+                    },
+                };
+                self.expectations.push(expectation);
+            }
+            _ => return Err(format!("unknown directive '@{keyword}'")),
+        }
+        Ok(())
+    }
+
+    pub fn apply(&self, vm: &mut VM) {
+        for preset in &self.presets {
+            match *preset {
+                Preset::Register(register, value) => vm.set_register(register, value),
+                Preset::Memory(address, value) => {
+                    vm.data_memory.memory[address as usize] = value.into()
+                }
+            }
+        }
+    }
+
+    // One message per expectation the machine does not meet
+    pub fn check(&self, vm: &VM) -> Vec<String> {
+        let mut failures = Vec::new();
+        for expectation in &self.expectations {
+            let (name, expected, found) = match expectation {
+                Expectation::Register(register, value) => (
+                    format!("r{register}"),
+                    value.to_string(),
+                    u8::from(vm.reg_file.register_banks[0][*register as usize]).to_string(),
+                ),
+                Expectation::Memory(address, value) => (
+                    format!("mem[{address}]"),
+                    value.to_string(),
+                    u8::from(vm.data_memory.memory[*address as usize]).to_string(),
+                ),
+                Expectation::Chars(text) => (
+                    "chars".to_string(),
+                    format!("{text:?}"),
+                    format!("{:?}", vm.io_devices.character_display.active),
+                ),
+                Expectation::Number(text) => (
+                    "number".to_string(),
+                    text.clone(),
+                    vm.io_devices.number_display.get_display_val(),
+                ),
+                Expectation::Screen(screen) => {
+                    if let Some(diff) = snapshot::diff(screen, &vm.io_devices.screen.active) {
+                        failures.push(format!("screen: {diff}"));
+                    }
+                    continue;
+                }
+                // checked by `run`, which knows whether the program halted
+                Expectation::Halted => continue,
+            };
+            if expected != found {
+                failures.push(format!("{name}: expected {expected}, found {found}"));
+            }
+        }
+        failures
+    }
+
+    // Runs a fresh machine until it halts or has run `cycles` instructions. Not halting
+    // in time only fails a spec that expects it.
+    pub fn run(&self, source: &str) -> Result<SpecReport> {
+        let mut vm = VM::with_config(crate::VmConfig {
+            rng_seed: self.seed,
+            extended_alu: self.extended_alu,
+            ..Default::default()
+        });
+        vm.load_source(source)?;
+        self.apply(&mut vm);
+        let mut cycles = 0;
+        let mut halted = false;
+        while cycles < self.cycles && !halted {
+            halted = vm.step()? == crate::OPCODE_HLT;
+            cycles += 1;
+        }
+        let mut failures = Vec::new();
+        if !halted && self.expectations.contains(&Expectation::Halted) {
+            failures.push(format!("did not halt within {} cycles", self.cycles));
+        }
+        failures.extend(self.check(&vm));
+        Ok(SpecReport {
+            cycles,
+            halted,
+            failures,
+        })
+    }
+}
+
+// Loads a program and runs its spec. None when the program has no directives.
+pub fn run_file(file_path: impl AsRef<Path>) -> Result<Option<SpecReport>> {
+    run_file_with(file_path, false)
+}
+
+// On a machine with the extended ALU
+pub fn run_file_with(
+    file_path: impl AsRef<Path>,
+    extended_alu: bool,
+) -> Result<Option<SpecReport>> {
+    let file_path = file_path.as_ref();
+    let source = std::fs::read_to_string(file_path)?;
+    let dir = file_path.parent().unwrap_or(Path::new(""));
+    match Spec::parse(&source, dir)? {
+        Some(spec) => Ok(Some(
+            Spec {
+                extended_alu,
+                ..spec
+            }
+            .run(&source)?,
+        )),
+        None => Ok(None),
+    }
+}
+
+// The text after `@` in a comment, if the line has one
+fn directive(line: &str) -> Option<&str> {
+    let start = [line.find("//"), line.find('#')]
+        .into_iter()
+        .flatten()
+        .min()?;
+    let comment = line[start..].trim_start_matches(['/', '#']).trim();
+    comment.strip_prefix('@').map(str::trim)
+}
+
+enum Location {
+    Register(u8),
+    Memory(u8),
+}
+
+fn parse_location(target: &str) -> std::result::Result<Location, String> {
+    if let Some(index) = target.strip_prefix('r') {
+        if let Ok(index @ 0..=15) = index.parse::<u8>() {
+            return Ok(Location::Register(index));
+        }
+    }
+    if let Some(address) = target
+        .strip_prefix("mem[")
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        return Ok(Location::Memory(parse_number(address)?));
+    }
+    Err(format!(
+        "unknown target '{target}', expected rN, mem[N], chars, number or screen"
+    ))
+}
+
+fn split<'a>(text: &'a str, operator: &str) -> std::result::Result<(&'a str, &'a str), String> {
+    text.split_once(operator)
+        .map(|(left, right)| (left.trim(), right.trim()))
+        .ok_or(format!(
+            "expected 'TARGET {operator} VALUE', found '{text}'"
+        ))
+}
+
+fn unquote(text: &str) -> &str {
+    text.strip_prefix('"')
+        .and_then(|text| text.strip_suffix('"'))
+        .unwrap_or(text)
+}
+
+// decimal, or hexadecimal with a 0x prefix; the command line reads numbers this way too
+pub fn parse_number<T: TryFrom<u64>>(text: &str) -> std::result::Result<T, String> {
+    let parsed = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed
+        .ok()
+        .and_then(|number| T::try_from(number).ok())
+        .ok_or(format!("invalid number '{text}'"))
+}
+
+// negative values are stored in two's complement
+fn parse_byte(text: &str) -> std::result::Result<u8, String> {
+    match text.parse::<i8>() {
+        Ok(value) if value < 0 => Ok(value as u8),
+        _ => parse_number(text),
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,126 @@
+use super::{Expectation, Preset, Spec};
+use crate::error::VmError;
+use std::path::Path;
+
+const PROGRAM: &str = "\
+// @set r1 = 4
+// @set mem[0x10] = 0xFF
+// @cycles 50
+// @seed 9
+ADD r1 r1 r2 // @expect r2 == 8
+LDI r4 16
+LOD r4 r3
+LDI r4 200
+STR r4 r2
+LDI r15 250
+STR r15 r3
+HLT
+# @expect mem[200] == 8
+# @expect number == 255
+# @expect r3 == -1
+";
+
+#[test]
+fn parses_directives() {
+    let spec = Spec::parse(PROGRAM, Path::new("")).unwrap().unwrap();
+    assert_eq!(spec.cycles, 50);
+    assert_eq!(spec.seed, Some(9));
+    assert_eq!(
+        spec.presets,
+        vec![Preset::Register(1, 4), Preset::Memory(16, 255)]
+    );
+    assert_eq!(
+        spec.expectations,
+        vec![
+            Expectation::Register(2, 8),
+            Expectation::Memory(200, 8),
+            Expectation::Number("255".to_string()),
+            Expectation::Register(3, 255),
+        ]
+    );
+}
+
+#[test]
+fn no_directives() {
+    assert_eq!(
+        Spec::parse("LDI r1 1 // one\nHLT", Path::new("")).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn passing_spec() {
+    let spec = Spec::parse(PROGRAM, Path::new("")).unwrap().unwrap();
+    let report = spec.run(PROGRAM).unwrap();
+    assert!(report.passed(), "{:?}", report.failures);
+    assert!(report.halted);
+    assert_eq!(report.cycles, 8);
+}
+
+#[test]
+fn failing_spec() {
+    let source = "LDI r1 3\n// @expect r1 == 4\n// @expect chars == \"hi\"\n// @expect halted\n.loop\nJMP .loop";
+    let spec = Spec::parse(source, Path::new("")).unwrap().unwrap();
+    let report = spec.run(source).unwrap();
+    assert!(!report.halted);
+    assert_eq!(
+        report.failures,
+        vec![
+            "did not halt within 100000 cycles",
+            "r1: expected 4, found 3",
+            "chars: expected \"hi\", found \"\"",
+        ]
+    );
+}
+
+#[test]
+fn halting_is_only_checked_when_expected() {
+    let source = "LDI r1 3\n// @cycles 50\n// @expect r1 == 3\n.loop\nJMP .loop";
+    let spec = Spec::parse(source, Path::new("")).unwrap().unwrap();
+    let report = spec.run(source).unwrap();
+    assert!(!report.halted);
+    assert_eq!(report.cycles, 50);
+    assert!(report.passed(), "{:?}", report.failures);
+}
+
+#[test]
+fn screen_file() {
+    let dir = std::env::temp_dir();
+    let name = format!("rust_vm_spec_{}.screen", std::process::id());
+    std::fs::write(dir.join(&name), crate::VM::new().io_devices.screen.render()).unwrap();
+    let source = format!("HLT // @expect screen == {name}");
+    let spec = Spec::parse(&source, &dir).unwrap().unwrap();
+    std::fs::remove_file(dir.join(&name)).unwrap();
+    assert!(spec.run(&source).unwrap().passed());
+
+    let source = "LDI r15 240\nSTR r15 r0 2\nSTR r15 r0 5\nHLT";
+    assert_eq!(spec.run(source).unwrap().failures.len(), 1);
+}
+
+#[test]
+fn invalid_directives() {
+    let parse = |source: &str| Spec::parse(source, Path::new("")).unwrap_err();
+    assert_eq!(
+        parse("HLT\n// @expect r16 == 1"),
+        VmError::Spec(
+            "line 2: unknown target 'r16', expected rN, mem[N], chars, number or screen"
+                .to_string()
+        )
+    );
+    assert_eq!(
+        parse("// @set r0 = 1"),
+        VmError::Spec("line 1: r0 cannot be set".to_string())
+    );
+    assert_eq!(
+        parse("// @expect r1 = 1"),
+        VmError::Spec("line 1: expected 'TARGET == VALUE', found 'r1 = 1'".to_string())
+    );
+    assert_eq!(
+        parse("// @expect mem[3] == 256"),
+        VmError::Spec("line 1: invalid number '256'".to_string())
+    );
+    assert_eq!(
+        parse("// @check r1"),
+        VmError::Spec("line 1: unknown directive '@check'".to_string())
+    );
+}
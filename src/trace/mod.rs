@@ -0,0 +1,211 @@
+use crate::error::{RuntimeError, VmError};
+use crate::instruction::Instruction;
+use crate::{Access, AluFlags, Control, Observer, Result, StepEvent, VM};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+const MAGIC: &[u8; 4] = b"BPT2";
+const RECORD_SIZE: usize = 16;
+const EXTENDED_ALU: u8 = 1 << 5;
+
+// Records every executed instruction. Install it with `VM::add_observer` and take it back
+// out with `VM::remove_observer::<Tracer>()` once the program has run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Tracer {
+    pub records: Vec<StepEvent>,
+}
+
+impl Observer for Tracer {
+    fn after_instruction(&mut self, _vm: &VM, event: &StepEvent) -> Control {
+        self.records.push(*event);
+        Control::Continue
+    }
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Tracer::default()
+    }
+
+    pub fn write_jsonl(&self, mut out: impl Write) -> io::Result<()> {
+        for record in &self.records {
+            writeln!(out, "{}", to_json(record))?;
+        }
+        Ok(())
+    }
+
+    // 4 magic bytes followed by fixed-size little-endian records, see `encode_record`
+    pub fn write_binary(&self, mut out: impl Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        for record in &self.records {
+            out.write_all(&encode_record(record))?;
+        }
+        Ok(())
+    }
+
+    pub fn read_binary(bytes: &[u8]) -> Result<Self> {
+        let body = bytes
+            .strip_prefix(MAGIC)
+            .ok_or_else(|| VmError::Trace("missing BPT2 header".to_string()))?;
+        let trailing = body.len() % RECORD_SIZE;
+        if trailing != 0 {
+            return Err(VmError::Trace(format!(
+                "{trailing} trailing bytes after the last record"
+            )));
+        }
+        let records = body.chunks_exact(RECORD_SIZE).map(decode_record).collect();
+        Ok(Tracer { records })
+    }
+
+    // Index of the first record where two traces disagree, if any
+    pub fn first_difference(&self, other: &Tracer) -> Option<usize> {
+        let mismatch = self
+            .records
+            .iter()
+            .zip(&other.records)
+            .position(|(a, b)| a != b);
+        match mismatch {
+            Some(index) => Some(index),
+            None if self.records.len() != other.records.len() => {
+                Some(self.records.len().min(other.records.len()))
+            }
+            None => None,
+        }
+    }
+}
+
+pub fn to_json(record: &StepEvent) -> String {
+    let mut out = format!(
+        "{{\"pc\":{},\"word\":\"{}\",\"asm\":\"{}\"",
+        record.pc, record.word, record.instruction
+    );
+    match record.register_write {
+        Some((register, value)) => {
+            let _ = write!(out, ",\"reg\":{{\"r\":{register},\"value\":{value}}}");
+        }
+        None => out.push_str(",\"reg\":null"),
+    }
+    let _ = write!(out, ",\"mem\":{}", access_json(record.memory));
+    let _ = write!(out, ",\"io\":{}", access_json(record.io));
+    let _ = write!(
+        out,
+        ",\"zero\":{},\"carry\":{}",
+        record.flags.zero, record.flags.carry
+    );
+    let _ = write!(
+        out,
+        ",\"flags_changed\":{},\"next_pc\":{}",
+        record.flags != record.flags_before,
+        record.next_pc
+    );
+    match record.fault {
+        Some(fault) => {
+            let _ = write!(out, ",\"fault\":\"{fault}\"}}");
+        }
+        None => out.push_str(",\"fault\":null}"),
+    }
+    out
+}
+
+fn access_json(access: Option<Access>) -> String {
+    match access {
+        Some(Access::Read { address, value }) => {
+            format!("{{\"op\":\"read\",\"addr\":{address},\"value\":{value}}}")
+        }
+        Some(Access::Write { address, value }) => {
+            format!("{{\"op\":\"write\",\"addr\":{address},\"value\":{value}}}")
+        }
+        None => "null".to_string(),
+    }
+}
+
+// pc, word, next_pc (u16 each), flag bits, access bits, then register, memory and io
+// (address, value) pairs and the emulated fault with its port. Pairs that did not happen are written
+// as zeroes. Bit 5 of the access bits marks words decoded by the extended ALU.
+fn encode_record(record: &StepEvent) -> [u8; RECORD_SIZE] {
+    let mut bytes = [0; RECORD_SIZE];
+    bytes[0..2].copy_from_slice(&record.pc.to_le_bytes());
+    bytes[2..4].copy_from_slice(&u16::from(record.word).to_le_bytes());
+    bytes[4..6].copy_from_slice(&record.next_pc.to_le_bytes());
+    bytes[6] = flag_bits(record.flags_before) | flag_bits(record.flags) << 2;
+    let (register, value) = record.register_write.unwrap_or_default();
+    bytes[8] = register;
+    bytes[9] = value;
+    bytes[7] = u8::from(record.register_write.is_some());
+    for (shift, access, offset) in [(1, record.memory, 10), (3, record.io, 12)] {
+        let (kind, address, value) = match access {
+            Some(Access::Read { address, value }) => (0b01, address, value),
+            Some(Access::Write { address, value }) => (0b11, address, value),
+            None => (0b00, 0, 0),
+        };
+        bytes[7] |= kind << shift;
+        bytes[offset] = address;
+        bytes[offset + 1] = value;
+    }
+    if record.instruction != Instruction::decode(record.word) {
+        bytes[7] |= EXTENDED_ALU;
+    }
+    [bytes[14], bytes[15]] = encode_fault(record.fault);
+    bytes
+}
+
+// The port is the original port number, not the io address, which moves with `io_base`
+fn encode_fault(fault: Option<RuntimeError>) -> [u8; 2] {
+    match fault {
+        None => [0, 0],
+        Some(RuntimeError::CallStackUnderflow) => [1, 0],
+        Some(RuntimeError::CallStackOverflow) => [2, 0],
+        Some(RuntimeError::PcOutOfBounds) => [3, 0],
+        Some(RuntimeError::WriteToReadOnlyPort(port)) => [4, port],
+        Some(RuntimeError::ReadFromWriteOnlyPort(port)) => [5, port],
+    }
+}
+
+fn decode_fault(code: u8, port: u8) -> Option<RuntimeError> {
+    match code {
+        1 => Some(RuntimeError::CallStackUnderflow),
+        2 => Some(RuntimeError::CallStackOverflow),
+        3 => Some(RuntimeError::PcOutOfBounds),
+        4 => Some(RuntimeError::WriteToReadOnlyPort(port)),
+        5 => Some(RuntimeError::ReadFromWriteOnlyPort(port)),
+        _ => None,
+    }
+}
+
+fn decode_record(bytes: &[u8]) -> StepEvent {
+    let word = u16::from_le_bytes([bytes[2], bytes[3]]);
+    let access = |shift: u8, offset: usize| {
+        let (address, value) = (bytes[offset], bytes[offset + 1]);
+        match (bytes[7] >> shift) & 0b11 {
+            0b01 => Some(Access::Read { address, value }),
+            0b11 => Some(Access::Write { address, value }),
+            _ => None,
+        }
+    };
+    StepEvent {
+        pc: u16::from_le_bytes([bytes[0], bytes[1]]),
+        word: word.into(),
+        instruction: Instruction::decode_with(word.into(), bytes[7] & EXTENDED_ALU != 0),
+        next_pc: u16::from_le_bytes([bytes[4], bytes[5]]),
+        register_write: (bytes[7] & 1 == 1).then_some((bytes[8], bytes[9])),
+        memory: access(1, 10),
+        io: access(3, 12),
+        flags_before: flags_from_bits(bytes[6]),
+        flags: flags_from_bits(bytes[6] >> 2),
+        fault: decode_fault(bytes[14], bytes[15]),
+    }
+}
+
+fn flag_bits(flags: AluFlags) -> u8 {
+    u8::from(flags.zero) | u8::from(flags.carry) << 1
+}
+
+fn flags_from_bits(bits: u8) -> AluFlags {
+    AluFlags {
+        zero: bits & 1 == 1,
+        carry: bits & 2 == 2,
+    }
+}
+
+#[cfg(test)]
+mod tests;
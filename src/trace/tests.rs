@@ -0,0 +1,107 @@
+use super::{to_json, Tracer};
+use crate::error::{RuntimeError, VmError};
+use crate::{FaultPolicy, VM};
+
+const PROGRAM: &str = "\
+LDI r1 5
+STR r0 r1 3
+LOD r0 r2 3
+LDI r3 show_number
+STR r3 r1
+CMP r1 r2
+HLT
+";
+
+fn trace(source: &str) -> Tracer {
+    let mut vm = VM::new();
+    vm.load_source(source).unwrap();
+    vm.add_observer(Tracer::new());
+    vm.run().unwrap();
+    vm.remove_observer().unwrap()
+}
+
+#[test]
+fn jsonl_lines() {
+    let tracer = trace(PROGRAM);
+    let mut out = vec![];
+    tracer.write_jsonl(&mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 7);
+    assert_eq!(
+        lines[0],
+        "{\"pc\":0,\"word\":\"1000000100000101\",\"asm\":\"LDI r1 5\",\"reg\":{\"r\":1,\"value\":5},\
+         \"mem\":null,\"io\":null,\"zero\":false,\"carry\":false,\"flags_changed\":false,\"next_pc\":1,\"fault\":null}"
+    );
+    assert!(lines[1].contains("\"mem\":{\"op\":\"write\",\"addr\":3,\"value\":5}"));
+    assert!(lines[4].contains("\"io\":{\"op\":\"write\",\"addr\":250,\"value\":5}"));
+    assert!(to_json(&tracer.records[5]).contains("\"flags_changed\":true"));
+}
+
+#[test]
+fn binary_round_trip() {
+    let tracer = trace(PROGRAM);
+    let mut out = vec![];
+    tracer.write_binary(&mut out).unwrap();
+    assert_eq!(out.len(), 4 + 16 * 7);
+    assert_eq!(Tracer::read_binary(&out).unwrap(), tracer);
+}
+
+#[test]
+fn binary_round_trip_keeps_extended_alu_functions() {
+    let mut vm = VM::builder().extended_alu(true).build().unwrap();
+    vm.load_source("LDI r1 3\nOR r1 r2\nRSH r1 r3\nHLT")
+        .unwrap();
+    vm.add_observer(Tracer::new());
+    vm.run().unwrap();
+    let tracer: Tracer = vm.remove_observer().unwrap();
+    assert_eq!(tracer.records[1].instruction.to_string(), "OR r1 r2");
+    let mut out = vec![];
+    tracer.write_binary(&mut out).unwrap();
+    assert_eq!(Tracer::read_binary(&out).unwrap(), tracer);
+}
+
+#[test]
+fn binary_round_trip_keeps_fault_ports_with_a_moved_io_base() {
+    let mut vm = VM::builder()
+        .io_base(128)
+        .fault_policy(FaultPolicy::Emulate)
+        .build()
+        .unwrap();
+    vm.load_source("LDI r1 142\nSTR r1 r2\nHLT").unwrap();
+    vm.add_observer(Tracer::new());
+    vm.run().unwrap();
+    let tracer: Tracer = vm.remove_observer().unwrap();
+    assert_eq!(
+        tracer.records[1].fault,
+        Some(RuntimeError::WriteToReadOnlyPort(254))
+    );
+    let mut out = vec![];
+    tracer.write_binary(&mut out).unwrap();
+    assert_eq!(Tracer::read_binary(&out).unwrap(), tracer);
+}
+
+#[test]
+fn invalid_binary() {
+    assert_eq!(
+        Tracer::read_binary(b"nope"),
+        Err(VmError::Trace("missing BPT2 header".to_string()))
+    );
+    assert_eq!(
+        Tracer::read_binary(b"BPT2abc"),
+        Err(VmError::Trace(
+            "3 trailing bytes after the last record".to_string()
+        ))
+    );
+}
+
+#[test]
+fn first_difference() {
+    let tracer = trace(PROGRAM);
+    assert_eq!(tracer.first_difference(&tracer), None);
+    let other = trace(&PROGRAM.replace("LDI r1 5", "LDI r1 6"));
+    assert_eq!(tracer.first_difference(&other), Some(0));
+    let mut shorter = tracer.clone();
+    shorter.records.truncate(3);
+    assert_eq!(tracer.first_difference(&shorter), Some(3));
+}
@@ -0,0 +1,178 @@
+// Builds machines that differ from the original BatPU-2: smaller memories, fewer
+// registers, a smaller screen, the IO ports somewhere else or devices left out. Nothing
+// can grow past what the instruction encoding addresses. User devices are attached on top
+// of the built-in ones.
+use super::config::{FaultPolicy, VmConfig};
+use super::fast::FastVm;
+use crate::control_rom::ControlRom;
+use crate::error::VmError;
+use crate::instruction_memory::INSTRUCTION_MEMORY_SIZE;
+use crate::io_devices::rng::RngSource;
+use crate::io_devices::screen::SCREEN_SIZE;
+use crate::io_devices::{AttachedDevices, Device, IO_PORTS};
+use crate::registers::call_stack::MAX_STACK_DEPTH;
+use crate::registers::data_memory::MEMORY_SIZE;
+use crate::registers::register_file::REGISTER_BANK_SIZE;
+use crate::{Result, VM};
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Default)]
+pub struct VmBuilder {
+    config: VmConfig,
+    devices: Vec<(RangeInclusive<u8>, Box<dyn Device>)>,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        VmBuilder::default()
+    }
+
+    pub fn from_config(config: VmConfig) -> Self {
+        VmBuilder {
+            config,
+            ..Default::default()
+        }
+    }
+
+    pub fn fault_policy(mut self, fault_policy: FaultPolicy) -> Self {
+        self.config.fault_policy = fault_policy;
+        self
+    }
+
+    pub fn stack_depth(mut self, depth: usize) -> Self {
+        self.config.stack_depth = depth;
+        self
+    }
+
+    pub fn rng_seed(mut self, seed: u64) -> Self {
+        self.config.rng_seed = Some(seed);
+        self
+    }
+
+    pub fn rng_source(mut self, source: RngSource) -> Self {
+        self.config.rng_source = source;
+        self
+    }
+
+    pub fn instruction_memory(mut self, words: usize) -> Self {
+        self.config.instruction_memory_size = words;
+        self
+    }
+
+    pub fn data_memory(mut self, bytes: usize) -> Self {
+        self.config.data_memory_size = bytes;
+        self
+    }
+
+    pub fn registers(mut self, count: usize) -> Self {
+        self.config.register_count = count;
+        self
+    }
+
+    pub fn io_base(mut self, address: u8) -> Self {
+        self.config.io_base = address;
+        self
+    }
+
+    pub fn screen(mut self, width: usize, height: usize) -> Self {
+        self.config.screen_width = width;
+        self.config.screen_height = height;
+        self
+    }
+
+    pub fn devices(mut self, devices: AttachedDevices) -> Self {
+        self.config.devices = devices;
+        self
+    }
+
+    // Ports are numbered 240..=255 whatever the IO base; the device replaces anything
+    // attached before on the same ports
+    pub fn device(mut self, ports: RangeInclusive<u8>, device: impl Device) -> Self {
+        self.devices.push((ports, Box::new(device)));
+        self
+    }
+
+    // Banks of `window` bytes at the bottom of data memory, switched through the
+    // bank-select register at `io_base - 1`
+    pub fn memory_banks(mut self, count: usize, window: usize) -> Self {
+        self.config.memory_banks = count;
+        self.config.bank_window = window;
+        self
+    }
+
+    pub fn extended_alu(mut self, enabled: bool) -> Self {
+        self.config.extended_alu = enabled;
+        self
+    }
+
+    pub fn control_rom(mut self, control_rom: ControlRom) -> Self {
+        self.config.control_rom = control_rom;
+        self
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    pub fn build(self) -> Result<VM> {
+        check_config(&self.config)?;
+        let mut vm = VM::with_config(self.config);
+        for (ports, device) in self.devices {
+            vm.io_devices.bus.attach(ports, device)?;
+        }
+        Ok(vm)
+    }
+
+    // The same machine on the pre-decoded integer core
+    pub fn build_fast(self) -> Result<FastVm> {
+        Ok(FastVm::from_vm(&self.build()?))
+    }
+}
+
+fn check_range(name: &str, value: usize, min: usize, max: usize) -> Result<()> {
+    if (min..=max).contains(&value) {
+        Ok(())
+    } else {
+        Err(VmError::Config(format!(
+            "{name} must be between {min} and {max}, found {value}"
+        )))
+    }
+}
+
+// Also checks the call stacks of restored state files
+pub(crate) fn check_stack_depth(depth: usize) -> Result<()> {
+    check_range("call stack depth", depth, 1, MAX_STACK_DEPTH)
+}
+
+fn check_config(config: &VmConfig) -> Result<()> {
+    check_range(
+        "instruction memory size",
+        config.instruction_memory_size,
+        1,
+        INSTRUCTION_MEMORY_SIZE,
+    )?;
+    check_range("data memory size", config.data_memory_size, 0, MEMORY_SIZE)?;
+    check_range(
+        "register count",
+        config.register_count,
+        1,
+        REGISTER_BANK_SIZE,
+    )?;
+    check_stack_depth(config.stack_depth)?;
+    check_range("screen width", config.screen_width, 1, SCREEN_SIZE)?;
+    check_range("screen height", config.screen_height, 1, SCREEN_SIZE)?;
+    let last_base = MEMORY_SIZE - IO_PORTS as usize;
+    if config.io_base as usize > last_base {
+        return Err(VmError::Config(format!(
+            "IO base {} leaves no room for {IO_PORTS} ports, it can be at most {last_base}",
+            config.io_base
+        )));
+    }
+    check_range("memory bank count", config.memory_banks, 1, MEMORY_SIZE)?;
+    if config.memory_banks > 1 {
+        // the window ends below the bank-select register
+        let last_window = (config.io_base as usize).saturating_sub(1);
+        check_range("bank window", config.bank_window, 1, last_window)?;
+    }
+    Ok(())
+}
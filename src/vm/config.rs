@@ -0,0 +1,66 @@
+use crate::control_rom::ControlRom;
+use crate::instruction_memory::INSTRUCTION_MEMORY_SIZE;
+use crate::io_devices::rng::RngSource;
+use crate::io_devices::screen::SCREEN_SIZE;
+use crate::io_devices::{AttachedDevices, IO_BASE};
+use crate::registers::call_stack::DEFAULT_STACK_DEPTH;
+use crate::registers::data_memory::MEMORY_SIZE;
+use crate::registers::register_file::REGISTER_BANK_SIZE;
+
+pub(crate) const DEFAULT_BANK_WINDOW: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FaultPolicy {
+    // Stop with a runtime error, leaving the PC on the faulting instruction
+    Trap,
+    // Carry on like the original hardware: underflowing RETs fall through, overflowing CALs
+    // drop the oldest return address and bad port accesses are ignored or read as 0
+    #[default]
+    Emulate,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    pub fault_policy: FaultPolicy,
+    pub stack_depth: usize, // nested CALs the call stack can hold, 16 on the original hardware
+    pub rng_seed: Option<u64>, // None seeds from the host's entropy, so every run differs
+    pub rng_source: RngSource,
+    // The sizes below default to the original machine, which is also as large as the
+    // instruction encoding can address. `VmBuilder` checks them; `VM::with_config` clamps.
+    pub instruction_memory_size: usize, // words
+    pub data_memory_size: usize,        // bytes, addresses past it read 0 and drop writes
+    pub register_count: usize,          // r0 included
+    pub io_base: u8,                    // first of the 16 IO ports
+    pub screen_width: usize,
+    pub screen_height: usize,
+    pub devices: AttachedDevices,
+    // More than one bank adds a bank-select register just below the IO ports (239 by
+    // default) that swaps the first `bank_window` bytes of data memory
+    pub memory_banks: usize,
+    pub bank_window: usize,
+    // Decodes the B field of RSH as a function select, giving OR, XNOR, NAND, IMP and NIMP
+    pub extended_alu: bool,
+    pub control_rom: ControlRom, // the decode table, the BatPU-2 one by default
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        VmConfig {
+            fault_policy: FaultPolicy::default(),
+            stack_depth: DEFAULT_STACK_DEPTH,
+            rng_seed: None,
+            rng_source: RngSource::default(),
+            instruction_memory_size: INSTRUCTION_MEMORY_SIZE,
+            data_memory_size: MEMORY_SIZE,
+            register_count: REGISTER_BANK_SIZE,
+            io_base: IO_BASE,
+            screen_width: SCREEN_SIZE,
+            screen_height: SCREEN_SIZE,
+            devices: AttachedDevices::default(),
+            memory_banks: 1,
+            bank_window: DEFAULT_BANK_WINDOW,
+            extended_alu: false,
+            control_rom: ControlRom::batpu2(),
+        }
+    }
+}
@@ -0,0 +1,277 @@
+// What a frontend needs from an execution core, so that it can run the gate-level `VM` and
+// the `FastVm` alike
+use super::config::VmConfig;
+use super::fast::FastVm;
+use crate::io_devices::IoDevices;
+use crate::registers::data_memory::MEMORY_SIZE;
+use crate::registers::register_file::REGISTER_BANK_SIZE;
+use crate::{AluFlags, OpCode, ProgramInstruction, Result, VM};
+use std::path::Path;
+
+pub trait Engine {
+    // Runs one instruction, see `VM::step`
+    fn step(&mut self) -> Result<OpCode>;
+
+    // Like `step`, but a fault simply reads as HLT
+    fn clock(&mut self) -> OpCode {
+        self.step().unwrap_or(crate::OPCODE_HLT)
+    }
+
+    fn io_devices(&self) -> &IoDevices;
+
+    fn io_devices_mut(&mut self) -> &mut IoDevices;
+
+    fn save_state(&self, file_path: &Path) -> Result<()>;
+
+    fn load_state(&mut self, file_path: &Path) -> Result<()>;
+
+    fn config(&self) -> &VmConfig;
+
+    fn pc(&self) -> u16;
+
+    fn instruction_at(&self, address: usize) -> Option<ProgramInstruction>;
+
+    // Everything a program can observe, for comparing engines
+    fn state(&self) -> MachineState;
+}
+
+// Missing registers and unmapped memory read 0 here, whatever their storage holds. Attached
+// user devices have no equality of their own, so they are compared by their `Debug` output.
+#[derive(Debug, Clone)]
+pub struct MachineState {
+    pub pc: u16,
+    pub registers: [[u8; REGISTER_BANK_SIZE]; 2], // both banks of the register file
+    pub memory: [u8; MEMORY_SIZE],
+    pub bank: usize,
+    pub banks: Vec<Vec<u8>>, // every memory bank, the selected one included
+    pub flags: AluFlags,
+    pub call_stack: Vec<u16>, // most recent first
+    pub io_devices: IoDevices,
+}
+
+impl PartialEq for MachineState {
+    fn eq(&self, other: &Self) -> bool {
+        self.pc == other.pc
+            && self.registers == other.registers
+            && self.memory == other.memory
+            && self.bank == other.bank
+            && self.banks == other.banks
+            && self.flags == other.flags
+            && self.call_stack == other.call_stack
+            && self.io_devices == other.io_devices
+            && same_attached_devices(&self.io_devices, &other.io_devices)
+    }
+}
+
+fn same_attached_devices(a: &IoDevices, b: &IoDevices) -> bool {
+    format!("{:?}", a.bus) == format!("{:?}", b.bus)
+}
+
+impl MachineState {
+    // One line per part of the machine that differs, `self` first
+    pub fn differences(&self, other: &MachineState) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut compare = |name: String, a: &dyn std::fmt::Debug, b: &dyn std::fmt::Debug| {
+            let (a, b) = (format!("{a:?}"), format!("{b:?}"));
+            if a != b {
+                out.push(format!("{name}: {a} vs {b}"));
+            }
+        };
+        compare("pc".to_string(), &self.pc, &other.pc);
+        for (bank, (a, b)) in self.registers.iter().zip(&other.registers).enumerate() {
+            for (index, (a, b)) in a.iter().zip(b).enumerate() {
+                compare(format!("r{index} (register bank {bank})"), a, b);
+            }
+        }
+        for (address, (a, b)) in self.memory.iter().zip(&other.memory).enumerate() {
+            compare(format!("mem[{address}]"), a, b);
+        }
+        compare("selected bank".to_string(), &self.bank, &other.bank);
+        compare(
+            "bank count".to_string(),
+            &self.banks.len(),
+            &other.banks.len(),
+        );
+        for (bank, (a, b)) in self.banks.iter().zip(&other.banks).enumerate() {
+            for (address, (a, b)) in a.iter().zip(b).enumerate() {
+                compare(format!("bank {bank} mem[{address}]"), a, b);
+            }
+        }
+        compare("flags".to_string(), &self.flags, &other.flags);
+        compare(
+            "call stack".to_string(),
+            &self.call_stack,
+            &other.call_stack,
+        );
+        out.extend(device_differences(&self.io_devices, &other.io_devices));
+        out
+    }
+}
+
+fn device_differences(a: &IoDevices, b: &IoDevices) -> Vec<String> {
+    let mut out = Vec::new();
+    if a.screen != b.screen {
+        let pixels = |screen: &crate::io_devices::screen::Screen| {
+            screen.active.into_iter().chain(screen.buffer).flatten()
+        };
+        let differing = pixels(&a.screen)
+            .zip(pixels(&b.screen))
+            .filter(|(a, b)| a != b)
+            .count();
+        out.push(format!(
+            "screen: {differing} pixels differ, cursor ({}, {}) vs ({}, {})",
+            a.screen.current_x, a.screen.current_y, b.screen.current_x, b.screen.current_y
+        ));
+    }
+    let (a_chars, b_chars) = (&a.character_display, &b.character_display);
+    if a_chars != b_chars {
+        out.push(format!(
+            "character display: {:?} (buffer {:?}) vs {:?} (buffer {:?})",
+            a_chars.active, a_chars.buffer, b_chars.active, b_chars.buffer
+        ));
+    }
+    if a.number_display != b.number_display {
+        out.push(format!(
+            "number display: {} vs {}",
+            a.number_display.get_display_val(),
+            b.number_display.get_display_val()
+        ));
+    }
+    if a.rng != b.rng {
+        out.push(format!(
+            "rng: {} values drawn vs {}",
+            a.rng.draws, b.rng.draws
+        ));
+    }
+    if a.controller != b.controller {
+        out.push(format!(
+            "controller: {:08b} vs {:08b}",
+            u8::from(a.controller.value),
+            u8::from(b.controller.value)
+        ));
+    }
+    if a.bus != b.bus || !same_attached_devices(a, b) {
+        out.push(format!("attached devices: {:?} vs {:?}", a.bus, b.bus));
+    }
+    out
+}
+
+impl Engine for VM {
+    fn step(&mut self) -> Result<OpCode> {
+        VM::step(self)
+    }
+
+    fn io_devices(&self) -> &IoDevices {
+        &self.io_devices
+    }
+
+    fn io_devices_mut(&mut self) -> &mut IoDevices {
+        &mut self.io_devices
+    }
+
+    fn save_state(&self, file_path: &Path) -> Result<()> {
+        VM::save_state(self, file_path)
+    }
+
+    fn load_state(&mut self, file_path: &Path) -> Result<()> {
+        VM::load_state(self, file_path)
+    }
+
+    fn config(&self) -> &VmConfig {
+        VM::config(self)
+    }
+
+    fn pc(&self) -> u16 {
+        self.pc.value.to_usize() as u16
+    }
+
+    fn instruction_at(&self, address: usize) -> Option<ProgramInstruction> {
+        VM::instruction_at(self, address)
+    }
+
+    fn state(&self) -> MachineState {
+        let registers = self.reg_file.register_banks.map(|bank| {
+            let mut out = [0; REGISTER_BANK_SIZE];
+            for (register, &value) in out.iter_mut().zip(&bank).take(self.reg_file.count) {
+                *register = value.into();
+            }
+            out
+        });
+        let memory = &self.data_memory;
+        let mut visible = [0; MEMORY_SIZE];
+        for (byte, &value) in visible.iter_mut().zip(&memory.memory).take(memory.size) {
+            *byte = value.into();
+        }
+        MachineState {
+            pc: Engine::pc(self),
+            registers,
+            memory: visible,
+            bank: memory.bank(),
+            banks: (0..memory.bank_count())
+                .map(|bank| {
+                    memory
+                        .bank_contents(bank)
+                        .iter()
+                        .map(|&value| value.into())
+                        .collect()
+                })
+                .collect(),
+            flags: self.flags(),
+            call_stack: self.call_stack(),
+            io_devices: self.io_devices.clone(),
+        }
+    }
+}
+
+impl Engine for FastVm {
+    fn step(&mut self) -> Result<OpCode> {
+        FastVm::step(self)
+    }
+
+    fn io_devices(&self) -> &IoDevices {
+        &self.io_devices
+    }
+
+    fn io_devices_mut(&mut self) -> &mut IoDevices {
+        &mut self.io_devices
+    }
+
+    fn save_state(&self, file_path: &Path) -> Result<()> {
+        FastVm::save_state(self, file_path)
+    }
+
+    fn load_state(&mut self, file_path: &Path) -> Result<()> {
+        FastVm::load_state(self, file_path)
+    }
+
+    fn config(&self) -> &VmConfig {
+        FastVm::config(self)
+    }
+
+    fn pc(&self) -> u16 {
+        FastVm::pc(self)
+    }
+
+    fn instruction_at(&self, address: usize) -> Option<ProgramInstruction> {
+        FastVm::instruction_at(self, address)
+    }
+
+    fn state(&self) -> MachineState {
+        let registers: [u8; REGISTER_BANK_SIZE] =
+            std::array::from_fn(|index| self.register(index as u8));
+        let mut memory = *self.memory();
+        memory[self.config().data_memory_size.min(MEMORY_SIZE)..].fill(0);
+        MachineState {
+            pc: FastVm::pc(self),
+            registers: [registers; 2],
+            memory,
+            bank: self.bank(),
+            banks: (0..self.bank_count())
+                .map(|bank| self.bank_contents(bank).to_vec())
+                .collect(),
+            flags: self.flags(),
+            call_stack: self.call_stack(),
+            io_devices: self.io_devices.clone(),
+        }
+    }
+}
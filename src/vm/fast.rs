@@ -0,0 +1,509 @@
+// The same machine without the gate-level model: the program is decoded once when it is
+// loaded, registers and memory are plain integers and the ALU is integer arithmetic. Only
+// the architectural state is kept, so there are no observers, history or half-clocked
+// registers; `to_vm` and `from_vm` move a machine between the two cores.
+use super::config::{FaultPolicy, VmConfig};
+use crate::control_rom::{
+    AddrMux, AluMux, AluSettings, CallStackState, ControlRom, ControlSignals, DataMux, DestMux,
+    ImmediateMux, MemoryAccess, ReadMux,
+};
+use crate::error::{Fault, RuntimeError};
+use crate::instruction::{Condition, Instruction};
+use crate::instruction_memory::INSTRUCTION_MEMORY_SIZE;
+use crate::io_devices::{IoDevices, IO_BASE, IO_PORTS};
+use crate::registers::data_memory::MEMORY_SIZE;
+use crate::registers::register_file::REGISTER_BANK_SIZE;
+use crate::{AluFlags, Bits, OpCode, ProgramInstruction, VM};
+use std::collections::VecDeque;
+use std::path::Path;
+
+const ADDRESS_MASK: u16 = INSTRUCTION_MEMORY_SIZE as u16 - 1;
+
+#[derive(Debug, Clone)]
+pub struct FastVm {
+    words: Vec<u16>,
+    decoded: Vec<Instruction>,
+    program_len: usize,
+    pc: u16,
+    registers: [u8; REGISTER_BANK_SIZE],
+    memory: [u8; MEMORY_SIZE],
+    // the other memory banks, as in `DataMemory`: the selected one's slot is stale
+    banks: Vec<Vec<u8>>,
+    bank: usize,
+    call_stack: VecDeque<u16>, // most recent last
+    flags: AluFlags,
+    pub io_devices: IoDevices,
+    config: VmConfig,
+    custom_rom: bool, // decoded words are ignored, see `execute_signals`
+}
+
+impl Default for FastVm {
+    fn default() -> Self {
+        FastVm::new()
+    }
+}
+
+impl FastVm {
+    pub fn new() -> Self {
+        FastVm::from_vm(&VM::new())
+    }
+
+    // Clamped like `VM::with_config`
+    pub fn with_config(config: VmConfig) -> Self {
+        FastVm::from_vm(&VM::with_config(config))
+    }
+
+    // Takes over the state of a gate-level machine. Its observers and history stay behind.
+    pub fn from_vm(vm: &VM) -> Self {
+        let words: Vec<u16> = vm
+            .instruction_memory
+            .instructions
+            .iter()
+            .map(|&word| u16::from(word))
+            .collect();
+        let mut registers = [0; REGISTER_BANK_SIZE];
+        for (register, value) in registers
+            .iter_mut()
+            .zip(vm.reg_file.register_banks[0])
+            .take(vm.reg_file.count)
+            .skip(1)
+        {
+            *register = u8::from(value);
+        }
+        let memory = &vm.data_memory;
+        FastVm {
+            decoded: words
+                .iter()
+                .map(|&word| Instruction::decode_with(Bits::from(word), vm.config.extended_alu))
+                .collect(),
+            words,
+            program_len: vm.program_len,
+            pc: vm.pc.value.to_usize() as u16,
+            registers,
+            memory: memory.memory.map(u8::from),
+            banks: memory
+                .banks
+                .iter()
+                .map(|bank| bank.iter().map(|&value| u8::from(value)).collect())
+                .collect(),
+            bank: memory.bank,
+            call_stack: vm.call_stack().into_iter().rev().collect(),
+            flags: vm.flags(),
+            io_devices: vm.io_devices.clone(),
+            config: vm.config.clone(),
+            custom_rom: vm.config.control_rom != ControlRom::batpu2(),
+        }
+    }
+
+    pub fn to_vm(&self) -> VM {
+        let mut vm = VM::with_config(self.config.clone());
+        for (slot, &word) in vm
+            .instruction_memory
+            .instructions
+            .iter_mut()
+            .zip(&self.words)
+        {
+            *slot = Bits::from(word);
+        }
+        vm.program_len = self.program_len;
+        vm.pc.value = Bits::from(self.pc).resize();
+        for bank in vm.reg_file.register_banks.iter_mut() {
+            *bank = self.registers.map(Bits::from);
+        }
+        vm.data_memory.memory = self.memory.map(Bits::from);
+        for (slot, bank) in vm.data_memory.banks.iter_mut().zip(&self.banks) {
+            *slot = bank.iter().map(|&value| Bits::from(value)).collect();
+        }
+        vm.data_memory.bank = self.bank;
+        vm.call_stack.stack.stack = self
+            .call_stack
+            .iter()
+            .rev()
+            .map(|&address| Bits::from(address).resize())
+            .collect();
+        vm.alu.flags = self.flags;
+        vm.io_devices = self.io_devices.clone();
+        vm
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
+    pub fn load_program(&mut self, file_path: impl AsRef<Path>) -> crate::Result<()> {
+        let program = crate::parser::parse_program_with(file_path, &self.config)?;
+        self.load_instructions(&program)
+    }
+
+    pub fn load_source(&mut self, source: &str) -> crate::Result<()> {
+        let program =
+            crate::parser::assemble_file("<input>", source, &self.config).into_result()?;
+        self.load_instructions(&program)
+    }
+
+    pub fn load_instructions(&mut self, instructions: &[ProgramInstruction]) -> crate::Result<()> {
+        // the config is not clamped, the instruction memory is
+        if instructions.len() > self.words.len().min(self.config.instruction_memory_size) {
+            return Err(crate::Error::InstructionMemoryOverflow);
+        }
+        for (address, &word) in instructions.iter().enumerate() {
+            self.words[address] = u16::from(word);
+            self.decoded[address] = Instruction::decode_with(word, self.config.extended_alu);
+        }
+        self.pc = 0;
+        self.program_len = instructions.len();
+        Ok(())
+    }
+
+    // The state files are the gate-level machine's
+    pub fn save_state(&self, file_path: impl AsRef<Path>) -> crate::Result<()> {
+        self.to_vm().save_state(file_path)
+    }
+
+    pub fn load_state(&mut self, file_path: impl AsRef<Path>) -> crate::Result<()> {
+        let mut vm = self.to_vm();
+        vm.load_state(file_path)?;
+        *self = FastVm::from_vm(&vm);
+        Ok(())
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn register(&self, register: u8) -> u8 {
+        self.registers
+            .get(register as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // r0 and registers the machine does not have are left alone
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        if register != 0 && (register as usize) < self.config.register_count {
+            self.registers[register as usize] = value;
+        }
+    }
+
+    pub fn memory(&self) -> &[u8; MEMORY_SIZE] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8; MEMORY_SIZE] {
+        &mut self.memory
+    }
+
+    pub fn flags(&self) -> AluFlags {
+        self.flags
+    }
+
+    // Most recent first, like `VM::call_stack`
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.call_stack.iter().rev().copied().collect()
+    }
+
+    pub fn bank(&self) -> usize {
+        self.bank
+    }
+
+    pub fn bank_count(&self) -> usize {
+        self.banks.len().max(1)
+    }
+
+    // The contents of a bank, whether it is selected or not
+    pub fn bank_contents(&self, bank: usize) -> &[u8] {
+        if bank == self.bank {
+            &self.memory[..self.banks.get(bank).map_or(0, Vec::len)]
+        } else {
+            &self.banks[bank]
+        }
+    }
+
+    pub fn instruction_at(&self, address: usize) -> Option<ProgramInstruction> {
+        self.words.get(address).map(|&word| Bits::from(word))
+    }
+
+    // Runs until HLT or a fault
+    pub fn run(&mut self) -> crate::Result<()> {
+        while self.try_step()? != Instruction::Hlt {}
+        Ok(())
+    }
+
+    // Runs one instruction. On a fault nothing is changed and the PC stays on the
+    // faulting instruction.
+    pub fn step(&mut self) -> crate::Result<OpCode> {
+        let word = self.words[self.pc as usize];
+        self.try_step()?;
+        Ok(Bits::from((word >> 12) as u8).resize())
+    }
+
+    fn try_step(&mut self) -> Result<Instruction, Fault> {
+        let pc = self.pc;
+        let instruction = self.decoded[pc as usize];
+        let result = if pc as usize >= self.program_len {
+            self.fault(RuntimeError::PcOutOfBounds)
+        } else {
+            Ok(())
+        };
+        let word = self.words[pc as usize];
+        let signals = self.custom_rom.then(|| {
+            self.config
+                .control_rom
+                .decode(Bits::from(word), self.config.extended_alu)
+        });
+        result
+            .and_then(|()| match signals {
+                Some(signals) => self.execute_signals(word, signals),
+                None => self.execute(instruction),
+            })
+            .map_err(|error| Fault { pc, error })?;
+        self.io_devices.tick();
+        Ok(instruction)
+    }
+
+    fn fault(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        match self.config.fault_policy {
+            FaultPolicy::Trap => Err(error),
+            FaultPolicy::Emulate => Ok(()),
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), RuntimeError> {
+        let pc_inc = self.pc.wrapping_add(1) & ADDRESS_MASK;
+        let mut next_pc = pc_inc;
+        match instruction {
+            Instruction::Nop | Instruction::Hlt => {}
+            Instruction::Add { a, b, c } => self.compute(AluSettings::Add, a, b, c),
+            Instruction::Sub { a, b, c } => self.compute(AluSettings::Sub, a, b, c),
+            Instruction::Nor { a, b, c } => self.compute(AluSettings::Nor, a, b, c),
+            Instruction::And { a, b, c } => self.compute(AluSettings::And, a, b, c),
+            Instruction::Xor { a, b, c } => self.compute(AluSettings::Xor, a, b, c),
+            // RSH leaves the flags alone
+            Instruction::Rsh { a, c } => self.set_register(c, self.register(a) >> 1),
+            Instruction::Or { a, c } => self.compute(AluSettings::Or, a, c, c),
+            Instruction::Xnor { a, c } => self.compute(AluSettings::Xnor, a, c, c),
+            Instruction::Nand { a, c } => self.compute(AluSettings::Nand, a, c, c),
+            Instruction::Imp { a, c } => self.compute(AluSettings::Implies, a, c, c),
+            Instruction::Nimp { a, c } => self.compute(AluSettings::Nimplies, a, c, c),
+            Instruction::Ldi { a, immediate } => self.set_register(a, immediate),
+            Instruction::Adi { a, immediate } => {
+                let value = self.set_flags(alu(AluSettings::Add, self.register(a), immediate));
+                self.set_register(a, value);
+            }
+            Instruction::Jmp { address } => next_pc = address,
+            Instruction::Brh { condition, address } => {
+                if self.condition(condition) {
+                    next_pc = address;
+                }
+            }
+            Instruction::Cal { address } => {
+                self.check_push()?;
+                self.push(pc_inc);
+                next_pc = address;
+            }
+            Instruction::Ret => next_pc = self.pop(pc_inc)?,
+            Instruction::Lod { a, b, offset } => {
+                let address = self.register(a).wrapping_add(offset as u8);
+                let value = self.load(address)?;
+                self.set_register(b, value);
+            }
+            Instruction::Str { a, b, offset } => {
+                let address = self.register(a).wrapping_add(offset as u8);
+                self.store(address, self.register(b))?;
+            }
+        }
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    // A table other than the BatPU-2 one runs through the control signals, in the order
+    // the gate-level datapath in `VM::process_instruction` uses them
+    fn execute_signals(&mut self, word: u16, signals: ControlSignals) -> Result<(), RuntimeError> {
+        let pc_inc = self.pc.wrapping_add(1) & ADDRESS_MASK;
+        let field = |shift: u16| (word >> shift & 0xF) as u8;
+        if signals.call_stack_state == CallStackState::Push {
+            self.check_push()?;
+        }
+        let mut next_pc = match signals.addr_mux {
+            AddrMux::Increment => pc_inc,
+            AddrMux::Jump => word & ADDRESS_MASK,
+            AddrMux::Return if signals.call_stack_state == CallStackState::Pop => {
+                self.pop(pc_inc)?
+            }
+            AddrMux::Return => {
+                self.fault(RuntimeError::CallStackUnderflow)?;
+                pc_inc
+            }
+        };
+        if signals.is_branch && self.condition(Condition::from_bits(word >> 10)) {
+            next_pc = word & ADDRESS_MASK;
+        }
+
+        let a = self.register(field(8));
+        let b = self.register(match signals.read_mux {
+            ReadMux::Second => field(4),
+            ReadMux::Third => field(0),
+        });
+        let immediate = match signals.immediate_mux {
+            ImmediateMux::Immediate => word as u8,
+            ImmediateMux::Offset => ((field(0) << 4) as i8 >> 4) as u8,
+        };
+        let alu_b = match signals.alu_mux {
+            AluMux::R2 => b,
+            AluMux::BypassRegisterFile => immediate,
+        };
+        let (result, carry) = alu(signals.alu_settings, a, alu_b);
+        // RSH never reaches the flags
+        if signals.set_flags && signals.alu_settings != AluSettings::Rshift {
+            self.set_flags((result, carry));
+        }
+
+        let data = match signals.data_mux {
+            DataMux::Alu => result,
+            DataMux::Immediate => word as u8,
+            DataMux::Memory if signals.memory_access == MemoryAccess::Read => self.load(result)?,
+            // with the memory not reading, only the devices and bank select answer
+            DataMux::Memory if self.io_port(result).is_some() || self.is_bank_select(result) => {
+                self.load(result)?
+            }
+            DataMux::Memory => 0,
+        };
+        if signals.memory_access == MemoryAccess::Write {
+            self.store(result, b)?;
+        } else if signals.reg_file_enable {
+            self.set_register(
+                match signals.dest_mux {
+                    DestMux::First => field(8),
+                    DestMux::Second => field(4),
+                    DestMux::Third => field(0),
+                },
+                data,
+            );
+        }
+        if signals.call_stack_state == CallStackState::Push {
+            self.push(pc_inc);
+        }
+        self.pc = next_pc;
+        Ok(())
+    }
+
+    fn condition(&self, condition: Condition) -> bool {
+        match condition {
+            Condition::Zero => self.flags.zero,
+            Condition::NotZero => !self.flags.zero,
+            Condition::Carry => self.flags.carry,
+            Condition::NotCarry => !self.flags.carry,
+        }
+    }
+
+    fn check_push(&mut self) -> Result<(), RuntimeError> {
+        if self.call_stack.len() == self.config.stack_depth {
+            self.fault(RuntimeError::CallStackOverflow)?;
+        }
+        Ok(())
+    }
+
+    // A full stack shifts its oldest return address out
+    fn push(&mut self, address: u16) {
+        if self.config.stack_depth == 0 {
+            return;
+        }
+        if self.call_stack.len() == self.config.stack_depth {
+            self.call_stack.pop_front();
+        }
+        self.call_stack.push_back(address);
+    }
+
+    // With nothing to return to, execution falls through to the next instruction
+    fn pop(&mut self, pc_inc: u16) -> Result<u16, RuntimeError> {
+        match self.call_stack.pop_back() {
+            Some(address) => Ok(address),
+            None => {
+                self.fault(RuntimeError::CallStackUnderflow)?;
+                Ok(pc_inc)
+            }
+        }
+    }
+
+    fn compute(&mut self, setting: AluSettings, a: u8, b: u8, c: u8) {
+        let value = self.set_flags(alu(setting, self.register(a), self.register(b)));
+        self.set_register(c, value);
+    }
+
+    fn set_flags(&mut self, (value, carry): (u8, bool)) -> u8 {
+        self.flags = AluFlags {
+            zero: value == 0,
+            carry,
+        };
+        value
+    }
+
+    fn io_port(&self, address: u8) -> Option<u8> {
+        let offset = address.checked_sub(self.config.io_base)?;
+        (offset < IO_PORTS).then(|| IO_BASE + offset)
+    }
+
+    fn is_bank_select(&self, address: u8) -> bool {
+        self.banks.len() > 1 && address == self.config.io_base.wrapping_sub(1)
+    }
+
+    fn load(&mut self, address: u8) -> Result<u8, RuntimeError> {
+        if let Some(port) = self.io_port(address) {
+            if !self.io_devices.can_load(port) {
+                self.fault(RuntimeError::ReadFromWriteOnlyPort(port))?;
+            }
+            Ok(u8::from(self.io_devices.on_read(port)))
+        } else if self.is_bank_select(address) {
+            Ok(self.bank as u8)
+        } else if (address as usize) < self.config.data_memory_size {
+            Ok(self.memory[address as usize])
+        } else {
+            Ok(0)
+        }
+    }
+
+    fn store(&mut self, address: u8, value: u8) -> Result<(), RuntimeError> {
+        if let Some(port) = self.io_port(address) {
+            if !self.io_devices.can_store(port) {
+                self.fault(RuntimeError::WriteToReadOnlyPort(port))?;
+            }
+            self.io_devices.on_write(port, Bits::from(value));
+        } else if self.is_bank_select(address) {
+            self.select_bank(value as usize);
+        } else if (address as usize) < self.config.data_memory_size {
+            self.memory[address as usize] = value;
+        }
+        Ok(())
+    }
+
+    // Numbers past the last bank wrap around
+    fn select_bank(&mut self, bank: usize) {
+        let bank = bank % self.banks.len();
+        if bank == self.bank {
+            return;
+        }
+        let window = self.banks[bank].len();
+        self.banks[self.bank].copy_from_slice(&self.memory[..window]);
+        self.memory[..window].copy_from_slice(&self.banks[bank]);
+        self.bank = bank;
+    }
+}
+
+// The ALU on plain integers, returning the result and the carry out. The logic operations
+// run through the adder with every carry forced one way or the other, which decides their
+// carry flag.
+pub(crate) fn alu(setting: AluSettings, a: u8, b: u8) -> (u8, bool) {
+    match setting {
+        AluSettings::Add => a.overflowing_add(b),
+        AluSettings::Sub => (a.wrapping_sub(b), a >= b),
+        AluSettings::Xor => (a ^ b, true),
+        AluSettings::Xnor => (!(a ^ b), true),
+        AluSettings::Or => (a | b, false),
+        AluSettings::Nor => (!(a | b), true),
+        AluSettings::And => (a & b, true),
+        AluSettings::Nand => (!(a & b), false),
+        AluSettings::Implies => (!a | b, false),
+        AluSettings::Nimplies => (a & !b, true),
+        AluSettings::Rshift => (a >> 1, false),
+    }
+}
@@ -0,0 +1,36 @@
+use crate::io_devices::IoDevices;
+use crate::registers::call_stack::CallStack;
+use crate::{Address, AluFlags, Bits};
+use std::collections::VecDeque;
+
+// What an instruction overwrote, so that it can be put back. Only the parts of the
+// machine the instruction can touch are kept.
+#[derive(Debug, Clone)]
+pub(crate) struct Undo {
+    pub(crate) pc: Address,
+    pub(crate) flags: AluFlags,
+    pub(crate) register: Option<(usize, Bits<8>)>,
+    pub(crate) memory: Option<(usize, Bits<8>)>,
+    pub(crate) call_stack: Option<Box<CallStack>>,
+    pub(crate) io_devices: Option<Box<IoDevices>>,
+    pub(crate) bank: Option<usize>, // selected before a store to the bank-select register
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct History {
+    pub(crate) entries: VecDeque<Undo>,
+    pub(crate) capacity: usize,
+}
+
+impl History {
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub(crate) fn push(&mut self, undo: Undo) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(undo);
+    }
+}
@@ -1,15 +1,36 @@
-use crate::control_rom::{AddrMux, AluMux, DataMux, DestMux, ImmediateMux, MemoryAccess};
-use crate::io_devices::{Device, IoDevices};
-use crate::registers::call_stack::CallStack;
+use crate::alu::alu_flags::AluFlags;
+use crate::control_rom::{
+    AddrMux, AluMux, CallStackState, ControlSignals, DataMux, DestMux, ImmediateMux, MemoryAccess,
+    ReadMux,
+};
+use crate::error::{Fault, RuntimeError};
+use crate::instruction::Instruction;
+use crate::io_devices::bus::IoBus;
+use crate::io_devices::rng::RNG;
+use crate::io_devices::screen::Screen;
+use crate::io_devices::{IoDevices, IO_BASE, IO_PORTS};
+use crate::registers::call_stack::{CallStack, MAX_STACK_DEPTH};
 use crate::registers::data_memory::MemoryState;
 use crate::registers::Register;
 use crate::{
     alu::Alu, bits::Bits, control_rom::ControlRom, instruction_memory::InstructionMemory,
-    parser::parse_as_instruction, program_counter::PC, registers::data_memory::DataMemory,
-    registers::RegisterFile, OpCode, ProgramInstruction,
+    program_counter::PC, registers::data_memory::DataMemory, registers::RegisterFile, Immediate,
+    OpCode, ProgramInstruction,
 };
+use builder::VmBuilder;
+use config::{FaultPolicy, VmConfig};
+use history::{History, Undo};
+use observer::{Access, Control, Observer, Observers, RunOutcome, StepEvent};
 use std::path::Path;
 
+pub mod builder;
+pub mod config;
+pub mod engine;
+pub mod fast;
+mod history;
+pub mod observer;
+mod state;
+
 #[derive(Debug, Default)]
 pub struct VM {
     alu: Alu,
@@ -20,13 +41,21 @@ pub struct VM {
     call_stack: CallStack,
     pub data_memory: DataMemory,
     pub io_devices: IoDevices,
+    observers: Observers,
+    paused: bool,
+    memory_access: Option<Access>,
+    io_access: Option<Access>,
+    history: History,
+    config: VmConfig,
+    program_len: usize,
+    emulated_fault: Option<RuntimeError>,
 }
 
 impl VM {
     pub fn new() -> Self {
         let alu = Alu::default();
         let reg_file = RegisterFile::default();
-        let control_rom = ControlRom;
+        let control_rom = ControlRom::batpu2();
         let instruction_memory = InstructionMemory::default();
         let pc = PC::default();
         let call_stack = CallStack::new();
@@ -41,33 +70,173 @@ impl VM {
             call_stack,
             data_memory,
             io_devices,
+            observers: Observers::default(),
+            paused: false,
+            memory_access: None,
+            io_access: None,
+            history: History::default(),
+            config: VmConfig::default(),
+            program_len: 0,
+            emulated_fault: None,
         }
     }
 
+    // Sizes beyond what the instruction encoding can address are clamped; `VmBuilder`
+    // reports them as errors instead
+    pub fn with_config(config: VmConfig) -> Self {
+        let mut vm = VM {
+            reg_file: RegisterFile::with_count(config.register_count),
+            instruction_memory: InstructionMemory::with_size(config.instruction_memory_size),
+            call_stack: CallStack::with_depth(config.stack_depth.min(MAX_STACK_DEPTH)),
+            data_memory: DataMemory::with_size(config.data_memory_size),
+            control_rom: config.control_rom.clone(),
+            ..VM::new()
+        };
+        let seed = config.rng_seed.unwrap_or_else(rand::random);
+        vm.io_devices.rng = RNG::with_seed(seed, config.rng_source.clone());
+        vm.io_devices.screen = Screen::with_size(config.screen_width, config.screen_height);
+        vm.io_devices.bus = IoBus::new(config.devices);
+        let io_base = config.io_base.min(u8::MAX - (IO_PORTS - 1));
+        let window = config.bank_window.min(io_base.saturating_sub(1) as usize);
+        vm.data_memory.set_banks(config.memory_banks, window);
+        vm.config = VmConfig {
+            io_base,
+            stack_depth: vm.call_stack.max_depth(),
+            ..config
+        };
+        vm
+    }
+
+    pub fn builder() -> VmBuilder {
+        VmBuilder::new()
+    }
+
+    pub fn config(&self) -> &VmConfig {
+        &self.config
+    }
+
     pub fn execute_program(&mut self, file_path: impl AsRef<Path>) -> crate::Result<()> {
         let file_path = file_path.as_ref();
         self.load_program(file_path)?;
-        while self.clock() != crate::OPCODE_HLT {}
+        self.run()?;
         Ok(())
     }
 
     pub fn load_program(&mut self, file_path: impl AsRef<Path>) -> crate::Result<()> {
+        let program = crate::parser::parse_program_with(file_path, &self.config)?;
+        self.load_instructions(&program)
+    }
+
+    pub fn load_source(&mut self, source: &str) -> crate::Result<()> {
+        let program =
+            crate::parser::assemble_file("<input>", source, &self.config).into_result()?;
+        self.load_instructions(&program)
+    }
+
+    pub fn load_instructions(&mut self, instructions: &[ProgramInstruction]) -> crate::Result<()> {
         self.pc.value = Bits::from(0u16).resize();
-        let file_path = file_path.as_ref();
-        crate::parser::parse_program(file_path)?;
-        let path = Path::new(file_path).with_extension("mc");
-        let program = std::fs::read_to_string(path)?;
-        let content_bits = program
-            .lines()
-            .map(parse_as_instruction)
-            .collect::<Vec<_>>();
-        self.instruction_memory.load_instructions(content_bits)?;
+        self.instruction_memory
+            .load_instructions(instructions.to_vec())?;
+        self.program_len = instructions.len();
         Ok(())
     }
 
-    fn process_instruction(&mut self, instruction: ProgramInstruction) {
-        let opcode = instruction.slice(12);
-        let control_signals = self.control_rom.get_control_signals(opcode);
+    pub fn flags(&self) -> AluFlags {
+        self.alu.flags
+    }
+
+    // Writes both register banks; r0 always reads as zero and is left alone
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        if register == 0 {
+            return;
+        }
+        for bank in self.reg_file.register_banks.iter_mut() {
+            if let Some(slot) = bank.get_mut(register as usize) {
+                *slot = Bits::from(value);
+            }
+        }
+    }
+
+    pub fn call_stack(&self) -> Vec<u16> {
+        self.call_stack
+            .contents()
+            .map(|address| u16::from(address.resize::<16>()))
+            .collect()
+    }
+
+    pub fn instruction_at(&self, address: usize) -> Option<ProgramInstruction> {
+        self.instruction_memory.instructions.get(address).copied()
+    }
+
+    pub fn add_observer(&mut self, observer: impl Observer) {
+        self.observers.0.push(Box::new(observer));
+    }
+
+    pub fn observer<O: Observer>(&self) -> Option<&O> {
+        let observer = self.observers.0.get(self.observers.position::<O>()?)?;
+        (observer.as_ref() as &dyn std::any::Any).downcast_ref()
+    }
+
+    pub fn observer_mut<O: Observer>(&mut self) -> Option<&mut O> {
+        let index = self.observers.position::<O>()?;
+        (self.observers.0[index].as_mut() as &mut dyn std::any::Any).downcast_mut()
+    }
+
+    pub fn remove_observer<O: Observer>(&mut self) -> Option<O> {
+        let observer = self.observers.0.remove(self.observers.position::<O>()?);
+        let observer: Box<dyn std::any::Any> = observer;
+        observer.downcast().ok().map(|observer| *observer)
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    // Runs until HLT, a fault, or until an observer asks to pause
+    pub fn run(&mut self) -> crate::Result<RunOutcome> {
+        self.resume();
+        loop {
+            if self.step()? == crate::OPCODE_HLT {
+                return Ok(RunOutcome::Halted);
+            }
+            if self.paused {
+                return Ok(RunOutcome::Paused);
+            }
+        }
+    }
+
+    // The original port number of an address inside the IO window
+    pub(crate) fn io_port(&self, address: u8) -> Option<u8> {
+        let offset = address.checked_sub(self.config.io_base)?;
+        (offset < IO_PORTS).then(|| IO_BASE + offset)
+    }
+
+    // Only there with more than one memory bank, just below the IO ports
+    pub fn bank_select_address(&self) -> Option<u8> {
+        (self.data_memory.bank_count() > 1).then(|| self.config.io_base.wrapping_sub(1))
+    }
+
+    // Under `FaultPolicy::Emulate` the caller carries on with the hardware behaviour
+    fn fault(&mut self, error: RuntimeError) -> Result<(), RuntimeError> {
+        match self.config.fault_policy {
+            FaultPolicy::Trap => Err(error),
+            FaultPolicy::Emulate => {
+                self.emulated_fault = Some(error);
+                Ok(())
+            }
+        }
+    }
+
+    fn process_instruction(&mut self, instruction: ProgramInstruction) -> Result<(), RuntimeError> {
+        self.memory_access = None;
+        self.io_access = None;
+        let control_signals = self
+            .control_rom
+            .decode(instruction, self.config.extended_alu);
         self.call_stack.state = control_signals.call_stack_state;
         self.reg_file.enable(control_signals.reg_file_enable);
         self.alu.set_setting(control_signals.alu_settings);
@@ -90,20 +259,21 @@ impl VM {
         let current_pc = self.pc.value;
         let pc_inc = current_pc + Bits::from(1u16).resize::<10>();
 
+        if self.call_stack.state == CallStackState::Push && self.call_stack.is_full() {
+            self.fault(RuntimeError::CallStackOverflow)?;
+        }
         self.call_stack.push(pc_inc);
         let mut next_pc = match control_signals.addr_mux {
             AddrMux::Increment => pc_inc,
             AddrMux::Jump => instruction.slice(0),
-            AddrMux::Return => {
-                match self.call_stack.pop() {
-                    Some(addr) => addr,
-                    None => {
-                        // TODO add proper error handling
-                        eprintln!("Call stack underflow, returning to next instruction");
-                        pc_inc
-                    }
+            AddrMux::Return => match self.call_stack.pop() {
+                Some(addr) => addr,
+                None => {
+                    // with nothing to return to, execution falls through to the next instruction
+                    self.fault(RuntimeError::CallStackUnderflow)?;
+                    pc_inc
                 }
-            }
+            },
         };
 
         if control_signals.is_branch {
@@ -113,56 +283,81 @@ impl VM {
             }
         }
 
-        let r1 = instruction.slice(8);
-        let r2 = instruction.slice(4);
-        self.reg_file.set_read_addresses([r1, r2]);
+        self.reg_file
+            .set_read_addresses(read_addresses(instruction, control_signals.read_mux));
 
         let [a, b] = self.reg_file.read_outputs;
 
-        let immediate = match control_signals.immediate_mux {
-            ImmediateMux::Immediate => instruction.slice(0),
-            ImmediateMux::Offset => {
-                let offset = instruction.slice::<4>(0).to_signed(); // signed offset from -8 to 7
-                if offset < 0 {
-                    Bits::from((256isize + offset) as u8) // Convert negative offset to unsigned
-                } else {
-                    Bits::from(offset as u8) // Positive offset
-                }
-            }
-        };
-
         let alu_input_b = match control_signals.alu_mux {
             AluMux::R2 => b,
-            AluMux::BypassRegisterFile => immediate,
+            AluMux::BypassRegisterFile => immediate(instruction, control_signals.immediate_mux),
         };
         let alu_result = self.alu.compute(a, alu_input_b);
 
+        let io_port = self.io_port(alu_result.into());
+        let bank_select = self.bank_select_address() == Some(alu_result.into());
         let data = match control_signals.data_mux {
             DataMux::Alu => alu_result,
             DataMux::Immediate => instruction.slice(0),
             DataMux::Memory => {
-                if alu_result >= Bits::from(240u8) {
+                if let Some(port) = io_port {
                     // If the ALU result is an I/O address, read from the corresponding device
-                    if control_signals.memory_access == MemoryAccess::Read {
-                        // Read from the I/O device
-                        self.io_devices.on_read(alu_result)
-                    } else {
-                        unreachable!("attempted to load value from memory without read access")
+                    if !self.io_devices.can_load(port) {
+                        self.fault(RuntimeError::ReadFromWriteOnlyPort(port))?;
                     }
+                    let value = self.io_devices.on_read(port);
+                    self.io_access = Some(Access::Read {
+                        address: alu_result.into(),
+                        value: value.into(),
+                    });
+                    value
+                } else if bank_select {
+                    let value = Bits::from(self.data_memory.bank() as u8);
+                    self.io_access = Some(Access::Read {
+                        address: alu_result.into(),
+                        value: value.into(),
+                    });
+                    value
                 } else {
                     // Otherwise, read from the data memory
-                    self.data_memory.read(alu_result)
+                    let value = self.data_memory.read(alu_result);
+                    self.memory_access = Some(Access::Read {
+                        address: alu_result.into(),
+                        value: value.into(),
+                    });
+                    value
                 }
             }
         };
-        if alu_result >= Bits::from(240u8) {
+        if let Some(port) = io_port {
             // If the ALU result is an I/O address, write to the corresponding device
             if control_signals.memory_access == MemoryAccess::Write {
-                self.io_devices.on_write(alu_result, b);
+                if !self.io_devices.can_store(port) {
+                    self.fault(RuntimeError::WriteToReadOnlyPort(port))?;
+                }
+                self.io_devices.on_write(port, b);
+                self.io_access = Some(Access::Write {
+                    address: alu_result.into(),
+                    value: b.into(),
+                });
+            }
+        } else if bank_select {
+            if control_signals.memory_access == MemoryAccess::Write {
+                self.data_memory.select_bank(b.to_usize());
+                self.io_access = Some(Access::Write {
+                    address: alu_result.into(),
+                    value: b.into(),
+                });
             }
         } else {
             // Otherwise, write to the data memory
             self.data_memory.schedule_write((alu_result, b));
+            if control_signals.memory_access == MemoryAccess::Write {
+                self.memory_access = Some(Access::Write {
+                    address: alu_result.into(),
+                    value: b.into(),
+                });
+            }
         }
 
         let write_address = match control_signals.dest_mux {
@@ -175,19 +370,233 @@ impl VM {
         }
 
         self.pc.value = next_pc;
+        Ok(())
+    }
+
+    // Runs one instruction. On a fault nothing is changed and the PC stays on the
+    // faulting instruction.
+    pub fn step(&mut self) -> crate::Result<OpCode> {
+        Ok(self.try_clock()?)
     }
 
+    // Like `step`, but a fault simply reads as HLT
     pub fn clock(&mut self) -> OpCode {
+        self.try_clock().unwrap_or(crate::OPCODE_HLT)
+    }
+
+    pub(crate) fn try_clock(&mut self) -> Result<OpCode, Fault> {
         let instr_adr = self.pc.clock().to_usize();
-        if instr_adr >= self.instruction_memory.instructions.len() {
-            return crate::OPCODE_HLT;
-        }
+        let pc = instr_adr as u16;
         let instruction = self.instruction_memory.instructions[instr_adr];
-        self.process_instruction(instruction);
+        self.emulated_fault = None;
+        let result = if instr_adr >= self.program_len {
+            self.fault(RuntimeError::PcOutOfBounds)
+        } else {
+            Ok(())
+        };
+        let result = result.and_then(|()| {
+            if self.observers.0.is_empty() {
+                self.execute(instruction).map(|_| ())
+            } else {
+                self.execute_observed(pc, instruction)
+            }
+        });
+        result.map_err(|error| Fault { pc, error })?;
+        Ok(instruction.slice(12))
+    }
+
+    // Runs one instruction and returns the register write it made
+    fn execute(
+        &mut self,
+        instruction: ProgramInstruction,
+    ) -> Result<Option<(Bits<4>, Immediate)>, RuntimeError> {
+        let undo = self
+            .history
+            .is_enabled()
+            .then(|| self.undo_for(instruction));
+        self.process_instruction(instruction)?;
+        let register_write = self.reg_file.write_buffer;
+        if let Some(mut undo) = undo {
+            undo.register = register_write.map(|(index, _)| {
+                let index = index.to_usize();
+                (index, self.reg_file.register_banks[0][index])
+            });
+            if let Some(Access::Write { address, .. }) = self.memory_access {
+                let address = address as usize;
+                undo.memory = Some((address, self.data_memory.memory[address]));
+            }
+            self.history.push(undo);
+        }
+        self.clock_registers();
+        self.io_devices.tick();
+        Ok(register_write)
+    }
+
+    // Register and memory writes are filled in once the instruction has been processed
+    fn undo_for(&self, word: ProgramInstruction) -> Undo {
+        let signals = self.control_rom.decode(word, self.config.extended_alu);
+        let call_stack = signals.call_stack_state != CallStackState::Disabled;
+        let writes = signals.memory_access == MemoryAccess::Write;
+        let (touches_io, selects_bank) = if writes || signals.data_mux == DataMux::Memory {
+            let address = self.memory_address(word, signals);
+            let selects_bank = writes && self.bank_select_address() == Some(address);
+            (self.io_port(address).is_some(), selects_bank)
+        } else {
+            (false, false)
+        };
+        Undo {
+            pc: self.pc.value,
+            flags: self.alu.flags,
+            register: None,
+            memory: None,
+            call_stack: call_stack.then(|| Box::new(self.call_stack.clone())),
+            io_devices: touches_io.then(|| Box::new(self.io_devices.clone())),
+            bank: selects_bank.then_some(self.data_memory.bank()),
+        }
+    }
+
+    // The data address `process_instruction` will compute, worked out on copies of the
+    // register file and ALU
+    fn memory_address(&self, instruction: ProgramInstruction, signals: ControlSignals) -> u8 {
+        let mut reg_file = self.reg_file.clone();
+        reg_file.enable(signals.reg_file_enable);
+        reg_file.set_read_addresses(read_addresses(instruction, signals.read_mux));
+        let [a, b] = reg_file.read_outputs;
+        let b = match signals.alu_mux {
+            AluMux::R2 => b,
+            AluMux::BypassRegisterFile => immediate(instruction, signals.immediate_mux),
+        };
+        let mut alu = self.alu;
+        alu.set_setting(signals.alu_settings);
+        alu.compute(a, b).into()
+    }
+
+    // Keeps the last `capacity` instructions so they can be undone with `step_back`.
+    // A capacity of 0 turns the history off.
+    pub fn enable_history(&mut self, capacity: usize) {
+        self.history.capacity = capacity;
+        while self.history.entries.len() > capacity {
+            self.history.entries.pop_front();
+        }
+    }
+
+    pub fn history_len(&self) -> usize {
+        self.history.entries.len()
+    }
+
+    // Undoes the last executed instruction, returning false when there is nothing to undo
+    pub fn step_back(&mut self) -> bool {
+        let Some(undo) = self.history.entries.pop_back() else {
+            return false;
+        };
+        self.pc.value = undo.pc;
+        self.alu.flags = undo.flags;
+        if let Some((index, value)) = undo.register {
+            for bank in self.reg_file.register_banks.iter_mut() {
+                bank[index] = value;
+            }
+        }
+        if let Some((address, value)) = undo.memory {
+            self.data_memory.memory[address] = value;
+        }
+        if let Some(call_stack) = undo.call_stack {
+            self.call_stack = *call_stack;
+        }
+        if let Some(io_devices) = undo.io_devices {
+            self.io_devices = *io_devices;
+        }
+        if let Some(bank) = undo.bank {
+            self.data_memory.select_bank(bank);
+        }
+        true
+    }
+
+    // Steps back until the instruction at `pc` is the next one to run, leaving the
+    // machine alone when `pc` is not in the history
+    pub fn run_back_to(&mut self, pc: u16) -> bool {
+        if !self
+            .history
+            .entries
+            .iter()
+            .any(|undo| undo.pc.to_usize() == pc as usize)
+        {
+            return false;
+        }
+        while self.step_back() {
+            if self.pc.value.to_usize() == pc as usize {
+                return true;
+            }
+        }
+        unreachable!("pc {pc} was in the history")
+    }
+
+    fn clock_registers(&mut self) {
         self.reg_file.clock();
         self.call_stack.stack.clock();
         self.data_memory.clock();
-        instruction.slice(12)
+    }
+
+    fn execute_observed(&mut self, pc: u16, word: ProgramInstruction) -> Result<(), RuntimeError> {
+        let instruction = Instruction::decode_with(word, self.config.extended_alu);
+        // observers are moved out so that they can look at the VM while being notified
+        let mut observers = std::mem::take(&mut self.observers);
+        for observer in observers.0.iter_mut() {
+            if observer.before_instruction(self, pc, instruction) == Control::Pause {
+                self.paused = true;
+            }
+        }
+
+        let flags_before = self.alu.flags;
+        let register_write = match self.execute(word) {
+            Ok(write) => write.map(|(index, value)| (index.to_usize() as u8, u8::from(value))),
+            Err(error) => {
+                self.observers = observers;
+                return Err(error);
+            }
+        };
+
+        let event = StepEvent {
+            pc,
+            word,
+            instruction,
+            next_pc: self.pc.value.to_usize() as u16,
+            register_write,
+            memory: self.memory_access,
+            io: self.io_access,
+            flags_before,
+            flags: self.alu.flags,
+            fault: self.emulated_fault,
+        };
+        for observer in observers.0.iter_mut() {
+            if observer.after_instruction(self, &event) == Control::Pause {
+                self.paused = true;
+            }
+        }
+        self.observers = observers;
+        Ok(())
+    }
+}
+
+// The two registers the instruction reads
+fn read_addresses(instruction: ProgramInstruction, read_mux: ReadMux) -> [Bits<4>; 2] {
+    let r2 = match read_mux {
+        ReadMux::Second => instruction.slice(4),
+        ReadMux::Third => instruction.slice(0),
+    };
+    [instruction.slice(8), r2]
+}
+
+fn immediate(instruction: ProgramInstruction, immediate_mux: ImmediateMux) -> Bits<8> {
+    match immediate_mux {
+        ImmediateMux::Immediate => instruction.slice(0),
+        ImmediateMux::Offset => {
+            let offset = instruction.slice::<4>(0).to_signed(); // signed offset from -8 to 7
+            if offset < 0 {
+                Bits::from((256isize + offset) as u8) // Convert negative offset to unsigned
+            } else {
+                Bits::from(offset as u8) // Positive offset
+            }
+        }
     }
 }
 
@@ -0,0 +1,67 @@
+use crate::error::RuntimeError;
+use crate::instruction::Instruction;
+use crate::{AluFlags, ProgramInstruction, VM};
+use std::any::Any;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read { address: u8, value: u8 },
+    Write { address: u8, value: u8 },
+}
+
+// Everything one executed instruction did to the machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepEvent {
+    pub pc: u16,
+    pub word: ProgramInstruction,
+    pub instruction: Instruction,
+    pub next_pc: u16,
+    pub register_write: Option<(u8, u8)>, // (register, value)
+    pub memory: Option<Access>,
+    pub io: Option<Access>,
+    pub flags_before: AluFlags,
+    pub flags: AluFlags,
+    pub fault: Option<RuntimeError>, // a fault carried on from under `FaultPolicy::Emulate`
+}
+
+// A pause takes effect once the current instruction has finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Control {
+    #[default]
+    Continue,
+    Pause,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunOutcome {
+    Halted,
+    Paused,
+}
+
+pub trait Observer: Any {
+    fn before_instruction(&mut self, _vm: &VM, _pc: u16, _instruction: Instruction) -> Control {
+        Control::Continue
+    }
+
+    fn after_instruction(&mut self, _vm: &VM, _event: &StepEvent) -> Control {
+        Control::Continue
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Observers(pub(crate) Vec<Box<dyn Observer>>);
+
+impl fmt::Debug for Observers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Observers({})", self.0.len())
+    }
+}
+
+impl Observers {
+    pub(crate) fn position<O: Observer>(&self) -> Option<usize> {
+        self.0
+            .iter()
+            .position(|observer| (observer.as_ref() as &dyn Any).is::<O>())
+    }
+}
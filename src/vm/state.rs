@@ -0,0 +1,317 @@
+// Machine snapshots. A state file is the `BPSS` magic, a little-endian u16 version and
+// then every part of the machine in a fixed order, see `VM::state_bytes`.
+use crate::error::VmError;
+use crate::io_devices::controller::Button;
+use crate::io_devices::number_display::DisplayState;
+use crate::io_devices::rng::RngSource;
+use crate::io_devices::screen::Screen;
+use crate::io_devices::IoDevices;
+use crate::registers::call_stack::CallStack;
+use crate::vm::builder::check_stack_depth;
+use crate::{AluFlags, Bits, Result, VM};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"BPSS";
+const VERSION: u16 = 1;
+
+impl VM {
+    pub fn save_state(&self, file_path: impl AsRef<Path>) -> Result<()> {
+        std::fs::write(file_path, self.state_bytes())?;
+        Ok(())
+    }
+
+    pub fn load_state(&mut self, file_path: impl AsRef<Path>) -> Result<()> {
+        self.restore_state(&std::fs::read(file_path)?)
+    }
+
+    pub fn state_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend(VERSION.to_le_bytes());
+        for instruction in self.instruction_memory.instructions.iter() {
+            out.extend(u16::from(*instruction).to_le_bytes());
+        }
+        out.extend((self.pc.value.to_usize() as u16).to_le_bytes());
+        for bank in self.reg_file.register_banks.iter() {
+            out.extend(bank.iter().map(|&value| u8::from(value)));
+        }
+        out.extend(self.data_memory.memory.iter().map(|&value| u8::from(value)));
+        out.extend((self.call_stack.max_depth() as u32).to_le_bytes());
+        out.extend((self.call_stack.depth() as u32).to_le_bytes());
+        // every slot is written, the empty ones as zeroes
+        let empty = self.call_stack.max_depth() - self.call_stack.depth();
+        for address in self.call_stack.contents() {
+            out.extend((address.to_usize() as u16).to_le_bytes());
+        }
+        out.extend(std::iter::repeat_n(0, 2 * empty));
+        let flags = self.flags();
+        out.push(u8::from(flags.zero) | u8::from(flags.carry) << 1);
+        write_devices(&mut out, &self.io_devices);
+        // the selected bank is already in data memory
+        let memory = &self.data_memory;
+        out.extend((memory.bank_count() as u32).to_le_bytes());
+        out.extend((memory.bank_window() as u32).to_le_bytes());
+        out.extend((memory.bank() as u32).to_le_bytes());
+        for (bank, contents) in memory.banks.iter().enumerate() {
+            if bank != memory.bank() {
+                out.extend(contents.iter().map(|&value| u8::from(value)));
+            }
+        }
+        out.extend((self.program_len as u32).to_le_bytes());
+        out
+    }
+
+    // Replaces the whole machine with a snapshot. The undo history is cleared since it
+    // belongs to the state being replaced.
+    pub fn restore_state(&mut self, bytes: &[u8]) -> Result<()> {
+        let mut reader = Reader { bytes, position: 0 };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(VmError::State("not a state file".to_string()));
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(VmError::State(format!("unsupported version {version}")));
+        }
+
+        let mut instructions = self.instruction_memory.instructions;
+        for instruction in instructions.iter_mut() {
+            *instruction = Bits::from(reader.u16()?);
+        }
+        let pc = reader.u16()?;
+        let mut register_banks = self.reg_file.register_banks;
+        for bank in register_banks.iter_mut() {
+            for value in bank.iter_mut() {
+                *value = Bits::from(reader.u8()?);
+            }
+        }
+        let mut memory = self.data_memory.memory;
+        for value in memory.iter_mut() {
+            *value = Bits::from(reader.u8()?);
+        }
+        let max_depth = reader.u32()? as usize;
+        let depth = reader.u32()? as usize;
+        check_stack_depth(max_depth)?;
+        let mut call_stack = CallStack::with_depth(max_depth);
+        for _ in 0..max_depth {
+            let address = Bits::from(reader.u16()?).resize();
+            call_stack.stack.stack.push_back(address);
+        }
+        if depth > max_depth {
+            return Err(VmError::State(format!(
+                "call stack depth {depth} exceeds its size {max_depth}"
+            )));
+        }
+        call_stack.stack.stack.truncate(depth);
+        let flags = reader.u8()?;
+        let io_devices = read_devices(&mut reader, &self.io_devices)?;
+        let (banks, bank) = self.read_banks(&mut reader)?;
+        let program_len = reader.u32()? as usize;
+        if program_len > self.instruction_memory.size {
+            return Err(VmError::State(format!(
+                "program length {program_len} exceeds the instruction memory of {}",
+                self.instruction_memory.size
+            )));
+        }
+        if reader.position != bytes.len() {
+            return Err(VmError::State(format!(
+                "{} unexpected bytes at the end",
+                bytes.len() - reader.position
+            )));
+        }
+
+        // only touch the machine once the whole file has been read
+        self.instruction_memory.instructions = instructions;
+        self.program_len = program_len;
+        self.pc.value = Bits::from(pc).resize();
+        self.reg_file.register_banks = register_banks;
+        self.data_memory.memory = memory;
+        self.data_memory.banks = banks;
+        self.data_memory.bank = bank;
+        self.config.stack_depth = call_stack.max_depth();
+        self.call_stack = call_stack;
+        self.alu.flags = AluFlags {
+            zero: flags & 1 == 1,
+            carry: flags & 2 == 2,
+        };
+        self.io_devices = io_devices;
+        self.history.entries.clear();
+        Ok(())
+    }
+}
+
+impl VM {
+    // The banks are part of the machine, so the file has to have the same layout
+    fn read_banks(&self, reader: &mut Reader) -> Result<(Vec<Vec<Bits<8>>>, usize)> {
+        let memory = &self.data_memory;
+        let count = reader.u32()? as usize;
+        let window = reader.u32()? as usize;
+        if (count, window) != (memory.bank_count(), memory.bank_window()) {
+            return Err(VmError::State(format!(
+                "the file has {count} memory banks of {window} bytes, the machine {} of {}",
+                memory.bank_count(),
+                memory.bank_window()
+            )));
+        }
+        let bank = reader.u32()? as usize;
+        if bank >= count {
+            return Err(VmError::State(format!(
+                "memory bank {bank} is selected out of {count}"
+            )));
+        }
+        let mut banks = memory.banks.clone();
+        for (index, contents) in banks.iter_mut().enumerate() {
+            if index != bank {
+                for value in contents.iter_mut() {
+                    *value = Bits::from(reader.u8()?);
+                }
+            }
+        }
+        Ok((banks, bank))
+    }
+}
+
+fn write_devices(out: &mut Vec<u8>, devices: &IoDevices) {
+    let screen = &devices.screen;
+    out.extend([screen.current_x as u8, screen.current_y as u8]);
+    write_pixels(out, &screen.buffer);
+    write_pixels(out, &screen.active);
+
+    let characters = &devices.character_display;
+    for text in [&characters.buffer, &characters.active] {
+        out.extend((text.len() as u32).to_le_bytes());
+        out.extend(text.as_bytes());
+    }
+
+    let number = &devices.number_display;
+    let signed = number.state == DisplayState::SignedMode;
+    out.extend([
+        u8::from(number.display),
+        u8::from(signed),
+        u8::from(number.active),
+    ]);
+    let rng = &devices.rng;
+    out.extend([u8::from(rng.seed), u8::from(rng.state)]);
+    out.extend(rng.host_seed.to_le_bytes());
+    out.extend(rng.draws.to_le_bytes());
+    match &rng.source {
+        RngSource::Lfsr => out.push(0),
+        RngSource::Sequence(values) => {
+            out.push(1);
+            out.extend((values.len() as u32).to_le_bytes());
+            out.extend(values);
+        }
+        RngSource::Host => out.push(2),
+    }
+    out.push(u8::from(devices.controller.value));
+}
+
+// The screen size and the devices on the bus belong to the machine, not to the file, so
+// user devices keep their state
+fn read_devices(reader: &mut Reader, machine: &IoDevices) -> Result<IoDevices> {
+    let mut devices = IoDevices {
+        screen: Screen::with_size(machine.screen.width, machine.screen.height),
+        bus: machine.bus.clone(),
+        ..IoDevices::default()
+    };
+    let screen = &mut devices.screen;
+    screen.current_x = reader.u8()? as usize & 0x1F;
+    screen.current_y = reader.u8()? as usize & 0x1F;
+    screen.buffer = read_pixels(reader)?;
+    screen.active = read_pixels(reader)?;
+
+    let characters = &mut devices.character_display;
+    for text in [&mut characters.buffer, &mut characters.active] {
+        let len = reader.u32()?;
+        *text = String::from_utf8(reader.take(len as usize)?.to_vec())
+            .map_err(|_| VmError::State("character display is not valid text".to_string()))?;
+    }
+
+    let number = &mut devices.number_display;
+    number.display = Bits::from(reader.u8()?);
+    number.state = match reader.u8()? {
+        1 => DisplayState::SignedMode,
+        _ => DisplayState::UnsignedMode,
+    };
+    number.active = reader.u8()? == 1;
+    let rng = &mut devices.rng;
+    rng.seed = Bits::from(reader.u8()?);
+    rng.state = Bits::from(reader.u8()?);
+    rng.host_seed = reader.u64()?;
+    rng.draws = reader.u64()?;
+    rng.source = match reader.u8()? {
+        0 => RngSource::Lfsr,
+        1 => {
+            let len = reader.u32()?;
+            RngSource::Sequence(reader.take(len as usize)?.to_vec())
+        }
+        2 => RngSource::Host,
+        other => return Err(VmError::State(format!("unknown RNG source {other}"))),
+    };
+    rng.restore_host();
+
+    // the individual buttons follow from the controller byte
+    let value = reader.u8()?;
+    for (bit, button) in Button::ALL.into_iter().enumerate() {
+        devices.controller.set(button, value >> bit & 1 == 1);
+    }
+    Ok(devices)
+}
+
+// 32 rows of 32 pixels, packed into 4 bytes per row
+fn write_pixels(out: &mut Vec<u8>, pixels: &[[bool; 32]; 32]) {
+    for row in pixels {
+        for byte in row.chunks(8) {
+            let packed = byte
+                .iter()
+                .enumerate()
+                .fold(0u8, |acc, (bit, &on)| acc | u8::from(on) << bit);
+            out.push(packed);
+        }
+    }
+}
+
+fn read_pixels(reader: &mut Reader) -> Result<[[bool; 32]; 32]> {
+    let mut pixels = [[false; 32]; 32];
+    for row in pixels.iter_mut() {
+        let packed = reader.take(4)?;
+        for (index, pixel) in row.iter_mut().enumerate() {
+            *pixel = packed[index / 8] >> (index % 8) & 1 == 1;
+        }
+    }
+    Ok(pixels)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.position..self.position + len)
+            .ok_or_else(|| VmError::State("unexpected end of file".to_string()))?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+}
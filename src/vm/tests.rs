@@ -1,4 +1,6 @@
-use crate::VM;
+use crate::instruction::Instruction;
+use crate::lockstep::LockStep;
+use crate::{Access, Control, Observer, RunOutcome, StepEvent, VM};
 
 #[test]
 fn dvd() {
@@ -11,3 +13,1183 @@ fn dvd() {
         vm.clock();
     }
 }
+
+#[derive(Default)]
+struct Recorder {
+    before: Vec<u16>,
+    events: Vec<StepEvent>,
+    pause_at: Option<u16>,
+}
+
+impl Observer for Recorder {
+    fn before_instruction(&mut self, _vm: &VM, pc: u16, _instruction: Instruction) -> Control {
+        self.before.push(pc);
+        Control::Continue
+    }
+
+    fn after_instruction(&mut self, _vm: &VM, event: &StepEvent) -> Control {
+        self.events.push(*event);
+        if Some(event.next_pc) == self.pause_at {
+            Control::Pause
+        } else {
+            Control::Continue
+        }
+    }
+}
+
+const OBSERVED: &str = "\
+LDI r1 5
+STR r0 r1 3
+LOD r0 r2 3
+LDI r3 show_number
+STR r3 r1
+CMP r1 r2
+HLT
+";
+
+#[test]
+fn observer_sees_every_effect() {
+    let mut vm = VM::new();
+    vm.load_source(OBSERVED).unwrap();
+    vm.add_observer(Recorder::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+
+    let recorder = vm.remove_observer::<Recorder>().unwrap();
+    assert_eq!(recorder.before, vec![0, 1, 2, 3, 4, 5, 6]);
+    let events = recorder.events;
+    assert_eq!(events[0].register_write, Some((1, 5)));
+    assert_eq!(
+        events[0].instruction,
+        Instruction::Ldi { a: 1, immediate: 5 }
+    );
+    assert_eq!(
+        events[1].memory,
+        Some(Access::Write {
+            address: 3,
+            value: 5
+        })
+    );
+    assert_eq!(events[1].register_write, None);
+    assert_eq!(
+        events[2].memory,
+        Some(Access::Read {
+            address: 3,
+            value: 5
+        })
+    );
+    assert_eq!(events[2].register_write, Some((2, 5)));
+    assert_eq!(
+        events[4].io,
+        Some(Access::Write {
+            address: 250,
+            value: 5
+        })
+    );
+    assert!(!events[5].flags_before.zero);
+    assert!(events[5].flags.zero);
+    assert_eq!(events[6].next_pc, 7);
+    assert!(vm.observer::<Recorder>().is_none());
+}
+
+#[test]
+fn observer_can_pause() {
+    let mut vm = VM::new();
+    vm.load_source(OBSERVED).unwrap();
+    vm.add_observer(Recorder {
+        pause_at: Some(4),
+        ..Recorder::default()
+    });
+    assert_eq!(vm.run().unwrap(), RunOutcome::Paused);
+    assert!(vm.is_paused());
+    assert_eq!(vm.pc.value.to_usize(), 4);
+    assert_eq!(vm.observer::<Recorder>().unwrap().events.len(), 4);
+
+    vm.observer_mut::<Recorder>().unwrap().pause_at = None;
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+    assert!(!vm.is_paused());
+}
+
+type Snapshot = (
+    crate::registers::register_file::RegisterBank,
+    Vec<crate::Bits<8>>,
+    crate::Address,
+    crate::AluFlags,
+    Vec<crate::Address>,
+    usize,
+    crate::io_devices::IoDevices,
+);
+
+fn snapshot(vm: &VM) -> Snapshot {
+    (
+        vm.reg_file.register_banks[0],
+        vm.data_memory.memory.to_vec(),
+        vm.pc.value,
+        vm.flags(),
+        vm.call_stack.contents().copied().collect::<Vec<_>>(),
+        vm.call_stack.depth(),
+        vm.io_devices.clone(),
+    )
+}
+
+#[test]
+fn step_back_restores_every_state() {
+    for program in ["programs/dvd.as", "programs/minesweeper.as"] {
+        let mut vm = VM::new();
+        vm.load_source(&std::fs::read_to_string(program).unwrap())
+            .unwrap();
+        vm.enable_history(1000);
+        let mut snapshots = vec![];
+        for _ in 0..600 {
+            snapshots.push(snapshot(&vm));
+            vm.clock();
+        }
+        assert_eq!(vm.history_len(), 600);
+        while let Some(expected) = snapshots.pop() {
+            assert!(vm.step_back());
+            assert_eq!(
+                snapshot(&vm),
+                expected,
+                "{program} at step {}",
+                snapshots.len()
+            );
+        }
+        assert!(!vm.step_back());
+    }
+}
+
+// The history follows the control ROM, not the BatPU-2 decoding of the word
+#[test]
+fn step_back_with_a_custom_control_rom() {
+    use crate::control_rom::ControlRom;
+
+    let batpu2 = ControlRom::batpu2();
+    let rom = batpu2
+        .clone()
+        .with_instruction(0b0000, "CAL", batpu2.signals(0b1100))
+        .with_instruction(0b1110, "STR", batpu2.signals(0b1111));
+    let mut program = crate::assemble("LDI r1 240\nLDI r2 5").unwrap();
+    // stores r2 to the pixel x port, then calls address 5
+    program.extend([0xE120u16, 0x0005, 0x1000, 0x1000].map(crate::Bits::from));
+
+    let mut vm = VM::builder().control_rom(rom).build().unwrap();
+    vm.load_instructions(&program).unwrap();
+    vm.enable_history(10);
+    let mut snapshots = vec![];
+    for _ in 0..4 {
+        snapshots.push(snapshot(&vm));
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.call_stack.depth(), 1);
+    assert_ne!(vm.io_devices, snapshots[2].6);
+    while let Some(expected) = snapshots.pop() {
+        assert!(vm.step_back());
+        assert_eq!(snapshot(&vm), expected, "step {}", snapshots.len());
+    }
+}
+
+#[test]
+fn history_is_bounded() {
+    let mut vm = VM::new();
+    vm.load_source("LDI r1 1\n.loop\nADD r1 r1 r1\nJMP .loop")
+        .unwrap();
+    vm.enable_history(10);
+    for _ in 0..50 {
+        vm.clock();
+    }
+    assert_eq!(vm.history_len(), 10);
+    assert!(vm.run_back_to(1));
+    assert_eq!(vm.pc.value.to_usize(), 1);
+    let history_len = vm.history_len();
+    let registers = vm.reg_file.register_banks;
+    assert!(!vm.run_back_to(0));
+    assert_eq!(vm.pc.value.to_usize(), 1);
+    assert_eq!(vm.history_len(), history_len);
+    assert_eq!(vm.reg_file.register_banks, registers);
+}
+
+#[test]
+fn state_round_trip() {
+    let mut vm = VM::new();
+    vm.load_source(&std::fs::read_to_string("programs/minesweeper.as").unwrap())
+        .unwrap();
+    vm.io_devices.controller.set_right(true);
+    for _ in 0..700 {
+        vm.clock();
+    }
+    vm.io_devices.character_display.active.push_str("hi!");
+    let path = std::env::temp_dir().join(format!("rust_vm_state_{}.bpss", std::process::id()));
+    vm.save_state(&path).unwrap();
+
+    let mut restored = VM::new();
+    restored.load_state(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(snapshot(&restored), snapshot(&vm));
+    assert_eq!(
+        restored.instruction_memory.instructions,
+        vm.instruction_memory.instructions
+    );
+    for _ in 0..300 {
+        assert_eq!(restored.clock(), vm.clock());
+        assert_eq!(snapshot(&restored), snapshot(&vm));
+    }
+}
+
+#[test]
+fn invalid_state() {
+    use crate::error::VmError;
+
+    let mut vm = VM::new();
+    let bytes = vm.state_bytes();
+    assert_eq!(
+        vm.restore_state(b"nope"),
+        Err(VmError::State("not a state file".to_string()))
+    );
+    let mut future = bytes.clone();
+    future[4] = 9;
+    assert_eq!(
+        vm.restore_state(&future),
+        Err(VmError::State("unsupported version 9".to_string()))
+    );
+    assert_eq!(
+        vm.restore_state(&bytes[..100]),
+        Err(VmError::State("unexpected end of file".to_string()))
+    );
+    let mut longer = bytes.clone();
+    longer.push(0);
+    assert_eq!(
+        vm.restore_state(&longer),
+        Err(VmError::State("1 unexpected bytes at the end".to_string()))
+    );
+    let stack_start = 6 + 1024 * 2 + 2 + 32 + 256;
+    let stack_size = |size: u32| {
+        let mut bytes = bytes.clone();
+        bytes[stack_start..stack_start + 4].copy_from_slice(&size.to_le_bytes());
+        bytes
+    };
+    assert_eq!(
+        vm.restore_state(&stack_size(u32::MAX)),
+        Err(VmError::Config(
+            "call stack depth must be between 1 and 65536, found 4294967295".to_string()
+        ))
+    );
+    assert_eq!(
+        vm.restore_state(&stack_size(0)),
+        Err(VmError::Config(
+            "call stack depth must be between 1 and 65536, found 0".to_string()
+        ))
+    );
+    assert_eq!(
+        vm.restore_state(&stack_size(1 << 16)),
+        Err(VmError::State("unexpected end of file".to_string()))
+    );
+    assert_eq!(vm.restore_state(&bytes), Ok(()));
+}
+
+fn trapping(source: &str) -> VM {
+    let mut vm = VM::with_config(crate::VmConfig {
+        fault_policy: crate::FaultPolicy::Trap,
+        ..Default::default()
+    });
+    vm.load_source(source).unwrap();
+    vm
+}
+
+#[test]
+fn trailing_nops_survive_state_round_trip() {
+    let mut vm = trapping("LDI r1 1\nNOP\nNOP");
+    vm.step().unwrap();
+    let mut restored = trapping("");
+    restored.restore_state(&vm.state_bytes()).unwrap();
+    let fault = run_to_fault(&mut restored);
+    assert_eq!(fault.error, crate::RuntimeError::PcOutOfBounds);
+    assert_eq!(fault.pc, 3);
+}
+
+fn run_to_fault(vm: &mut VM) -> crate::Fault {
+    match vm.run() {
+        Err(crate::error::VmError::Runtime(fault)) => fault,
+        other => panic!("expected a fault, got {other:?}"),
+    }
+}
+
+#[test]
+fn trap_call_stack_underflow() {
+    use crate::RuntimeError::CallStackUnderflow;
+
+    let mut vm = trapping("LDI r1 1\nRET\nHLT");
+    let fault = run_to_fault(&mut vm);
+    assert_eq!(
+        fault,
+        crate::Fault {
+            pc: 1,
+            error: CallStackUnderflow
+        }
+    );
+    assert_eq!(vm.pc.value.to_usize(), 1);
+    assert_eq!(
+        vm.step().unwrap_err().to_string(),
+        "Runtime error: call stack underflow at pc 1"
+    );
+
+    let mut vm = VM::new();
+    vm.load_source("LDI r1 1\nRET\nHLT").unwrap();
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+}
+
+#[test]
+fn trap_call_stack_overflow() {
+    let mut vm = trapping(".recurse\nCAL .recurse");
+    let fault = run_to_fault(&mut vm);
+    assert_eq!(fault.error, crate::RuntimeError::CallStackOverflow);
+    assert_eq!(vm.call_stack().len(), 16);
+}
+
+#[test]
+fn trap_pc_out_of_bounds() {
+    let mut vm = trapping("LDI r1 1");
+    assert_eq!(
+        run_to_fault(&mut vm),
+        crate::Fault {
+            pc: 1,
+            error: crate::RuntimeError::PcOutOfBounds
+        }
+    );
+}
+
+#[test]
+fn trap_port_direction() {
+    let mut vm = trapping("LDI r1 rng\nSTR r1 r0\nHLT");
+    assert_eq!(
+        run_to_fault(&mut vm).error,
+        crate::RuntimeError::WriteToReadOnlyPort(254)
+    );
+
+    let mut vm = trapping("LDI r1 show_number\nLOD r1 r2\nHLT");
+    assert_eq!(
+        run_to_fault(&mut vm).error,
+        crate::RuntimeError::ReadFromWriteOnlyPort(250)
+    );
+    assert_eq!(vm.reg_file.register_banks[0][2].to_usize(), 0);
+
+    let mut vm = VM::new();
+    vm.load_source("LDI r1 show_number\nLOD r1 r2\nHLT")
+        .unwrap();
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+}
+
+// Loads from the store-only screen ports read 0 when the fault is emulated
+#[test]
+fn emulated_loads_from_screen_ports() {
+    let mut vm = VM::new();
+    vm.load_source(
+        "LDI r15 pixel_x
+LDI r1 3
+STR r15 r1 0
+STR r15 r1 1
+STR r15 r0 2
+STR r15 r0 5
+LOD r15 r1 0
+LOD r15 r2 1
+LOD r15 r3 2
+LOD r15 r4 3
+LOD r15 r5 4
+LOD r15 r6 5
+LOD r15 r7 6
+HLT",
+    )
+    .unwrap();
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+    let registers: Vec<u8> = vm.reg_file.register_banks[0][1..8]
+        .iter()
+        .map(|&value| u8::from(value))
+        .collect();
+    assert_eq!(registers, [0, 0, 0, 0, 1, 0, 0]);
+}
+
+#[test]
+fn return_to_address_zero() {
+    let source = format!(
+        "JMP .call\n{}.sub\nRET\n.call\nCAL .sub\n",
+        "NOP\n".repeat(1021)
+    );
+    let mut vm = trapping(&source);
+    for _ in 0..2 {
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.call_stack(), vec![0]);
+    vm.step().unwrap();
+    assert_eq!(vm.pc.value.to_usize(), 0);
+    assert!(vm.call_stack().is_empty());
+}
+
+#[test]
+fn configurable_stack_depth() {
+    let mut vm = VM::with_config(crate::VmConfig {
+        fault_policy: crate::FaultPolicy::Trap,
+        stack_depth: 40,
+        ..Default::default()
+    });
+    vm.load_source(".recurse\nCAL .recurse").unwrap();
+    let fault = run_to_fault(&mut vm);
+    assert_eq!(fault.error, crate::RuntimeError::CallStackOverflow);
+    assert_eq!(vm.call_stack().len(), 40);
+
+    // a restored stack brings its size along
+    let mut restored = VM::new();
+    restored.restore_state(&vm.state_bytes()).unwrap();
+    assert_eq!(restored.config.stack_depth, 40);
+    assert_eq!(restored.call_stack(), vm.call_stack());
+}
+
+#[test]
+fn emulated_overflow_is_observed() {
+    let mut vm = VM::with_config(crate::VmConfig {
+        stack_depth: 2,
+        ..Default::default()
+    });
+    vm.load_source("CAL .a\nHLT\n.a\nCAL .b\nRET\nHLT\n.b\nCAL .c\nRET\n.c\nRET")
+        .unwrap();
+    vm.add_observer(Recorder::default());
+    assert_eq!(vm.run().unwrap(), RunOutcome::Halted);
+    let events = vm.remove_observer::<Recorder>().unwrap().events;
+    let faults: Vec<_> = events.iter().map(|event| (event.pc, event.fault)).collect();
+    assert_eq!(faults[2], (5, Some(crate::RuntimeError::CallStackOverflow)));
+    // the return address of the first CAL was shifted out, so the last RET falls through
+    assert_eq!(
+        faults[5..],
+        [
+            (3, Some(crate::RuntimeError::CallStackUnderflow)),
+            (4, None)
+        ]
+    );
+}
+
+fn rng_values(config: crate::VmConfig, count: usize) -> Vec<u8> {
+    let mut vm = VM::with_config(config);
+    vm.load_source("LDI r15 254\nLOD r15 r1\nHLT").unwrap();
+    (0..count)
+        .map(|_| {
+            vm.pc.value = Default::default();
+            vm.run().unwrap();
+            u8::from(vm.reg_file.register_banks[0][1])
+        })
+        .collect()
+}
+
+#[test]
+fn seeded_rng_is_reproducible() {
+    use crate::RngSource;
+
+    for source in [RngSource::Lfsr, RngSource::Host] {
+        let config = crate::VmConfig {
+            rng_seed: Some(0xAC),
+            rng_source: source,
+            ..Default::default()
+        };
+        let values = rng_values(config.clone(), 20);
+        assert_eq!(values, rng_values(config, 20));
+        assert!(values.iter().any(|&value| value != values[0]));
+    }
+    let lfsr = rng_values(
+        crate::VmConfig {
+            rng_seed: Some(0xAC),
+            ..Default::default()
+        },
+        3,
+    );
+    assert_eq!(lfsr, vec![89, 178, 101]);
+}
+
+#[test]
+fn rng_sequence_repeats() {
+    let config = crate::VmConfig {
+        rng_source: crate::RngSource::Sequence(vec![4, 8, 15]),
+        ..Default::default()
+    };
+    assert_eq!(rng_values(config, 5), vec![4, 8, 15, 4, 8]);
+}
+
+#[test]
+fn host_rng_survives_state_round_trip() {
+    let mut vm = VM::with_config(crate::VmConfig {
+        rng_seed: Some(12345),
+        rng_source: crate::RngSource::Host,
+        ..Default::default()
+    });
+    // across the point where the host PRNG is reseeded
+    for draws in [10, (1 << 16) - 5] {
+        for _ in 0..draws {
+            vm.io_devices.rng.generate_next();
+        }
+        let mut restored = VM::new();
+        restored.restore_state(&vm.state_bytes()).unwrap();
+        assert_eq!(restored.io_devices.rng, vm.io_devices.rng);
+        for _ in 0..10 {
+            assert_eq!(
+                restored.io_devices.rng.generate_next(),
+                vm.io_devices.rng.generate_next()
+            );
+        }
+    }
+
+    // any draw count restores without replaying it
+    vm.io_devices.rng.draws = u64::MAX;
+    let mut restored = VM::new();
+    restored.restore_state(&vm.state_bytes()).unwrap();
+    assert_eq!(restored.io_devices.rng.draws, u64::MAX);
+}
+
+#[test]
+fn builder_rejects_what_the_isa_cannot_address() {
+    use crate::error::VmError;
+    let error = |builder: crate::VmBuilder| builder.build().unwrap_err();
+    assert_eq!(
+        error(VM::builder().registers(17)),
+        VmError::Config("register count must be between 1 and 16, found 17".to_string())
+    );
+    assert_eq!(
+        error(VM::builder().stack_depth(usize::MAX)),
+        VmError::Config(format!(
+            "call stack depth must be between 1 and 65536, found {}",
+            usize::MAX
+        ))
+    );
+    assert_eq!(
+        error(VM::builder().instruction_memory(2048)),
+        VmError::Config(
+            "instruction memory size must be between 1 and 1024, found 2048".to_string()
+        )
+    );
+    assert_eq!(
+        error(VM::builder().screen(32, 0)),
+        VmError::Config("screen height must be between 1 and 32, found 0".to_string())
+    );
+    assert_eq!(
+        error(VM::builder().io_base(241)),
+        VmError::Config(
+            "IO base 241 leaves no room for 16 ports, it can be at most 240".to_string()
+        )
+    );
+    assert_eq!(
+        VM::builder().build().unwrap().config(),
+        &crate::VmConfig::default()
+    );
+}
+
+#[test]
+fn minimal_machine() {
+    let mut vm = VM::builder()
+        .instruction_memory(8)
+        .data_memory(16)
+        .registers(4)
+        .build()
+        .unwrap();
+    assert_eq!(
+        vm.load_source("NOP\nNOP\nNOP\nNOP\nNOP\nNOP\nNOP\nNOP\nHLT"),
+        Err(crate::error::VmError::InstructionMemoryOverflow)
+    );
+    // r5 and address 20 do not exist
+    vm.load_source(
+        "LDI r5 9\nLDI r1 7\nLDI r2 20\nSTR r2 r1 0\nLOD r2 r3 0\nSTR r0 r1 3\nADD r5 r1 r3\nHLT",
+    )
+    .unwrap();
+    vm.run().unwrap();
+    let registers: Vec<u8> = vm.reg_file.register_banks[0][..6]
+        .iter()
+        .map(|&value| value.into())
+        .collect();
+    assert_eq!(registers, vec![0, 7, 20, 7, 0, 0]);
+    assert_eq!(u8::from(vm.data_memory.memory[3]), 7);
+    assert_eq!(u8::from(vm.data_memory.memory[20]), 0);
+}
+
+const EXTENDED_ALU: &str = "\
+LDI r1 12
+LDI r2 10
+OR r1 r2
+LDI r3 10
+XNOR r1 r3
+LDI r4 10
+NAND r1 r4
+LDI r5 10
+IMP r1 r5
+LDI r6 10
+NIMP r1 r6
+LDI r7 12
+NIMP r1 r7
+BRH zero .done
+LDI r8 1
+.done
+RSH r1 r9
+HLT";
+
+fn registers_after(builder: crate::VmBuilder, source: &str) -> Vec<u8> {
+    let mut vm = builder.build().unwrap();
+    vm.load_source(source).unwrap();
+    vm.run().unwrap();
+    vm.reg_file.register_banks[0][1..10]
+        .iter()
+        .map(|&value| value.into())
+        .collect()
+}
+
+#[test]
+fn extended_alu() {
+    let extended = VM::builder().extended_alu(true);
+    assert_eq!(
+        registers_after(extended.clone(), EXTENDED_ALU),
+        vec![12, 14, 249, 247, 251, 4, 0, 0, 6]
+    );
+    // the BatPU-2 does not assemble them, and ignores the function field of the words, so
+    // every one of them is a shift
+    let mut vm = VM::new();
+    assert!(vm.load_source(EXTENDED_ALU).is_err());
+    vm.load_instructions(&crate::assemble_with(EXTENDED_ALU, true).unwrap())
+        .unwrap();
+    vm.run().unwrap();
+    let registers: Vec<u8> = vm.reg_file.register_banks[0][1..10]
+        .iter()
+        .map(|&value| value.into())
+        .collect();
+    assert_eq!(registers, vec![12, 6, 6, 6, 6, 6, 6, 1, 6]);
+
+    let mut vm = extended.build().unwrap();
+    vm.load_source(EXTENDED_ALU).unwrap();
+    let fast = crate::FastVm::from_vm(&vm);
+    if let Err(divergence) = LockStep::new(vm, fast).run(20) {
+        panic!("{divergence}");
+    }
+}
+
+// Opcode 0001 halts whatever its row says, here after adding like ADD
+#[test]
+fn opcode_0001_always_halts() {
+    use crate::control_rom::{ControlRom, ControlSignals, DestMux};
+
+    let add_and_halt = ControlSignals {
+        reg_file_enable: true,
+        set_flags: true,
+        dest_mux: DestMux::Third,
+        ..Default::default()
+    };
+    let rom = ControlRom::empty()
+        .with_instruction(0b1000, "LDI", ControlRom::batpu2().signals(0b1000))
+        .with_instruction(0b0001, "ADH", add_and_halt);
+    let mut program = crate::assemble("LDI r1 3\nLDI r2 4").unwrap();
+    program.push(crate::Bits::from(0x1123u16));
+    program.push(crate::Bits::from(0x8409u16));
+
+    let mut vm = VM::builder().control_rom(rom).build().unwrap();
+    vm.load_instructions(&program).unwrap();
+    let fast = crate::FastVm::from_vm(&vm);
+    let mut lock_step = LockStep::new(vm, fast);
+    assert_eq!(lock_step.run(10), Ok(crate::lockstep::Outcome::Halted));
+    assert_eq!(lock_step.steps(), 3);
+    assert_eq!(lock_step.a.reg_file.register_banks[0][3].to_usize(), 7);
+    assert_eq!(lock_step.a.reg_file.register_banks[0][4].to_usize(), 0);
+}
+
+#[test]
+fn custom_control_rom() {
+    use crate::control_rom::{AluSettings, ControlRom, ControlSignals};
+
+    // a variant where opcode 0100 computes OR instead of NOR
+    let or = ControlSignals {
+        alu_settings: AluSettings::Or,
+        reg_file_enable: true,
+        set_flags: true,
+        ..Default::default()
+    };
+    let builder =
+        VM::builder().control_rom(ControlRom::batpu2().with_instruction(0b0100, "OR", or));
+    let source = "LDI r1 12\nLDI r2 10\nNOR r1 r2 r3\nHLT";
+    assert_eq!(registers_after(builder.clone(), source)[2], 14);
+    assert_eq!(registers_after(VM::builder(), source)[2], 0b1111_0001);
+
+    let mut vm = builder.build().unwrap();
+    vm.load_source(source).unwrap();
+    let fast = crate::FastVm::from_vm(&vm);
+    if let Err(divergence) = LockStep::new(vm, fast).run(5) {
+        panic!("{divergence}");
+    }
+}
+
+#[test]
+fn moved_io_base() {
+    let mut vm = VM::builder().io_base(128).build().unwrap();
+    vm.add_observer(Recorder::default());
+    // the number display's show port, then a plain memory write at the old port address
+    vm.load_source("LDI r1 138\nLDI r2 42\nSTR r1 r2 0\nLDI r3 240\nSTR r3 r2 0\nHLT")
+        .unwrap();
+    vm.run().unwrap();
+    assert_eq!(u8::from(vm.io_devices.number_display.display), 42);
+    assert_eq!(u8::from(vm.data_memory.memory[240]), 42);
+    let events = vm.remove_observer::<Recorder>().unwrap().events;
+    assert_eq!(
+        events[2].io,
+        Some(Access::Write {
+            address: 138,
+            value: 42
+        })
+    );
+    assert_eq!(events[4].io, None);
+}
+
+#[test]
+fn port_names_follow_the_io_base() {
+    let mut vm = VM::builder()
+        .io_base(128)
+        .memory_banks(2, 64)
+        .build()
+        .unwrap();
+    vm.load_source(
+        "LDI r1 show_number\nLDI r2 42\nSTR r1 r2\nLDI r15 bank_select\nLDI r3 1\nSTR r15 r3\nHLT",
+    )
+    .unwrap();
+    vm.run().unwrap();
+    assert_eq!(u8::from(vm.reg_file.register_banks[0][1]), 138);
+    assert_eq!(u8::from(vm.io_devices.number_display.display), 42);
+    assert_eq!(u8::from(vm.reg_file.register_banks[0][15]), 127);
+    assert_eq!(vm.data_memory.bank(), 1);
+}
+
+#[test]
+fn detached_devices_and_small_screen() {
+    let mut vm = VM::builder()
+        .devices(crate::AttachedDevices {
+            number_display: false,
+            rng: false,
+            ..Default::default()
+        })
+        .screen(8, 4)
+        .build()
+        .unwrap();
+    // draws (2, 1) and (10, 1), which is off the screen, then reads the RNG
+    vm.load_source(
+        "LDI r15 240\nLDI r1 2\nLDI r2 1\nLDI r3 10\nSTR r15 r1 0\nSTR r15 r2 1\nSTR r15 r0 2\n\
+         STR r15 r3 0\nSTR r15 r0 2\nSTR r15 r0 5\nLDI r14 250\nSTR r14 r1 0\nLOD r14 r4 4\nHLT",
+    )
+    .unwrap();
+    vm.run().unwrap();
+    let screen = &vm.io_devices.screen;
+    assert!(screen.active[1][2]);
+    assert!(!screen.active[1][10]);
+    assert_eq!(
+        screen.render(),
+        "+--------+\n|        |\n|        |\n|  █     |\n|        |\n+--------+\n"
+    );
+    assert_eq!(u8::from(vm.io_devices.number_display.display), 0);
+    assert_eq!(u8::from(vm.reg_file.register_banks[0][4]), 0);
+}
+
+// a test harness port: the program stores its exit code
+#[derive(Debug, Clone, Default)]
+struct ExitCode(Option<u8>);
+
+impl crate::Device for ExitCode {
+    fn on_read(&mut self, _addr: crate::Bits<8>) -> crate::Bits<8> {
+        crate::Bits::default()
+    }
+
+    fn on_write(&mut self, _addr: crate::Bits<8>, value: crate::Bits<8>) {
+        self.0 = Some(value.into());
+    }
+
+    fn can_load(&self, _port: u8) -> bool {
+        false
+    }
+}
+
+// counts executed instructions
+#[derive(Debug, Clone, Default)]
+struct Timer(u8);
+
+impl crate::Device for Timer {
+    fn on_read(&mut self, _addr: crate::Bits<8>) -> crate::Bits<8> {
+        crate::Bits::from(self.0)
+    }
+
+    fn on_write(&mut self, _addr: crate::Bits<8>, _value: crate::Bits<8>) {
+        self.0 = 0;
+    }
+
+    fn tick(&mut self) {
+        self.0 = self.0.wrapping_add(1);
+    }
+}
+
+#[test]
+fn user_devices_shadow_built_in_ports() {
+    let mut vm = VM::builder()
+        .fault_policy(crate::FaultPolicy::Trap)
+        .device(250..=250, ExitCode::default())
+        .device(247..=247, Timer::default())
+        .build()
+        .unwrap();
+    vm.load_source(
+        "LDI r15 240\nNOP\nLOD r15 r1 7\nLDI r2 3\nLDI r14 250\nSTR r14 r2 0\nSTR r14 r0 1\nHLT",
+    )
+    .unwrap();
+    vm.run().unwrap();
+    assert_eq!(u8::from(vm.reg_file.register_banks[0][1]), 2);
+    assert_eq!(vm.io_devices.device::<ExitCode>().unwrap().0, Some(3));
+    assert_eq!(vm.io_devices.device::<Timer>().unwrap().0, 8);
+    // the rest of the number display is still there
+    assert_eq!(u8::from(vm.io_devices.number_display.display), 0);
+    assert!(!vm.io_devices.number_display.active);
+
+    vm.io_devices.device_mut::<Timer>().unwrap().0 = 0;
+    assert_eq!(vm.io_devices.device::<Timer>().unwrap().0, 0);
+}
+
+#[test]
+fn user_device_faults_and_history() {
+    let mut vm = VM::builder()
+        .fault_policy(crate::FaultPolicy::Trap)
+        .device(248..=248, ExitCode::default())
+        .build()
+        .unwrap();
+    vm.enable_history(8);
+    vm.load_source("LDI r15 248\nLDI r1 9\nSTR r15 r1 0\nLOD r15 r2 0\nHLT")
+        .unwrap();
+    for _ in 0..3 {
+        vm.step().unwrap();
+    }
+    assert_eq!(vm.io_devices.device::<ExitCode>().unwrap().0, Some(9));
+    assert_eq!(
+        vm.step(),
+        Err(crate::error::VmError::Runtime(crate::Fault {
+            pc: 3,
+            error: crate::RuntimeError::ReadFromWriteOnlyPort(248)
+        }))
+    );
+    let mut restored = VM::builder()
+        .device(248..=248, ExitCode::default())
+        .build()
+        .unwrap();
+    restored.restore_state(&vm.state_bytes()).unwrap();
+    assert_eq!(restored.io_devices.device::<ExitCode>().unwrap().0, None);
+    assert!(vm.step_back());
+    assert_eq!(vm.io_devices.device::<ExitCode>().unwrap().0, None);
+}
+
+#[test]
+fn unmapped_ports() {
+    let mut vm = VM::builder()
+        .fault_policy(crate::FaultPolicy::Trap)
+        .devices(crate::AttachedDevices::none())
+        .build()
+        .unwrap();
+    vm.load_source("LDI r15 250\nLDI r1 7\nSTR r15 r1 0\nLOD r15 r1 0\nHLT")
+        .unwrap();
+    vm.run().unwrap();
+    assert_eq!(u8::from(vm.reg_file.register_banks[0][1]), 0);
+    assert_eq!(u8::from(vm.io_devices.number_display.display), 0);
+    assert_eq!(
+        VM::builder()
+            .device(10..=20, Timer::default())
+            .build()
+            .unwrap_err(),
+        crate::error::VmError::Config("device ports 10..=20 are not within 240..=255".to_string())
+    );
+}
+
+const BANKED: &str = "\
+LDI r15 bank_select
+LDI r1 5
+STR r0 r1 3
+LDI r2 1
+STR r15 r2
+LOD r0 r3 3
+STR r0 r2 3
+LDI r4 100
+STR r4 r1 0
+LOD r15 r5
+STR r15 r0
+LOD r0 r6 3
+LOD r4 r7 0
+HLT";
+
+#[test]
+fn memory_banks_swap_the_lower_window() {
+    let mut vm = VM::builder().memory_banks(4, 16).build().unwrap();
+    assert_eq!(vm.bank_select_address(), Some(239));
+    vm.enable_history(32);
+    vm.load_source(BANKED).unwrap();
+    vm.run().unwrap();
+    let registers: Vec<u8> = vm.reg_file.register_banks[0][3..8]
+        .iter()
+        .map(|&value| value.into())
+        .collect();
+    // bank 1 starts empty, address 100 is shared by every bank
+    assert_eq!(registers, vec![0, 100, 1, 5, 5]);
+    assert_eq!(vm.data_memory.bank(), 0);
+    assert_eq!(u8::from(vm.data_memory.bank_contents(1)[3]), 1);
+    assert_eq!(u8::from(vm.data_memory.memory[239]), 0);
+
+    while vm.step_back() {}
+    assert_eq!(vm.data_memory.bank(), 0);
+    assert!(vm
+        .data_memory
+        .memory
+        .iter()
+        .all(|&value| u8::from(value) == 0));
+    assert!(vm
+        .data_memory
+        .bank_contents(1)
+        .iter()
+        .all(|&value| u8::from(value) == 0));
+}
+
+#[test]
+fn memory_banks_in_state_files() {
+    let build = || VM::builder().memory_banks(4, 16).build().unwrap();
+    let mut vm = build();
+    vm.load_source(BANKED).unwrap();
+    for _ in 0..7 {
+        vm.step().unwrap();
+    }
+    let mut restored = build();
+    restored.restore_state(&vm.state_bytes()).unwrap();
+    assert_eq!(restored.data_memory.bank(), 1);
+    assert_eq!(restored.data_memory.memory, vm.data_memory.memory);
+    for bank in 0..4 {
+        assert_eq!(
+            restored.data_memory.bank_contents(bank),
+            vm.data_memory.bank_contents(bank)
+        );
+    }
+    assert_eq!(
+        VM::new().restore_state(&vm.state_bytes()),
+        Err(crate::error::VmError::State(
+            "the file has 4 memory banks of 16 bytes, the machine 1 of 0".to_string()
+        ))
+    );
+}
+
+#[test]
+fn banking_is_opt_in() {
+    let mut vm = VM::new();
+    assert_eq!(vm.bank_select_address(), None);
+    vm.load_source("LDI r15 bank_select\nLDI r1 3\nSTR r15 r1\nHLT")
+        .unwrap();
+    vm.run().unwrap();
+    assert_eq!(u8::from(vm.data_memory.memory[239]), 3);
+    assert_eq!(
+        VM::builder().memory_banks(2, 240).build().unwrap_err(),
+        crate::error::VmError::Config(
+            "bank window must be between 1 and 239, found 240".to_string()
+        )
+    );
+}
+
+#[test]
+fn fast_core_matches_the_gates_on_programs() {
+    for program in [
+        "programs/dvd.as",
+        "programs/gol.as",
+        "tests/test_programs/test_bubble_sort.as",
+        "tests/test_programs/hello.as",
+        "tests/test_programs/fib.as",
+    ] {
+        let mut vm = VM::builder().rng_seed(7).build().unwrap();
+        vm.load_program(program).unwrap();
+        vm.io_devices.controller.set_right(true);
+        let fast = crate::FastVm::from_vm(&vm);
+        if let Err(divergence) = LockStep::new(vm, fast).run(3000) {
+            panic!("{program}: {divergence}");
+        }
+    }
+}
+
+#[test]
+fn fast_core_matches_the_gates_on_random_words() {
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(21);
+    for round in 0..200 {
+        let builder = VM::builder()
+            .rng_seed(round)
+            .fault_policy(if round % 2 == 0 {
+                crate::FaultPolicy::Trap
+            } else {
+                crate::FaultPolicy::Emulate
+            })
+            .stack_depth(round as usize % 4 + 1)
+            .extended_alu(round % 3 == 0);
+        // every other machine is cut down, with banks and its IO ports somewhere else
+        let builder = if round % 4 < 2 {
+            builder
+        } else {
+            builder
+                .data_memory(200)
+                .registers(12)
+                .io_base(128)
+                .memory_banks(3, 32)
+        };
+        let mut vm = builder.build().unwrap();
+        let words: Vec<u16> = (0..48).map(|_| rng.random()).collect();
+        let program: Vec<_> = words.into_iter().map(crate::Bits::from).collect();
+        vm.load_instructions(&program).unwrap();
+        let fast = crate::FastVm::from_vm(&vm);
+        if let Err(divergence) = LockStep::new(vm, fast).run(200) {
+            panic!("round {round}: {divergence}");
+        }
+    }
+}
+
+// Random decode tables with random programs, so that the fast core follows any
+// combination of control signals the way the gates do
+#[test]
+fn fast_core_matches_the_gates_with_random_control_roms() {
+    use crate::control_rom::*;
+    use rand::{Rng, SeedableRng};
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(25);
+    for round in 0..100 {
+        let mut rom = ControlRom::empty();
+        for opcode in 0..OPCODES as u8 {
+            let mut pick = |count: usize| rng.random_range(0..count);
+            let signals = ControlSignals {
+                alu_settings: [
+                    AluSettings::Add,
+                    AluSettings::Sub,
+                    AluSettings::Xor,
+                    AluSettings::Xnor,
+                    AluSettings::Or,
+                    AluSettings::Nor,
+                    AluSettings::And,
+                    AluSettings::Nand,
+                    AluSettings::Implies,
+                    AluSettings::Nimplies,
+                    AluSettings::Rshift,
+                ][pick(11)],
+                reg_file_enable: pick(2) == 0,
+                data_mux: [DataMux::Alu, DataMux::Immediate, DataMux::Memory][pick(3)],
+                dest_mux: [DestMux::First, DestMux::Second, DestMux::Third][pick(3)],
+                alu_mux: [AluMux::R2, AluMux::BypassRegisterFile][pick(2)],
+                addr_mux: [AddrMux::Increment, AddrMux::Jump, AddrMux::Return][pick(3)],
+                is_branch: pick(2) == 0,
+                set_flags: pick(2) == 0,
+                call_stack_state: [
+                    CallStackState::Disabled,
+                    CallStackState::Push,
+                    CallStackState::Pop,
+                ][pick(3)],
+                immediate_mux: [ImmediateMux::Immediate, ImmediateMux::Offset][pick(2)],
+                memory_access: [
+                    MemoryAccess::Disabled,
+                    MemoryAccess::Read,
+                    MemoryAccess::Write,
+                ][pick(3)],
+                read_mux: [ReadMux::Second, ReadMux::Third][pick(2)],
+            };
+            rom = rom.with_instruction(opcode, "", signals);
+        }
+        let builder = VM::builder()
+            .rng_seed(round)
+            .control_rom(rom)
+            .extended_alu(round % 2 == 0)
+            .stack_depth(round as usize % 3 + 1)
+            .fault_policy(if round % 3 == 0 {
+                crate::FaultPolicy::Trap
+            } else {
+                crate::FaultPolicy::Emulate
+            });
+        let builder = if round % 4 < 2 {
+            builder
+        } else {
+            builder.data_memory(200).registers(12).memory_banks(3, 32)
+        };
+        let mut vm = builder.build().unwrap();
+        let words: Vec<u16> = (0..48).map(|_| rng.random()).collect();
+        let program: Vec<_> = words.into_iter().map(crate::Bits::from).collect();
+        vm.load_instructions(&program).unwrap();
+        let fast = crate::FastVm::from_vm(&vm);
+        if let Err(divergence) = LockStep::new(vm, fast).run(200) {
+            panic!("round {round}: {divergence}");
+        }
+    }
+}
+
+// Every 16-bit word, in a random order, straight through the datapath on machines of
+// every shape. Whatever the word does, the VM must not panic or break its invariants.
+#[test]
+fn process_instruction_survives_any_word() {
+    use rand::seq::SliceRandom;
+    use rand::SeedableRng;
+
+    let mut rng = rand::rngs::StdRng::seed_from_u64(23);
+    let builders = [
+        VM::builder().rng_seed(1),
+        VM::builder()
+            .rng_seed(2)
+            .fault_policy(crate::FaultPolicy::Trap)
+            .stack_depth(1),
+        VM::builder()
+            .rng_seed(3)
+            .data_memory(100)
+            .registers(5)
+            .io_base(64)
+            .memory_banks(4, 16)
+            .stack_depth(2),
+        VM::builder()
+            .rng_seed(4)
+            .instruction_memory(8)
+            .extended_alu(true)
+            .screen(3, 5)
+            .devices(crate::AttachedDevices::none()),
+    ];
+    for builder in builders {
+        let stack_depth = builder.config().stack_depth;
+        let mut vm = builder.build().unwrap();
+        let mut words: Vec<u16> = (0..=u16::MAX).collect();
+        words.shuffle(&mut rng);
+        for word in words {
+            let _ = vm.process_instruction(crate::Bits::from(word));
+            vm.clock_registers();
+            vm.io_devices.tick();
+            assert_eq!(u8::from(vm.reg_file.register_banks[0][0]), 0, "{word:#06x}");
+            assert!(vm.call_stack().len() <= stack_depth, "{word:#06x}");
+            assert!(vm.data_memory.bank() < vm.data_memory.bank_count());
+        }
+    }
+}
+
+#[test]
+fn fast_core_round_trips_through_the_gates() {
+    let builder = VM::builder().rng_seed(3).memory_banks(4, 16);
+    let mut vm = builder.clone().build().unwrap();
+    vm.load_source(BANKED).unwrap();
+    vm.enable_history(8);
+    for _ in 0..7 {
+        vm.step().unwrap();
+    }
+    let mut fast = builder.build_fast().unwrap();
+    fast.load_source(BANKED).unwrap();
+    fast.run().unwrap();
+    vm.run().unwrap();
+    assert!(fast.to_vm().state_bytes() == vm.state_bytes());
+    assert_eq!(fast.bank(), 0);
+    assert_eq!(fast.register(7), 5);
+    assert!(crate::FastVm::from_vm(&vm).to_vm().state_bytes() == vm.state_bytes());
+}
+
+#[test]
+fn fast_core_clamps_instruction_memory() {
+    let config = crate::VmConfig {
+        instruction_memory_size: 4096,
+        ..Default::default()
+    };
+    let program = vec![crate::Bits::from(0u16); 1025];
+    let mut fast = crate::FastVm::with_config(config.clone());
+    assert_eq!(
+        fast.load_instructions(&program),
+        Err(crate::error::VmError::InstructionMemoryOverflow)
+    );
+    assert_eq!(
+        VM::with_config(config).load_instructions(&program),
+        Err(crate::error::VmError::InstructionMemoryOverflow)
+    );
+    assert_eq!(fast.load_instructions(&program[..1024]), Ok(()));
+}
@@ -0,0 +1,106 @@
+// Runs the `rust_vm run` and `test` subcommands as a separate process, the way scripts and
+// CI use them
+use std::process::Command;
+
+struct Run {
+    code: Option<i32>,
+    stdout: String,
+    stderr: String,
+}
+
+fn run(name: &str, source: &str, args: &[&str]) -> Run {
+    rust_vm("run", name, source, args)
+}
+
+fn rust_vm(subcommand: &str, name: &str, source: &str, args: &[&str]) -> Run {
+    let path = std::env::temp_dir().join(format!("rust_vm_cli_{}_{name}.as", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_rust_vm"))
+        .arg(subcommand)
+        .arg(&path)
+        .args(args)
+        .output()
+        .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    Run {
+        code: output.status.code(),
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+    }
+}
+
+#[test]
+fn halting_exits_with_0() {
+    let run = run("halt", "LDI r1 5\nHLT", &["--dump-registers"]);
+    assert_eq!(run.code, Some(0));
+    assert_eq!(run.stderr, "halted after 2 cycles\n");
+    let registers: Vec<&str> = run.stdout.lines().collect();
+    assert_eq!(registers.len(), 16);
+    assert_eq!(registers[..3], ["r0 = 0", "r1 = 5", "r2 = 0"]);
+}
+
+#[test]
+fn cycle_limit_exits_with_2() {
+    let run = run("limit", ".loop\nJMP .loop", &["--max-cycles", "0x10"]);
+    assert_eq!(run.code, Some(2));
+    assert_eq!(run.stderr, "stopped at the cycle limit of 16 cycles\n");
+    assert_eq!(run.stdout, "");
+}
+
+#[test]
+fn errors_exit_with_1() {
+    let fault = run("fault", "RET", &["--trap"]);
+    assert_eq!(fault.code, Some(1));
+    assert_eq!(
+        fault.stderr,
+        "Runtime error: call stack underflow at pc 0\n"
+    );
+
+    let usage = run("usage", "HLT", &["--nope"]);
+    assert_eq!(usage.code, Some(1));
+    assert!(usage.stderr.starts_with("unknown option '--nope'"));
+
+    let number = run("number", "HLT", &["--max-cycles", "ten"]);
+    assert_eq!(number.code, Some(1));
+    assert_eq!(number.stderr, "invalid number 'ten'\n");
+}
+
+#[test]
+fn presets_and_dumps() {
+    let source = "ADD r1 r1 r2\nLDI r3 32\nSTR r3 r2\nHLT";
+    let args = [
+        "--reg",
+        "r1=3",
+        "--mem",
+        "0x10=7",
+        "--dump-registers",
+        "--dump-memory",
+    ];
+    let run_gates = run("dumps", source, &args);
+    assert_eq!(run_gates.code, Some(0));
+    let lines: Vec<&str> = run_gates.stdout.lines().collect();
+    assert_eq!(lines[1..4], ["r1 = 3", "r2 = 6", "r3 = 32"]);
+    let memory = &lines[16..];
+    assert_eq!(memory.len(), 16);
+    assert!(memory[0].starts_with("  0:   0   0"));
+    assert!(memory[1].starts_with(" 16:   7   0"));
+    assert!(memory[2].starts_with(" 32:   6   0"));
+
+    let run_fast = run("dumps_fast", source, &[&args[..], &["--fast"]].concat());
+    assert_eq!(run_fast.code, Some(0));
+    assert_eq!(run_fast.stdout, run_gates.stdout);
+}
+
+#[test]
+fn extended_alu_programs_need_the_flag() {
+    let source = "// @expect r2 == 14\nLDI r1 12\nLDI r2 10\nOR r1 r2\nHLT";
+    let extended = rust_vm("test", "test_extended", source, &["--extended-alu"]);
+    assert_eq!(extended.code, Some(0));
+    assert!(extended.stdout.ends_with("1 passed, 0 failed\n"));
+
+    let plain = rust_vm("test", "test_plain", source, &[]);
+    assert_eq!(plain.code, Some(1));
+    assert!(plain
+        .stdout
+        .contains("OR needs the extended ALU, a BatPU-2 would run it as RSH"));
+}
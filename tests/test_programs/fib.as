@@ -1,3 +1,9 @@
+// @expect r1 == 1
+// @expect r2 == 1
+// @expect r3 == 2
+// @expect r4 == 3
+// @expect r5 == 5
+// @expect halted
 LDI r1 1
 LDI r2 1
 ADD r1 r2 r3
@@ -1,3 +1,5 @@
+// @expect r3 == 13
+// @expect halted
 .fib 
     LDI r1 8 
     LDI r2 0
@@ -1,3 +1,4 @@
+// @expect halted
 // define boundary 224 // 11100000 32x32
 define boundary 248 // 11111000 8x8
 // define boundary 252 // 11111100 4x4
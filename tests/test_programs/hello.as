@@ -1,3 +1,5 @@
+// @expect chars == "hello!"
+// @expect halted
 LDI r15 247
 LDI r1 "h"
 STR r15 r1
@@ -1,3 +1,4 @@
+// @expect halted
 // Simple Hello World Program by mattbatwings
 
 // Clear character buffer
@@ -1,3 +1,4 @@
+// @expect halted
 // Bouncing DVD by zPippo
 
 ldi r15 clear_chars_buffer
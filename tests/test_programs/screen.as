@@ -1,3 +1,5 @@
+// @expect screen == screen.screen
+// @expect halted
 LDI r15 240
 LDI r1 2
 LDI r2 3
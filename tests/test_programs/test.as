@@ -1,3 +1,10 @@
+// @set r1 = 7
+// @set r2 = 8
+// @set r3 = 6
+// @expect r1 == 7
+// @expect r2 == 7
+// @expect r3 == 9
+// @expect halted
 ADD r1 r1 r2
 XOR r1 r2 r3
 RSH r2    r2
@@ -1,3 +1,10 @@
+// @set r1 = 1
+// @set r2 = 1
+// @expect r1 == 3
+// @expect r2 == 1
+// @expect r3 == 2
+// @expect r4 == 3
+// @expect halted
 ADD r1 r2 r3
 ADD r2 r3 r4
 NOP
@@ -1,3 +1,6 @@
+// @expect r1 == 4
+// @expect r2 == 8
+// @expect halted
 LDI r1 4
 ADD r1 r1 r2
 HLT
\ No newline at end of file
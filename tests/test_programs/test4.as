@@ -1,2 +1,4 @@
+// @expect r1 == 3
+// @expect halted
 ADI r1 3
 HLT
\ No newline at end of file
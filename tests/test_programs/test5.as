@@ -1,3 +1,5 @@
+// @expect r1 == 3
+// @expect halted
 INC r1
 INC r1
 INC r1
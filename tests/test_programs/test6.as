@@ -1,3 +1,5 @@
+// @expect r1 == 1
+// @expect halted
 LDI r1 0
 JMP 3
 ADD r1 r1 r1
@@ -1,3 +1,4 @@
+// @expect halted
 LDI r1 3
 DEC r1
 BRH != 4
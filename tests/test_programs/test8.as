@@ -1,3 +1,4 @@
+// @expect halted
 LDI r1 2
 DEC r1
 CMP r1 r0
@@ -1,3 +1,5 @@
+// @expect r1 == 3
+// @expect halted
     CAL .add3
     HLT
 .add3 
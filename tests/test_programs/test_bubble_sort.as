@@ -1,3 +1,24 @@
+// @set mem[0] = 6
+// @set mem[1] = 2
+// @set mem[2] = 5
+// @set mem[3] = 8
+// @set mem[4] = 9
+// @set mem[5] = 1
+// @set mem[6] = 3
+// @set mem[7] = 4
+// @set mem[8] = 7
+// @set mem[9] = 0
+// @expect mem[0] == 0
+// @expect mem[1] == 1
+// @expect mem[2] == 2
+// @expect mem[3] == 3
+// @expect mem[4] == 4
+// @expect mem[5] == 5
+// @expect mem[6] == 6
+// @expect mem[7] == 7
+// @expect mem[8] == 8
+// @expect mem[9] == 9
+// @expect halted
     LDI r15 9
 .outer 
     LDI r14 8
@@ -1,149 +1,50 @@
 #![allow(unused_results)]
-use rust_vm::registers::register_file::RegisterBank;
-use rust_vm::registers::RegisterFile;
-use rust_vm::Bits;
 use rust_vm::VM;
 
-#[test]
-fn vm_execute_program() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test.as").unwrap();
-    vm.reg_file.display();
-}
-
-#[test]
-fn vm_execution() {
-    let mut arr = [Bits::from(0u8); 16];
-    arr[1] = Bits::from(7u8);
-    arr[2] = Bits::from(8u8);
-    arr[3] = Bits::from(6u8);
-    let mut vm = VM::new();
-    vm.reg_file = RegisterFile::new(RegisterBank::from(arr));
-    vm.execute_program("tests/test_programs/test.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 7,);
-    assert_eq!(vm.reg_file.register_banks[0][2].to_usize(), 7,);
-    assert_eq!(vm.reg_file.register_banks[0][3].to_usize(), 9,);
-    assert_eq!(vm.reg_file.register_banks[1][1].to_usize(), 7,);
-    assert_eq!(vm.reg_file.register_banks[1][2].to_usize(), 7,);
-    assert_eq!(vm.reg_file.register_banks[1][3].to_usize(), 9,);
+// Every program in test_programs states what it should leave behind in `@expect`
+// comments, see `rust_vm::spec`
+#[test]
+fn program_specs() {
+    let mut entries: Vec<_> = std::fs::read_dir("tests/test_programs")
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "as"))
+        .collect();
+    entries.sort();
+    let mut checked = 0;
+    for path in entries {
+        let Some(report) = rust_vm::spec::run_file(&path).unwrap() else {
+            continue;
+        };
+        assert!(
+            report.passed(),
+            "{}: {}",
+            path.display(),
+            report.failures.join("\n")
+        );
+        checked += 1;
+    }
+    assert_eq!(checked, 17);
 }
 
 #[test]
 fn nop() {
-    std::fs::write("nop.as", "NOP\n").unwrap();
-    std::fs::write("nop.as", "HLT").unwrap();
-    let mut vm = VM::default();
-    vm.execute_program("nop.as").unwrap();
-    std::fs::remove_file("nop.as").unwrap();
-    std::fs::remove_file("nop.mc").unwrap();
-}
-
-#[test]
-fn vm_execute_program_2() {
-    let mut arr = [Bits::from(0u8); 16];
-    arr[1] = Bits::from(1u8);
-    arr[2] = Bits::from(1u8);
-    let mut vm = VM::new();
-    vm.reg_file = RegisterFile::new(RegisterBank::from(arr));
-    vm.execute_program("tests/test_programs/test2.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 3);
-    assert_eq!(vm.reg_file.register_banks[0][2].to_usize(), 1);
-    assert_eq!(vm.reg_file.register_banks[0][3].to_usize(), 2);
-    assert_eq!(vm.reg_file.register_banks[0][4].to_usize(), 3);
-    assert_eq!(vm.reg_file.register_banks[1][1].to_usize(), 3);
-    assert_eq!(vm.reg_file.register_banks[1][2].to_usize(), 1);
-    assert_eq!(vm.reg_file.register_banks[1][3].to_usize(), 2);
-    assert_eq!(vm.reg_file.register_banks[1][4].to_usize(), 3);
-}
-
-#[test]
-fn vm_program_3() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test3.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 4);
-    assert_eq!(vm.reg_file.register_banks[0][2].to_usize(), 8);
-}
-
-#[test]
-fn fib() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/fib.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 1);
-    assert_eq!(vm.reg_file.register_banks[0][2].to_usize(), 1);
-    assert_eq!(vm.reg_file.register_banks[0][3].to_usize(), 2);
-    assert_eq!(vm.reg_file.register_banks[0][4].to_usize(), 3);
-    assert_eq!(vm.reg_file.register_banks[0][5].to_usize(), 5);
-}
-
-#[test]
-fn vm_program_4() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test4.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 3);
-}
-
-#[test]
-fn vm_program_5() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test5.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 3);
-}
-
-#[test]
-fn vm_program_6() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test6.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 1);
-}
-
-#[test]
-fn vm_program_7() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test7.as").unwrap();
-}
-
-#[test]
-fn vm_program_8() {
     let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test8.as").unwrap();
+    vm.load_source("NOP\nHLT").unwrap();
+    assert_ne!(vm.clock(), rust_vm::OPCODE_HLT);
+    assert_eq!(vm.clock(), rust_vm::OPCODE_HLT);
 }
 
 #[test]
-fn vm_fib_2() {
+fn load_instructions_runs_machine_code() {
+    let program = rust_vm::assemble("LDI r1 5\nADI r1 4\nHLT").unwrap();
     let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/fib2.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][3].to_usize(), 13);
-}
-
-#[test]
-fn vm_program_9() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/test9.as").unwrap();
-    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 3);
-}
-
-#[test]
-fn vm_program_bubble_sort() {
-    let mut vm = VM::new();
-    let arr: Vec<u64> = vec![6, 2, 5, 8, 9, 1, 3, 4, 7, 0];
-    let mut bit_arr = [Bits::<8>::from(0u8); 256];
-    for (i, &val) in arr.iter().enumerate() {
-        bit_arr[i] = Bits::from(val).resize();
-    }
-    vm.data_memory.memory[..arr.len()].copy_from_slice(&bit_arr[..arr.len()]);
-    vm.data_memory.memory[0..8]
-        .iter()
-        .for_each(|b| println!("{:?}", b.to_usize()));
-    vm.execute_program("tests/test_programs/test_bubble_sort.as")
-        .unwrap();
-    let res = vm.data_memory.memory[..arr.len()]
-        .iter()
-        .map(|bits| bits.to_usize())
-        .collect::<Vec<_>>();
-    println!("Sorted array: {res:?}");
-    assert!(res.is_sorted());
+    vm.load_instructions(&program).unwrap();
+    while vm.clock() != rust_vm::OPCODE_HLT {}
+    assert_eq!(vm.reg_file.register_banks[0][1].to_usize(), 9);
 }
 
+// These build on each other's display state, so they share one machine
 #[test]
 fn vm_program_number_display() {
     let mut vm = VM::default();
@@ -172,48 +73,93 @@ fn vm_program_number_display() {
     assert_eq!(vm.io_devices.number_display.get_display_val(), "202");
 }
 
-#[test]
-fn vm_program_character_display() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/hello.as").unwrap();
-    assert_eq!(
-        vm.io_devices.character_display.active,
-        "Hello!".to_ascii_lowercase()
-    );
-}
+// Runs a program and compares every buffered frame with tests/golden/<name>_<cycle>.<ext>.
+// Set RUST_VM_UPDATE_GOLDEN to rewrite the files.
+fn check_golden_frames(program: &str, max_cycles: u64, extension: &str) {
+    use rust_vm::snapshot::{self, ScreenCapture};
 
-#[test]
-fn vm_program_screen() {
-    let mut vm = VM::default();
-    vm.execute_program("tests/test_programs/screen.as").unwrap();
-    for row in vm.io_devices.screen.active.iter() {
-        for &pixel in row.iter() {
-            assert!(!pixel, "Expected all pixels to be false");
+    let mut vm = VM::new();
+    vm.load_program(program).unwrap();
+    vm.add_observer(ScreenCapture::new(Vec::new(), true));
+    for _ in 0..max_cycles {
+        vm.step().unwrap();
+    }
+    let frames = vm.remove_observer::<ScreenCapture>().unwrap().frames;
+    assert!(!frames.is_empty(), "{program} never buffered the screen");
+    let name = std::path::Path::new(program).file_stem().unwrap();
+    for frame in frames {
+        let golden = format!(
+            "tests/golden/{}_{}.{extension}",
+            name.display(),
+            frame.cycle
+        );
+        if let Some(diff) = snapshot::check_golden(&golden, &frame.pixels).unwrap() {
+            panic!("{golden}: {diff}");
         }
     }
 }
 
 #[test]
-fn logo() {
-    let mut vm = VM::new();
-    vm.execute_program("tests/test_programs/logo.as").unwrap();
+fn dvd_frames() {
+    check_golden_frames("programs/dvd.as", 3400, "txt");
 }
 
 #[test]
-fn gol_init() {
-    let mut vm = VM::new();
-    vm.execute_program("tests/test_programs/gol_init.as")
-        .unwrap();
-    vm.reg_file.display();
-    println!();
-    vm.data_memory.display();
-    vm.io_devices.screen.display_buffer();
+fn gol_generations() {
+    check_golden_frames("programs/gol.as", 22000, "pbm");
 }
 
 #[test]
-fn hello_char() {
+fn connect4_replay() {
+    use rust_vm::input::InputScript;
+    use rust_vm::snapshot;
+
+    let mut script = InputScript::load("tests/inputs/connect4.txt").unwrap();
     let mut vm = VM::new();
-    vm.execute_program("tests/test_programs/hello_char.as")
-        .unwrap();
-    vm.io_devices.character_display.display();
+    vm.load_program("programs/connect4.as").unwrap();
+    for cycle in 0..45000 {
+        script.advance(cycle, &mut vm.io_devices.controller);
+        vm.step().unwrap();
+    }
+    assert!(script.is_finished());
+    let golden = "tests/golden/connect4_45000.txt";
+    if let Some(diff) = snapshot::check_golden(golden, &vm.io_devices.screen.active).unwrap() {
+        panic!("{golden}: {diff}");
+    }
+}
+
+// The fast core has to agree with the gates on every shipped program, including the
+// parts that only run once buttons are pressed
+#[test]
+fn fast_core_runs_every_program_in_lock_step() {
+    use rust_vm::input::InputScript;
+    use rust_vm::lockstep::LockStep;
+    use rust_vm::FastVm;
+
+    let inputs = InputScript::parse(
+        "\
+at cycle 2000 press right for 500 cycles
+at cycle 4000 press a for 500 cycles
+at cycle 6000 press down+left for 500 cycles
+at cycle 8000 press start+b for 500 cycles",
+    )
+    .unwrap();
+    let mut programs: Vec<_> = ["programs", "tests/test_programs"]
+        .iter()
+        .flat_map(|directory| std::fs::read_dir(directory).unwrap())
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "as"))
+        .collect();
+    programs.sort();
+    for program in programs {
+        let mut vm = VM::builder().rng_seed(22).build().unwrap();
+        vm.load_program(program.to_str().unwrap()).unwrap();
+        let fast = FastVm::from_vm(&vm);
+        if let Err(divergence) = LockStep::new(vm, fast)
+            .with_inputs(inputs.clone())
+            .run(10_000)
+        {
+            panic!("{}: {divergence}", program.display());
+        }
+    }
 }